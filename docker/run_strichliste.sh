@@ -10,4 +10,5 @@ export DB_FILE=/var/lib/strichliste-rs/strichliste.sqlite
 
 chown strichliste /var/lib/strichliste-rs/
 
-su -c strichliste-rs strichliste
+# exec, so the SIGTERM of docker stop reaches strichliste-rs for a graceful shutdown
+exec su -c "exec strichliste-rs" strichliste
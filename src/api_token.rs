@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use log::warn;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use warp::{http::Method, path::FullPath, Filter, Rejection};
+
+use crate::{
+    api_token_db,
+    common::same_secret,
+    error::{ClientError, DbError},
+    model, openapi,
+    settings::ApiTokenSetting,
+};
+
+/// Scopes of API tokens, each one includes the ones before
+pub const SCOPES: &[&str] = &["read", "book", "admin"];
+
+/// Self-service writes of the kiosk, `POST /api/user/{userId}/<action>`
+const KIOSK_ACTIONS: &[&str] = &["transaction", "cart", "pin", "token", "avatar", "scheduled"];
+
+/// Method and path of a documented operation, like `/api/user/{userId}/pin`
+type Route = (Method, String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Scope {
+    /// GET requests outside of `/api/admin`
+    Read,
+    /// reads, bookings and the other writes of the stock frontend: users, articles and the self-service of the kiosk
+    Book,
+    /// everything
+    Admin,
+}
+
+impl Scope {
+    fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "read" => Some(Scope::Read),
+            "book" => Some(Scope::Book),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    /// The scope the route of the request needs, None if there is no such route.
+    /// The strictest one applies if the path could be several routes.
+    fn required(routes: &[Route], method: &Method, path: &str) -> Option<Scope> {
+        routes
+            .iter()
+            .filter(|(m, route)| m == method && matches_route(route, path))
+            .map(|(_, route)| Scope::of_route(method, route))
+            .max()
+    }
+
+    fn of_route(method: &Method, route: &str) -> Scope {
+        let segments: Vec<&str> = route.trim_start_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["api", "admin", ..] => Scope::Admin,
+            _ if *method == Method::GET => Scope::Read,
+            ["api", "user"] | ["api", "user", _] | ["api", "article"] | ["api", "article", _] => {
+                Scope::Book
+            }
+            ["api", "user", _, action] if KIOSK_ACTIONS.contains(action) => Scope::Book,
+            ["api", "user", _, "token", _] | ["api", "user", _, "scheduled", _] => Scope::Book,
+            _ => Scope::Admin,
+        }
+    }
+}
+
+/// The documented operations, main.rs declares no others
+fn routes() -> Vec<Route> {
+    let spec = openapi::spec();
+    let mut routes = vec![];
+    for (path, item) in spec["paths"].as_object().into_iter().flatten() {
+        for method in item.as_object().into_iter().flatten().map(|(k, _)| k) {
+            if let Ok(method) = Method::from_bytes(method.to_uppercase().as_bytes()) {
+                routes.push((method, path.clone()));
+            }
+        }
+    }
+    return routes;
+}
+
+/// Whether `path` may be the documented `route`. Errs on the side of matching, e.g. for percent-encoded
+/// or empty segments, as a request matching no route is not checked at all.
+fn matches_route(route: &str, path: &str) -> bool {
+    let route: Vec<&str> = route.split('/').filter(|v| !v.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|v| !v.is_empty()).collect();
+    route.len() == path.len()
+        && route
+            .iter()
+            .zip(path.iter())
+            .all(|(r, p)| r.starts_with('{') || r == p || p.contains('%'))
+}
+
+/// Rejects requests beyond the scope of their API token, or of `anonymous` if they have none.
+/// The admin token of the settings counts as a token with the admin scope, `/api/admin` always needs one.
+/// An `Authorization` header of another scheme, e.g. Basic auth of a proxy in front, counts as none.
+/// Requests matching no route are passed on unchecked, so they are answered with `404`.
+pub fn authorize(
+    db: SqlitePool,
+    settings: ApiTokenSetting,
+    admin_token: String,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let anonymous = Scope::parse(&settings.anonymous);
+    let admin_token = Arc::new(admin_token);
+    let routes = Arc::new(routes());
+
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("x-admin-token"))
+        .and_then(
+            move |method: Method,
+                  path: FullPath,
+                  authorization: Option<String>,
+                  given_admin_token: Option<String>| {
+                let db = db.clone();
+                let admin_token = admin_token.clone();
+                let routes = routes.clone();
+                async move {
+                    let required = match Scope::required(&routes, &method, path.as_str()) {
+                        Some(v) => v,
+                        None => return Ok(()),
+                    };
+                    let anonymous = match is_admin_api(path.as_str()) {
+                        true => None,
+                        false => anonymous,
+                    };
+                    let scope = match (given_admin_token, authorization.as_deref().and_then(bearer))
+                    {
+                        (Some(v), _)
+                            if !admin_token.is_empty() && same_secret(&v, &admin_token) =>
+                        {
+                            Scope::Admin
+                        }
+                        (Some(_), _) => {
+                            return Err(Rejection::from(ClientError::Unauthorized(
+                                "Wrong admin token.".to_string(),
+                            )))
+                        }
+                        (None, Some(v)) => authenticate(&db, v).await?,
+                        (None, None) if anonymous.map_or(false, |v| v >= required) => return Ok(()),
+                        (None, None) => {
+                            return Err(Rejection::from(ClientError::Unauthorized(
+                                "API token required.".to_string(),
+                            )))
+                        }
+                    };
+
+                    match scope >= required {
+                        true => Ok(()),
+                        false => {
+                            warn!(
+                                "API token with scope {:?} denied {} {}",
+                                scope,
+                                method,
+                                path.as_str()
+                            );
+                            Err(Rejection::from(ClientError::Forbidden(format!(
+                                "API token lacks the scope {:?}.",
+                                required
+                            ))))
+                        }
+                    }
+                }
+            },
+        )
+        .untuple_one()
+}
+
+fn is_admin_api(path: &str) -> bool {
+    let mut segments = path.trim_start_matches('/').split('/');
+    segments.next() == Some("api") && segments.next() == Some("admin")
+}
+
+/// The token of a `Bearer` authorization, the scheme is case-insensitive
+fn bearer(authorization: &str) -> Option<&str> {
+    let authorization = authorization.trim();
+    let (scheme, token) = authorization.split_at(authorization.find(' ')?);
+    match scheme.eq_ignore_ascii_case("bearer") {
+        true => Some(token.trim()),
+        false => None,
+    }
+}
+
+async fn authenticate(db: &SqlitePool, secret: &str) -> Result<Scope, Rejection> {
+    let token = api_token_db::use_api_token(db, &hash_token(secret))
+        .await?
+        .ok_or_else(|| {
+            ClientError::Unauthorized("API token is unknown, revoked or expired.".to_string())
+        })?;
+
+    // the scope is validated on creation
+    return Ok(Scope::parse(&token.scope).unwrap_or(Scope::Read));
+}
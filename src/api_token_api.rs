@@ -0,0 +1,62 @@
+use sqlx::SqlitePool;
+
+use crate::{
+    api_token, api_token_db, common,
+    error::ClientError,
+    model::{self, json_reply, JsonReply},
+};
+
+pub async fn get_api_tokens(
+    db: SqlitePool,
+) -> Result<JsonReply<model::ApiTokensResp>, warp::Rejection> {
+    let tokens = api_token_db::get_api_tokens(&db).await?;
+
+    Ok(json_reply(model::ApiTokensResp {
+        count: tokens.len(),
+        tokens,
+    }))
+}
+
+/// Creates a token, its secret is only part of this response.
+pub async fn add_api_token(
+    db: SqlitePool,
+    req: model::ApiTokenAddReq,
+) -> Result<JsonReply<model::ApiTokenResp>, warp::Rejection> {
+    let name = common::sanitize_control_chars(req.name.trim());
+    if name.is_empty() {
+        return Err(ClientError::ParameterMissing("name".to_string()).into());
+    }
+    if !api_token::SCOPES.contains(&req.scope.as_str()) {
+        return Err(ClientError::ParameterInvalid(format!(
+            "Scope '{}' is none of {}.",
+            req.scope,
+            api_token::SCOPES.join(", ")
+        ))
+        .into());
+    }
+    let expires = req
+        .expires
+        .as_deref()
+        .map(|v| common::parse_future_datetime(v, "Expiry"))
+        .transpose()?;
+
+    let (token, secret) =
+        api_token::create_token(&db, &name, &req.scope, expires.as_deref()).await?;
+
+    Ok(json_reply(model::ApiTokenResp {
+        token,
+        secret: Some(secret),
+    }))
+}
+
+pub async fn revoke_api_token(
+    db: SqlitePool,
+    token_id: i32,
+) -> Result<JsonReply<model::ApiTokenResp>, warp::Rejection> {
+    let token = api_token_db::revoke_api_token(&db, token_id).await?;
+
+    Ok(json_reply(model::ApiTokenResp {
+        token,
+        secret: None,
+    }))
+}
@@ -0,0 +1,106 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::{error::DbError, model};
+
+/// New random secret for a token
+pub async fn new_secret_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+) -> std::result::Result<String, DbError> {
+    let secret: String = sqlx::query_scalar("SELECT 'sl_' || lower(hex(randomblob(24)))")
+        .fetch_one(tx)
+        .await?;
+
+    return Ok(secret);
+}
+
+pub async fn add_api_token_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    name: &str,
+    hash: &str,
+    scope: &str,
+    expires: Option<&str>,
+) -> std::result::Result<model::ApiTokenEntity, DbError> {
+    let token = sqlx::query_as::<_, model::ApiTokenEntity>(
+        "INSERT INTO api_token (name, hash, scope, expires, revoked, created)
+        VALUES (?, ?, ?, ?, FALSE, datetime('now', 'localtime'));
+
+        SELECT id, name, scope, expires, last_used, revoked, created
+        FROM api_token WHERE id = last_insert_rowid();",
+    )
+    .bind(name)
+    .bind(hash)
+    .bind(scope)
+    .bind(expires)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(token);
+}
+
+pub async fn get_api_tokens(
+    db: &SqlitePool,
+) -> std::result::Result<Vec<model::ApiTokenEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let tokens = sqlx::query_as::<_, model::ApiTokenEntity>(
+        "SELECT id, name, scope, expires, last_used, revoked, created
+        FROM api_token
+        ORDER BY revoked, name, id",
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(tokens);
+}
+
+pub async fn revoke_api_token(
+    db: &SqlitePool,
+    token_id: i32,
+) -> std::result::Result<model::ApiTokenEntity, DbError> {
+    let mut tx = db.begin().await?;
+    let token = sqlx::query_as::<_, model::ApiTokenEntity>(
+        "UPDATE api_token SET revoked = TRUE
+        WHERE id = ? AND revoked IS FALSE;
+
+        SELECT id, name, scope, expires, last_used, revoked, created
+        FROM api_token WHERE id = ? AND changes() > 0;",
+    )
+    .bind(token_id)
+    .bind(token_id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| DbError::EntityNotFound("Active API token".to_string()))?;
+    tx.commit().await?;
+
+    return Ok(token);
+}
+
+/// Returns the valid token with the hash and records its use.
+pub async fn use_api_token(
+    db: &SqlitePool,
+    hash: &str,
+) -> std::result::Result<Option<model::ApiTokenEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let token = sqlx::query_as::<_, model::ApiTokenEntity>(
+        "SELECT id, name, scope, expires, last_used, revoked, created
+        FROM api_token
+        WHERE hash = ? AND revoked IS FALSE
+            AND (expires IS NULL OR expires > datetime('now', 'localtime'))",
+    )
+    .bind(hash)
+    .fetch_optional(&mut tx)
+    .await?;
+    if let Some(token) = &token {
+        // at most once a minute, so busy clients don't write on every request
+        sqlx::query(
+            "UPDATE api_token SET last_used = datetime('now', 'localtime')
+            WHERE id = ? AND (last_used IS NULL OR last_used < datetime('now', 'localtime', '-1 minute'))",
+        )
+        .bind(token.id)
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    return Ok(token);
+}
@@ -3,10 +3,16 @@ use std::collections::HashMap;
 use sqlx::SqlitePool;
 
 use crate::{
-    article_db,
+    article_db, common,
+    error::{ClientError, DbError},
+    events::{Event, EventBus},
     model::{self, json_reply, JsonReply},
+    price_change_db,
 };
 
+/// Longest category name, as in the database
+const MAX_CATEGORY_LEN: usize = 64;
+
 pub async fn get_articles(
     db: SqlitePool,
     query: HashMap<String, String>,
@@ -31,9 +37,11 @@ pub async fn get_articles(
         .map(|v| v.parse().ok())
         .flatten()
         .unwrap_or(0);
+    let category = query.get("category").map(|v| v.as_str());
 
-    let articles = article_db::get_articles(&db, limit, offset, active, ancestor).await?;
-    let num_articles = article_db::num_active(&db).await?;
+    let articles =
+        article_db::get_articles(&db, Some(limit), offset, active, ancestor, category).await?;
+    let num_articles = article_db::num_active(&db, category).await?;
 
     let result = model::ArticlesResp {
         articles,
@@ -54,6 +62,7 @@ pub async fn get_article(
 
 pub async fn add_article(
     db: SqlitePool,
+    events: EventBus,
     req: model::ArticleAddReq,
 ) -> Result<JsonReply<model::ArticleResp>, warp::Rejection> {
     let name = req.name.trim();
@@ -62,13 +71,23 @@ pub async fn add_article(
         .map(|v| v.trim().to_owned())
         .filter(|v| !v.is_empty());
 
-    let article = article_db::add_article(&db, name, barcode.as_deref(), req.amount).await?;
+    let amount = check_amount(req.amount)?;
+    let categories = clean_categories(req.categories.unwrap_or_default())?;
+    let deposit = check_deposit(req.deposit)?.unwrap_or(0);
+
+    let article =
+        article_db::add_article(&db, name, barcode.as_deref(), amount, deposit, &categories)
+            .await?;
+    events
+        .publish_committed(&db, Event::ArticleCreated(article.clone()))
+        .await;
 
     Ok(json_reply(model::ArticleResp { article }))
 }
 
 pub async fn update_article(
     db: SqlitePool,
+    events: EventBus,
     precursor_id: i32,
     req: model::ArticleAddReq,
 ) -> Result<JsonReply<model::ArticleResp>, warp::Rejection> {
@@ -78,17 +97,133 @@ pub async fn update_article(
         .map(|v| v.trim().to_owned())
         .filter(|v| !v.is_empty());
 
-    let article =
-        article_db::update_article(&db, precursor_id, name, barcode.as_deref(), req.amount).await?;
+    let amount = check_amount(req.amount)?;
+    let categories = req.categories.map(clean_categories).transpose()?;
+    let deposit = check_deposit(req.deposit)?;
+
+    let article = article_db::update_article(
+        &db,
+        precursor_id,
+        name,
+        barcode.as_deref(),
+        amount,
+        deposit,
+        categories.as_deref(),
+    )
+    .await?;
+    events
+        .publish_committed(&db, Event::ArticleUpdated(article.clone()))
+        .await;
 
     Ok(json_reply(model::ArticleResp { article }))
 }
 
 pub async fn delete_article(
     db: SqlitePool,
+    events: EventBus,
     article_id: i32,
 ) -> Result<JsonReply<model::ArticleResp>, warp::Rejection> {
     let article = article_db::delete_article(&db, article_id).await?;
+    events
+        .publish_committed(&db, Event::ArticleDeleted(article.clone()))
+        .await;
 
     Ok(json_reply(model::ArticleResp { article }))
 }
+
+pub async fn get_article_history(
+    db: SqlitePool,
+    article_id: i32,
+) -> Result<JsonReply<model::ArticleHistoryResp>, warp::Rejection> {
+    let versions = article_db::get_history(&db, article_id).await?;
+    if versions.is_empty() {
+        return Err(DbError::EntityNotFound("Article".to_string()).into());
+    }
+
+    Ok(json_reply(model::ArticleHistoryResp {
+        count: versions.len(),
+        versions,
+    }))
+}
+
+pub async fn get_price_changes(
+    db: SqlitePool,
+    article_id: i32,
+) -> Result<JsonReply<model::PriceChangesResp>, warp::Rejection> {
+    article_db::get_article_or_error(&db, article_id).await?;
+    let price_changes = price_change_db::get_price_changes(&db, article_id).await?;
+
+    Ok(json_reply(model::PriceChangesResp {
+        count: price_changes.len(),
+        price_changes,
+    }))
+}
+
+pub async fn add_price_change(
+    db: SqlitePool,
+    article_id: i32,
+    req: model::PriceChangeAddReq,
+) -> Result<JsonReply<model::PriceChangeResp>, warp::Rejection> {
+    let amount = check_amount(req.amount)?;
+    let activates = common::parse_future_datetime(&req.activates, "Activation time")?;
+    let article = article_db::get_article_or_error(&db, article_id).await?;
+    if !article.entity.active {
+        return Err(ClientError::ParameterInvalid(
+            "Price changes can only be scheduled on active articles.".to_string(),
+        )
+        .into());
+    }
+
+    let price_change =
+        price_change_db::add_price_change(&db, article_id, amount, &activates).await?;
+
+    Ok(json_reply(model::PriceChangeResp { price_change }))
+}
+
+pub async fn cancel_price_change(
+    db: SqlitePool,
+    price_change_id: i32,
+) -> Result<JsonReply<model::PriceChangeResp>, warp::Rejection> {
+    let price_change = price_change_db::cancel_price_change(&db, price_change_id).await?;
+
+    Ok(json_reply(model::PriceChangeResp { price_change }))
+}
+
+fn check_amount(amount: i32) -> Result<i32, ClientError> {
+    match amount {
+        v if v < 0 => Err(ClientError::ParameterInvalid(
+            "Amount must not be negative.".to_string(),
+        )),
+        v => Ok(v),
+    }
+}
+
+fn check_deposit(deposit: Option<i32>) -> Result<Option<i32>, ClientError> {
+    match deposit {
+        Some(v) if v < 0 => Err(ClientError::ParameterInvalid(
+            "Deposit must not be negative.".to_string(),
+        )),
+        v => Ok(v),
+    }
+}
+
+/// Trims the names and drops empty ones and duplicates
+fn clean_categories(categories: Vec<String>) -> Result<Vec<String>, ClientError> {
+    let mut result: Vec<String> = vec![];
+    for name in categories
+        .iter()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+    {
+        if name.chars().count() > MAX_CATEGORY_LEN {
+            return Err(ClientError::ParameterInvalid(format!(
+                "Category names must not be longer than {} characters.",
+                MAX_CATEGORY_LEN
+            )));
+        }
+        if !result.iter().any(|v| v == name) {
+            result.push(name.to_string());
+        }
+    }
+    return Ok(result);
+}
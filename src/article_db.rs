@@ -3,23 +3,32 @@ use std::iter::Iterator;
 
 use crate::{error::DbError, model};
 
+/// Latest versions of the articles by name, all of them if `limit` is None
 pub async fn get_articles(
     db: &SqlitePool,
-    limit: i32,
+    limit: Option<i32>,
     offset: i32,
     active: bool,
     ancestor: bool,
+    category: Option<&str>,
 ) -> std::result::Result<Vec<model::ArticleObject>, DbError> {
     let mut tx = db.begin().await?;
     let article_entities_result = sqlx::query_as::<_, model::ArticleEntity>(
-		"SELECT a1.id, a1.precursor_id, a1.name, a1.barcode, a1.amount, a1.active, a1.created, a1.usage_count
+		"SELECT a1.id, a1.precursor_id, a1.name, a1.barcode, a1.amount, a1.deposit, a1.active, a1.created, a1.usage_count
 		FROM article AS a1
 		LEFT JOIN article AS a2 ON a1.id = a2.precursor_id
 		WHERE a1.active IS ? AND a2.id IS NULL
+		AND (? IS NULL OR a1.id IN (
+			SELECT ac.article_id FROM article_category AS ac
+			INNER JOIN category AS c ON c.id = ac.category_id
+			WHERE c.name = ?))
 		ORDER BY a1.name LIMIT ? OFFSET ?"
 	)
 	.bind(active)
-	.bind(limit)
+	.bind(category)
+	.bind(category)
+	// a negative limit is none in SQLite
+	.bind(limit.unwrap_or(-1))
 	.bind(offset)
     .fetch_all(&mut tx).await?;
 
@@ -29,8 +38,10 @@ pub async fn get_articles(
             true => get_article_tx(&mut tx, parent.precursor_id).await?,
             false => None,
         };
+        let categories = get_categories_tx(&mut tx, parent.id).await?;
         let o = model::ArticleObject {
             entity: parent,
+            categories,
             precursor: child,
         };
         result.push(o);
@@ -41,13 +52,22 @@ pub async fn get_articles(
     return Ok(result);
 }
 
-pub async fn num_active(db: &SqlitePool) -> std::result::Result<i32, DbError> {
+pub async fn num_active(
+    db: &SqlitePool,
+    category: Option<&str>,
+) -> std::result::Result<i32, DbError> {
     let mut tx = db.begin().await?;
     let count_result = sqlx::query_scalar::<_, i32>(
         "SELECT count(*)
 		FROM article
-		WHERE active IS TRUE",
+		WHERE active IS TRUE
+		AND (? IS NULL OR id IN (
+			SELECT ac.article_id FROM article_category AS ac
+			INNER JOIN category AS c ON c.id = ac.category_id
+			WHERE c.name = ?))",
     )
+    .bind(category)
+    .bind(category)
     .fetch_one(&mut tx)
     .await?;
     tx.commit().await?;
@@ -73,10 +93,10 @@ pub async fn get_article_tx(
         Some(aid) => {
             let article_chain = sqlx::query_as::<_, model::ArticleEntity>(
                 "WITH article_chain AS (
-					SELECT id, precursor_id, name, barcode, amount, active, created, usage_count
+					SELECT id, precursor_id, name, barcode, amount, deposit, active, created, usage_count
 					FROM article WHERE id = ?
 					UNION
-					SELECT p.id, p.precursor_id, p.name, p.barcode, p.amount, p.active, p.created, p.usage_count
+					SELECT p.id, p.precursor_id, p.name, p.barcode, p.amount, p.deposit, p.active, p.created, p.usage_count
 					FROM article p
 						INNER JOIN article_chain o 
 							ON o.precursor_id = p.id
@@ -84,10 +104,16 @@ pub async fn get_article_tx(
 				SELECT * FROM article_chain;",
             )
             .bind(aid)
-            .fetch_all(tx)
+            .fetch_all(&mut *tx)
             .await?;
 
-            Ok(build_article_chain(article_chain))
+            let mut article = build_article_chain(article_chain);
+            let mut next = article.as_deref_mut();
+            while let Some(a) = next {
+                a.categories = get_categories_tx(tx, a.entity.id).await?;
+                next = a.precursor.as_deref_mut();
+            }
+            Ok(article)
         }
         None => Ok(None),
     }
@@ -119,6 +145,7 @@ fn build_article_chain(chain: Vec<model::ArticleEntity>) -> Option<Box<model::Ar
     return chain.into_iter().rev().fold(None, |acc, next| {
         Some(Box::new(model::ArticleObject {
             entity: next,
+            categories: vec![],
             precursor: acc,
         }))
     });
@@ -129,24 +156,30 @@ pub async fn add_article(
     name: &str,
     barcode: Option<&str>,
     amount: i32,
+    deposit: i32,
+    categories: &[String],
 ) -> std::result::Result<model::ArticleObject, DbError> {
     let mut tx = db.begin().await?;
     let article_entity = sqlx::query_as::<_, model::ArticleEntity>(
-        "INSERT INTO article (name, barcode, amount, active, created, usage_count)
-		VALUES(?, ?, ?, TRUE, datetime('now', 'localtime'), 0);
+        "INSERT INTO article (name, barcode, amount, deposit, active, created, usage_count)
+		VALUES(?, ?, ?, ?, TRUE, datetime('now', 'localtime'), 0);
 
-        SELECT id, precursor_id, name, barcode, amount, active, created, usage_count
+        SELECT id, precursor_id, name, barcode, amount, deposit, active, created, usage_count
         FROM article WHERE id = last_insert_rowid();",
     )
     .bind(name)
     .bind(barcode)
     .bind(amount)
+    .bind(deposit)
     .fetch_one(&mut tx)
     .await?;
+    set_categories_tx(&mut tx, article_entity.id, categories).await?;
+    let categories = get_categories_tx(&mut tx, article_entity.id).await?;
     tx.commit().await?;
 
     return Ok(model::ArticleObject {
         entity: article_entity,
+        categories,
         precursor: None,
     });
 }
@@ -157,9 +190,35 @@ pub async fn update_article(
     name: &str,
     barcode: Option<&str>,
     amount: i32,
+    deposit: Option<i32>,
+    categories: Option<&[String]>,
 ) -> std::result::Result<model::ArticleObject, DbError> {
     let mut tx = db.begin().await?;
-    let child = get_article_tx(&mut tx, Some(precursor_id))
+    let article = update_article_tx(
+        &mut tx,
+        precursor_id,
+        name,
+        barcode,
+        amount,
+        deposit,
+        categories,
+    )
+    .await?;
+    tx.commit().await?;
+
+    return Ok(article);
+}
+
+pub async fn update_article_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    precursor_id: i32,
+    name: &str,
+    barcode: Option<&str>,
+    amount: i32,
+    deposit: Option<i32>,
+    categories: Option<&[String]>,
+) -> std::result::Result<model::ArticleObject, DbError> {
+    let child = get_article_tx(&mut *tx, Some(precursor_id))
         .await?
         .map(|mut a| {
             a.entity.active = false;
@@ -168,28 +227,45 @@ pub async fn update_article(
 
     let article_entity = sqlx::query_as::<_, model::ArticleEntity>(
         "-- try to insert, trigger prevents updating inactive articles
-		INSERT INTO article (precursor_id, name, barcode, amount, active, created, usage_count)
-		SELECT id, ?, ?, ?, TRUE, datetime('now', 'localtime'), usage_count
+		INSERT INTO article (precursor_id, name, barcode, amount, deposit, active, created, usage_count)
+		SELECT id, ?, ?, ?, COALESCE(?, deposit), TRUE, datetime('now', 'localtime'), usage_count
 		FROM article WHERE id = ?;
 
 		-- deactivate old article if it had been active to make sure the transaction does not fail
 		UPDATE article SET active = FALSE
 		WHERE id = ?;
 
-		SELECT id, precursor_id, name, barcode, amount, active, created, usage_count
+		SELECT id, precursor_id, name, barcode, amount, deposit, active, created, usage_count
         FROM article WHERE id = last_insert_rowid();",
     )
     .bind(name)
     .bind(barcode)
     .bind(amount)
+    .bind(deposit)
     .bind(precursor_id)
     .bind(precursor_id)
-    .fetch_one(&mut tx)
+    .fetch_one(&mut *tx)
     .await?;
-    tx.commit().await?;
+
+    // the successor keeps the categories unless new ones are given
+    match categories {
+        Some(categories) => set_categories_tx(&mut *tx, article_entity.id, categories).await?,
+        None => {
+            sqlx::query(
+                "INSERT INTO article_category (article_id, category_id)
+                SELECT ?, category_id FROM article_category WHERE article_id = ?",
+            )
+            .bind(article_entity.id)
+            .bind(precursor_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+    };
+    let categories = get_categories_tx(&mut *tx, article_entity.id).await?;
 
     return Ok(model::ArticleObject {
         entity: article_entity,
+        categories,
         precursor: child,
     });
 }
@@ -211,3 +287,107 @@ pub async fn delete_article(
         None => Err(DbError::EntityNotFound("Article".to_string())),
     };
 }
+
+/// Names of the categories of the article, sorted
+pub async fn get_categories_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    article_id: i32,
+) -> std::result::Result<Vec<String>, DbError> {
+    let categories = sqlx::query_scalar::<_, String>(
+        "SELECT c.name FROM article_category AS ac
+        INNER JOIN category AS c ON c.id = ac.category_id
+        WHERE ac.article_id = ?
+        ORDER BY c.name",
+    )
+    .bind(article_id)
+    .fetch_all(tx)
+    .await?;
+
+    return Ok(categories);
+}
+
+/// Replaces the categories of the article, unknown ones are created
+async fn set_categories_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    article_id: i32,
+    categories: &[String],
+) -> std::result::Result<(), DbError> {
+    sqlx::query("DELETE FROM article_category WHERE article_id = ?")
+        .bind(article_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for name in categories.iter() {
+        sqlx::query(
+            "INSERT OR IGNORE INTO category (name, created)
+            VALUES (?, datetime('now', 'localtime'));
+
+            INSERT OR IGNORE INTO article_category (article_id, category_id)
+            SELECT ?, id FROM category WHERE name = ?;",
+        )
+        .bind(name)
+        .bind(article_id)
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    return Ok(());
+}
+
+/// All versions of the article the version `article_id` belongs to, newest first
+pub async fn get_history(
+    db: &SqlitePool,
+    article_id: i32,
+) -> std::result::Result<Vec<model::ArticleVersion>, DbError> {
+    let mut tx = db.begin().await?;
+    let versions = sqlx::query_as::<_, model::ArticleVersion>(
+        "WITH successors AS (
+			SELECT id FROM article WHERE id = ?
+			UNION
+			SELECT a.id FROM article AS a
+				INNER JOIN successors AS s ON a.precursor_id = s.id
+		), versions AS (
+			SELECT id, precursor_id FROM article WHERE id = (SELECT MAX(id) FROM successors)
+			UNION
+			SELECT a.id, a.precursor_id FROM article AS a
+				INNER JOIN versions AS v ON a.id = v.precursor_id
+		)
+		SELECT a.id, a.name, a.barcode, a.amount, a.deposit, a.active,
+			a.created AS valid_from, s.created AS valid_until,
+			COUNT(t.id) AS sales, COALESCE(SUM(t.quantity), 0) AS quantity
+		FROM versions AS v
+		INNER JOIN article AS a ON a.id = v.id
+		LEFT JOIN article AS s ON s.precursor_id = a.id
+		LEFT JOIN transactions AS t ON t.article_id = a.id AND t.deleted IS FALSE AND t.deposit IS FALSE
+		GROUP BY a.id
+		ORDER BY a.id DESC",
+    )
+    .bind(article_id)
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(versions);
+}
+
+/// The active version of the article the version `article_id` belongs to, None if it was retired
+pub async fn get_latest_version_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    article_id: i32,
+) -> std::result::Result<Option<i32>, DbError> {
+    let latest = sqlx::query_scalar::<_, i32>(
+        "WITH successors AS (
+			SELECT id, active FROM article WHERE id = ?
+			UNION
+			SELECT a.id, a.active FROM article AS a
+				INNER JOIN successors AS s ON a.precursor_id = s.id
+		)
+		SELECT id FROM successors WHERE active IS TRUE",
+    )
+    .bind(article_id)
+    .fetch_optional(tx)
+    .await?;
+
+    return Ok(latest);
+}
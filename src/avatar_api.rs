@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use hyper::body::Bytes;
+use sqlx::SqlitePool;
+use warp::Reply;
+
+use crate::{
+    avatar_db,
+    error::{ClientError, DbError},
+    events::{Event, EventBus},
+    model::{self, json_reply, JsonReply},
+    settings::StrichlisteSetting,
+    user_db,
+};
+
+/// Content type of the image, told by its first bytes rather than the header of the request
+fn image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+pub async fn get_avatar(db: SqlitePool, user_id: i32) -> Result<impl Reply, warp::Rejection> {
+    let (content_type, data) = avatar_db::get_avatar(&db, user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("Avatar".to_string()))?;
+
+    Ok(warp::reply::with_header(data, "content-type", content_type))
+}
+
+/// Replaces the avatar with the image in the body, the size is limited by the route.
+pub async fn set_avatar(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    events: EventBus,
+    user_id: i32,
+    data: Bytes,
+) -> Result<JsonReply<model::UserResp>, warp::Rejection> {
+    let content_type = image_type(&data).ok_or_else(|| {
+        ClientError::ParameterInvalid("Avatar is no PNG, JPEG, GIF or WebP image.".to_string())
+    })?;
+
+    let user = update_avatar(&db, &settings, user_id, Some((content_type, &data[..]))).await?;
+    events
+        .publish_committed(&db, Event::UserUpdated(user.clone()))
+        .await;
+
+    Ok(json_reply(model::UserResp { user }))
+}
+
+pub async fn delete_avatar(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    events: EventBus,
+    user_id: i32,
+) -> Result<JsonReply<model::UserResp>, warp::Rejection> {
+    let user = update_avatar(&db, &settings, user_id, None).await?;
+    events
+        .publish_committed(&db, Event::UserUpdated(user.clone()))
+        .await;
+
+    Ok(json_reply(model::UserResp { user }))
+}
+
+async fn update_avatar(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    user_id: i32,
+    avatar: Option<(&str, &[u8])>,
+) -> Result<model::UserEntity, DbError> {
+    let mut tx = db.begin().await?;
+    user_db::get_user_tx(&mut tx, settings, &user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    avatar_db::set_avatar_tx(&mut tx, user_id, avatar).await?;
+    let user = user_db::get_user_tx(&mut tx, settings, &user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    tx.commit().await?;
+
+    return Ok(user);
+}
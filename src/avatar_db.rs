@@ -0,0 +1,45 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::error::DbError;
+
+/// Content type and image data of the avatar of the user
+pub async fn get_avatar(
+    db: &SqlitePool,
+    user_id: i32,
+) -> std::result::Result<Option<(String, Vec<u8>)>, DbError> {
+    let mut tx = db.begin().await?;
+    let avatar = sqlx::query_as::<_, (String, Vec<u8>)>(
+        "SELECT content_type, data FROM user_avatar WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(avatar);
+}
+
+/// Replaces the avatar of the user, or removes it if `avatar` is None.
+pub async fn set_avatar_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: i32,
+    avatar: Option<(&str, &[u8])>,
+) -> std::result::Result<(), DbError> {
+    sqlx::query("DELETE FROM user_avatar WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    if let Some((content_type, data)) = avatar {
+        sqlx::query(
+            "INSERT INTO user_avatar (user_id, content_type, data, created)
+            VALUES (?, ?, ?, datetime('now', 'localtime'))",
+        )
+        .bind(user_id)
+        .bind(content_type)
+        .bind(data)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    return Ok(());
+}
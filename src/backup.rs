@@ -0,0 +1,72 @@
+use std::{io, path::Path};
+
+use chrono::Local;
+use log::{error, info};
+use sqlx::SqlitePool;
+
+use crate::{db, error::DbError, settings};
+
+const BACKUP_PREFIX: &str = "strichliste-";
+const BACKUP_SUFFIX: &str = ".sqlite";
+
+/// Writes a backup every `interval` and removes old ones exceeding `retain`.
+/// The first backup is written right away, so restarts don't delay it.
+pub async fn run_backup_job(db: SqlitePool, settings: settings::BackupSetting) {
+    let mut interval = tokio::time::interval(settings::to_duration(&settings.interval));
+    loop {
+        interval.tick().await;
+        match create_backup(&db, &settings.directory).await {
+            Ok(file) => info!("Scheduled backup written to {}", file),
+            Err(e) => error!("Scheduled backup failed: {}", e),
+        };
+        if let Err(e) = apply_retention(&settings.directory, settings.retain).await {
+            error!("Removing old backups failed: {}", e);
+        }
+    }
+}
+
+/// Writes a consistent snapshot of the database into `directory` and returns its path.
+pub async fn create_backup(db: &SqlitePool, directory: &str) -> Result<String, DbError> {
+    tokio::fs::create_dir_all(directory).await?;
+    // with milliseconds, as VACUUM INTO fails if two backups in the same second get the same name
+    let file_name = format!(
+        "{}{}{}",
+        BACKUP_PREFIX,
+        Local::now().format("%Y%m%d-%H%M%S%.3f"),
+        BACKUP_SUFFIX
+    );
+    let path = Path::new(directory).join(file_name);
+    let file = path.to_string_lossy();
+
+    db::backup_db(db, &file).await?;
+
+    return Ok(file.to_string());
+}
+
+/// Removes the oldest backups in `directory` beyond the newest `retain` ones, other files are left alone.
+pub async fn apply_retention(directory: &str, retain: usize) -> io::Result<()> {
+    let mut backups = Vec::new();
+    let mut entries = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if is_backup(&entry.path()) {
+            backups.push(entry.path());
+        }
+    }
+    // the timestamp in the name sorts them from old to new
+    backups.sort();
+
+    let num_obsolete = backups.len().saturating_sub(retain);
+    for path in backups.into_iter().take(num_obsolete) {
+        info!("Removing old backup {}", path.display());
+        tokio::fs::remove_file(path).await?;
+    }
+
+    return Ok(());
+}
+
+fn is_backup(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX))
+        .unwrap_or(false)
+}
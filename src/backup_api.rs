@@ -0,0 +1,52 @@
+use std::{path::Path, sync::Arc};
+
+use sqlx::SqlitePool;
+use warp::Reply;
+
+use crate::{
+    backup,
+    error::DbError,
+    model::{self, json_reply, JsonReply},
+    settings::BackupSetting,
+};
+
+pub async fn trigger_backup(
+    db: SqlitePool,
+    settings: Arc<BackupSetting>,
+) -> Result<JsonReply<model::BackupResp>, warp::Rejection> {
+    if !settings.download {
+        return Err(warp::reject::not_found());
+    }
+
+    let file = backup::create_backup(&db, &settings.directory).await?;
+
+    Ok(json_reply(model::BackupResp { file }))
+}
+
+pub async fn download_backup(
+    db: SqlitePool,
+    settings: Arc<BackupSetting>,
+) -> Result<impl Reply, warp::Rejection> {
+    if !settings.download {
+        return Err(warp::reject::not_found());
+    }
+
+    // the snapshot is only written to disk for the download
+    let file = backup::create_backup(&db, &std::env::temp_dir().to_string_lossy()).await?;
+    let data = tokio::fs::read(&file).await;
+    tokio::fs::remove_file(&file).await.map_err(DbError::from)?;
+    let data = data.map_err(DbError::from)?;
+
+    let file_name = Path::new(&file)
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let reply = warp::reply::with_header(data, "content-type", "application/vnd.sqlite3");
+    let reply = warp::reply::with_header(
+        reply,
+        "content-disposition",
+        format!("attachment; filename=\"{}\"", file_name),
+    );
+
+    Ok(reply)
+}
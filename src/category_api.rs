@@ -0,0 +1,17 @@
+use sqlx::SqlitePool;
+
+use crate::{
+    category_db,
+    model::{self, json_reply, JsonReply},
+};
+
+pub async fn get_categories(
+    db: SqlitePool,
+) -> Result<JsonReply<model::CategoriesResp>, warp::Rejection> {
+    let categories = category_db::get_categories(&db).await?;
+
+    Ok(json_reply(model::CategoriesResp {
+        count: categories.len(),
+        categories,
+    }))
+}
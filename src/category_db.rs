@@ -0,0 +1,22 @@
+use sqlx::sqlite::SqlitePool;
+
+use crate::{error::DbError, model};
+
+pub async fn get_categories(
+    db: &SqlitePool,
+) -> std::result::Result<Vec<model::CategoryEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let categories = sqlx::query_as::<_, model::CategoryEntity>(
+        "SELECT c.id, c.name, c.created, COUNT(a.id) AS article_count
+        FROM category AS c
+        LEFT JOIN article_category AS ac ON ac.category_id = c.id
+        LEFT JOIN article AS a ON a.id = ac.article_id AND a.active IS TRUE
+        GROUP BY c.id
+        ORDER BY c.name",
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(categories);
+}
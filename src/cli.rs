@@ -0,0 +1,242 @@
+use std::{error::Error, str::FromStr, sync::Arc};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    api_token, api_token_db, article_db, common, credential_db, db, error::ClientError, model,
+    settings::StrichlisteSetting, transaction_db, user_db,
+};
+
+const USAGE: &str = "Usage: strichliste-rs [--check-config]
+       strichliste-rs <command>
+
+Commands:
+  user list [--disabled]
+  user add <name> [<email>]
+  user disable <user-id>
+  user enable <user-id>
+  user reset-pin <user-id>
+  article list [--inactive]
+  article add <name> <amount> [<barcode>]
+  article update <article-id> <name> <amount> [<barcode>]
+  article retire <article-id>
+  tx list <user-id> [<limit>]
+  tx book <user-id> <amount> [<comment>]
+  tx revert <transaction-id>
+  token list
+  token add <name> <scope> [<expires>]
+  token revoke <token-id>
+  db migrate
+  db check
+  db backup <file>
+  db restore <file>
+
+Amounts are given in cents. Bookings done here are not subject to the payment boundaries.
+Token scopes are read, book or admin, expiry times are local as YYYY-MM-DD HH:MM:SS.
+Stop the server before restoring a backup.";
+
+/// Runs an administrative command against the database and prints its result as JSON to stdout.
+pub async fn run(
+    args: &[String],
+    db: &SqlitePool,
+    db_file: &str,
+    settings: Arc<StrichlisteSetting>,
+) -> Result<(), Box<dyn Error>> {
+    let args: Vec<&str> = args.iter().map(|v| v.as_str()).collect();
+
+    // db commands operate on the schema as it is, entity commands need the latest one
+    if matches!(
+        args.first(),
+        Some(&"user") | Some(&"article") | Some(&"tx") | Some(&"token")
+    ) {
+        db::migrate_db(db).await?;
+    }
+
+    match args.as_slice() {
+        ["user", "list", rest @ ..] => {
+            let disabled = rest.contains(&"--disabled");
+            print_json(&user_db::get_users(db, settings, disabled, None).await?)
+        }
+        ["user", "add", name, rest @ ..] => {
+            let email = rest.first().copied();
+            print_json(&user_db::create_user(db, name, email).await?)
+        }
+        ["user", "disable", user_id] => set_user_disabled(db, &settings, user_id, true).await,
+        ["user", "enable", user_id] => set_user_disabled(db, &settings, user_id, false).await,
+        ["user", "reset-pin", user_id] => reset_pin(db, &settings, user_id).await,
+        ["article", "list", rest @ ..] => {
+            let active = !rest.contains(&"--inactive");
+            print_json(&article_db::get_articles(db, None, 0, active, false, None).await?)
+        }
+        ["article", "add", name, amount, rest @ ..] => {
+            let amount = parse_arg(amount, "amount")?;
+            let barcode = rest.first().copied();
+            print_json(&article_db::add_article(db, name, barcode, amount, 0, &[]).await?)
+        }
+        ["article", "update", article_id, name, amount, rest @ ..] => {
+            let article_id = parse_arg(article_id, "article-id")?;
+            let amount = parse_arg(amount, "amount")?;
+            let barcode = rest.first().copied();
+            print_json(
+                &article_db::update_article(db, article_id, name, barcode, amount, None, None)
+                    .await?,
+            )
+        }
+        ["article", "retire", article_id] => {
+            let article_id = parse_arg(article_id, "article-id")?;
+            print_json(&article_db::delete_article(db, article_id).await?)
+        }
+        ["tx", "list", user_id, rest @ ..] => {
+            let user_id = parse_arg(user_id, "user-id")?;
+            let limit = match rest.first() {
+                Some(v) => parse_arg(v, "limit")?,
+                None => 25,
+            };
+            print_json(
+                &transaction_db::get_transactions(db, &settings, &user_id, &limit, &0).await?,
+            )
+        }
+        ["tx", "book", user_id, amount, rest @ ..] => {
+            let user_id = parse_arg(user_id, "user-id")?;
+            let amount = parse_arg(amount, "amount")?;
+            let comment = rest.first().copied();
+            book_value(db, &settings, user_id, amount, comment).await
+        }
+        ["tx", "revert", transaction_id] => {
+            let transaction_id = parse_arg(transaction_id, "transaction-id")?;
+            print_json(&transaction_db::revert_transaction(db, &settings, &transaction_id).await?)
+        }
+        ["token", "list"] => print_json(&api_token_db::get_api_tokens(db).await?),
+        ["token", "add", name, scope, rest @ ..] => {
+            if !api_token::SCOPES.contains(scope) {
+                return Err(ClientError::ParameterInvalid(format!("scope '{}'", scope)).into());
+            }
+            let expires = rest
+                .first()
+                .map(|v| common::parse_future_datetime(v, "expires"))
+                .transpose()?;
+            let (token, secret) =
+                api_token::create_token(db, name, scope, expires.as_deref()).await?;
+            print_json(&model::ApiTokenResp {
+                token,
+                secret: Some(secret),
+            })
+        }
+        ["token", "revoke", token_id] => {
+            let token_id = parse_arg(token_id, "token-id")?;
+            print_json(&api_token_db::revoke_api_token(db, token_id).await?)
+        }
+        ["db", "migrate"] => {
+            db::migrate_db(db).await?;
+            println!("Database schema is at version {}.", db::DB_VERSION);
+            Ok(())
+        }
+        ["db", "check"] => {
+            let problems = db::check_db(db).await?;
+            for p in problems.iter() {
+                println!("{}", p);
+            }
+            match problems.is_empty() {
+                true => Ok(()),
+                false => Err(format!("Database check found {} problem(s).", problems.len()).into()),
+            }
+        }
+        ["db", "backup", target] => {
+            db::backup_db(db, target).await?;
+            Ok(())
+        }
+        ["db", "restore", source] => {
+            // the file is replaced, so no connection must be left open
+            db.close().await;
+            db::restore_db(db_file, source).await?;
+            Ok(())
+        }
+        ["help"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(ClientError::ParameterInvalid(format!("unknown command\n\n{}", USAGE)).into()),
+    }
+}
+
+async fn set_user_disabled(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    user_id: &str,
+    disabled: bool,
+) -> Result<(), Box<dyn Error>> {
+    let user_id = parse_arg(user_id, "user-id")?;
+    let user =
+        user_db::get_user(db, settings, &user_id)
+            .await?
+            .ok_or(ClientError::ParameterInvalid(format!(
+                "User {} does not exist.",
+                user_id
+            )))?;
+
+    let user = user_db::update_user(
+        db,
+        settings,
+        user_id,
+        &user.name,
+        user.email.as_deref(),
+        None,
+        None,
+        disabled,
+    )
+    .await?;
+    print_json(&user)
+}
+
+/// Removes a forgotten PIN, the user can set a new one without the old one then.
+async fn reset_pin(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    user_id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let user_id = parse_arg(user_id, "user-id")?;
+    let mut tx = db.begin().await?;
+    credential_db::set_pin_tx(&mut tx, user_id, None).await?;
+    let user = user_db::get_user_tx(&mut tx, settings, &user_id)
+        .await?
+        .ok_or(ClientError::ParameterInvalid(format!(
+            "User {} does not exist.",
+            user_id
+        )))?;
+    tx.commit().await?;
+
+    print_json(&user)
+}
+
+async fn book_value(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    user_id: i32,
+    amount: i32,
+    comment: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let mut tx = db.begin().await?;
+    let user = user_db::get_user_tx(&mut tx, settings, &user_id)
+        .await?
+        .ok_or(ClientError::ParameterInvalid(format!(
+            "User {} does not exist.",
+            user_id
+        )))?;
+    let transaction =
+        transaction_db::add_transaction_with_value_tx(&mut tx, user, &amount, comment).await?;
+    tx.commit().await?;
+
+    print_json(&transaction)
+}
+
+fn parse_arg<T: FromStr>(value: &str, name: &str) -> Result<T, ClientError> {
+    value
+        .parse()
+        .map_err(|_| ClientError::ParameterInvalid(format!("{} '{}'", name, value)))
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
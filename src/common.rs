@@ -1,11 +1,14 @@
 use std::{borrow::Cow, convert::Infallible, env, sync::Arc};
 
-use chrono::Local;
+use chrono::{Local, NaiveDateTime};
 use regex::Regex;
 use sqlx::{SqlitePool};
 use warp::Filter;
 
-use crate::{error::ClientError, settings};
+use crate::error::ClientError;
+
+/// Format of the local times stored in the database
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 pub fn env_or(key: &str, default: &str) -> String {
     return env::var(key).ok().unwrap_or(default.to_string());
@@ -17,14 +20,21 @@ pub fn with_db(
     warp::any().map(move || db_pool.clone())
 }
 
-pub fn with_settings(
-    settings: settings::StrichlisteSetting,
-) -> impl Filter<Extract = (Arc<settings::StrichlisteSetting>,), Error = Infallible> + Clone {
+pub fn with_settings<T: Send + Sync + 'static>(
+    settings: T,
+) -> impl Filter<Extract = (Arc<T>,), Error = Infallible> + Clone {
     let sw = Arc::new(settings);
     // TODO: figure out how to use reference
     warp::any().map(move || sw.clone())
 }
 
+/// Hands a clone of `state` to every request, for state that is cheap to clone itself.
+pub fn with_state<T: Clone + Send + Sync + 'static>(
+    state: T,
+) -> impl Filter<Extract = (T,), Error = Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
+
 pub fn sanitize_control_chars(input: &str) -> Cow<str> {
     let re = Regex::new(r"[\x00-\x1F\x7F]").unwrap();
     return re.replace_all(input, "");
@@ -39,9 +49,45 @@ pub fn assert_email(input: &str) -> Result<(), ClientError> {
     };
 }
 
+/// Compares in constant time, so the token can't be guessed byte by byte from response times
+pub fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 pub fn cur_datetime_str() -> String {
     return Local::now()
         .naive_local()
-        .format("%Y-%m-%d %H:%M:%S")
+        .format(DATETIME_FORMAT)
         .to_string();
 }
+
+/// Parses a local time given as `YYYY-MM-DD HH:MM:SS`, which has to lie in the future.
+pub fn parse_future_datetime(input: &str, name: &str) -> Result<String, ClientError> {
+    let datetime = NaiveDateTime::parse_from_str(input.trim(), DATETIME_FORMAT).map_err(|_| {
+        ClientError::ParameterInvalid(format!(
+            "{} '{}' has to be given as YYYY-MM-DD HH:MM:SS.",
+            name, input
+        ))
+    })?;
+    if datetime <= Local::now().naive_local() {
+        return Err(ClientError::ParameterInvalid(format!(
+            "{} has to be in the future.",
+            name
+        )));
+    }
+    return Ok(datetime.format(DATETIME_FORMAT).to_string());
+}
+
+/// The user of routes like `/api/user/{userId}/transaction`
+pub fn path_user_id(path: &str) -> Option<i32> {
+    let mut segments = path.trim_start_matches('/').split('/');
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some("api"), Some("user"), Some(id)) => id.parse().ok(),
+        _ => None,
+    }
+}
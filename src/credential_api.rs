@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+
+use crate::{
+    credential_db,
+    error::{ClientError, DbError},
+    model::{self, json_reply, JsonReply},
+    settings::StrichlisteSetting,
+    user_db,
+};
+
+/// Rounds of HMAC-SHA256 applied to a PIN, PINs are short so guessing them has to be slow
+const PIN_HASH_ROUNDS: usize = 10000;
+
+/// Wrong PINs in a row before the PIN is locked
+const MAX_PIN_ATTEMPTS: i32 = 5;
+
+/// Lockout after `MAX_PIN_ATTEMPTS`, doubled with every further wrong PIN up to `MAX_PIN_LOCKOUT_SECS`
+const PIN_LOCKOUT_SECS: i64 = 30;
+const MAX_PIN_LOCKOUT_SECS: i64 = 3600;
+
+const MAX_UID_LEN: usize = 64;
+
+/// Fails unless `pin` matches the PIN of the user, passes if the user has none.
+/// Checked outside of the request's transaction, so wrong PINs are counted even though the request fails.
+/// Every attempt is counted before the PIN is hashed and the count is reset by the right PIN,
+/// so concurrent requests can't try more PINs than allowed.
+pub async fn check_pin(
+    db: &SqlitePool,
+    user_id: i32,
+    pin: Option<&str>,
+) -> Result<(), warp::Rejection> {
+    let stored = match credential_db::get_pin(db, user_id).await? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    let pin = match pin {
+        Some(v) => v,
+        None => return Err(ClientError::Forbidden("The user requires a PIN.".to_string()).into()),
+    };
+    let claimed = stored.locked_secs == 0
+        && credential_db::claim_pin_attempt(
+            db,
+            user_id,
+            MAX_PIN_ATTEMPTS,
+            PIN_LOCKOUT_SECS,
+            MAX_PIN_LOCKOUT_SECS,
+        )
+        .await?;
+    if !claimed {
+        // locked, possibly by a concurrent attempt since the PIN was read
+        let locked_secs = match credential_db::get_pin(db, user_id).await? {
+            Some(v) => v.locked_secs.max(1),
+            None => return Ok(()),
+        };
+        return Err(ClientError::TooManyRequests(locked_secs as u64).into());
+    }
+
+    if hash_pin(&stored.salt, pin).await == stored.hash {
+        credential_db::reset_pin_failures(db, user_id).await?;
+        return Ok(());
+    }
+    Err(ClientError::Forbidden("Wrong PIN.".to_string()).into())
+}
+
+/// Hashes on the blocking pool, the rounds would stall the other requests otherwise.
+async fn hash_pin(salt: &str, pin: &str) -> String {
+    let (salt, pin) = (salt.to_string(), pin.to_string());
+    return tokio::task::spawn_blocking(move || hash_pin_blocking(&salt, &pin))
+        .await
+        .expect("PIN hashing failed");
+}
+
+fn hash_pin_blocking(salt: &str, pin: &str) -> String {
+    let mut digest = pin.as_bytes().to_vec();
+    for _ in 0..PIN_HASH_ROUNDS {
+        let mut mac = Hmac::<Sha256>::new_varkey(salt.as_bytes()).expect("HMAC key rejected");
+        mac.update(&digest);
+        digest = mac.finalize().into_bytes().to_vec();
+    }
+    return hex::encode(digest);
+}
+
+/// Card readers differ in case and separators, e.g. `04:a2:3b` and `04A23B` are the same card.
+fn normalize_uid(uid: &str) -> Result<String, ClientError> {
+    let uid: String = uid
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != '-')
+        .collect::<String>()
+        .to_uppercase();
+    if uid.is_empty() || uid.len() > MAX_UID_LEN || !uid.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(ClientError::ParameterInvalid(format!(
+            "Token id must have 1 to {} letters or digits.",
+            MAX_UID_LEN
+        )));
+    }
+    return Ok(uid);
+}
+
+/// Changes or removes the PIN, the current one has to be given in `currentPin`.
+/// Only an admin can set the first PIN, see `reset_pin`, so nobody can lock others out of their account.
+pub async fn set_pin(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    user_id: i32,
+    req: model::UserPinReq,
+) -> Result<JsonReply<model::UserResp>, warp::Rejection> {
+    check_pin_format(&req.pin)?;
+    if req.pin.is_some() && credential_db::get_pin(&db, user_id).await?.is_none() {
+        return Err(
+            ClientError::Forbidden("Only an admin can set the first PIN.".to_string()).into(),
+        );
+    }
+
+    check_pin(&db, user_id, req.current_pin.as_deref()).await?;
+    store_pin(&db, &settings, user_id, req.pin.as_deref()).await
+}
+
+/// Sets or removes the PIN without the current one, for admins
+pub async fn reset_pin(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    user_id: i32,
+    req: model::UserPinReq,
+) -> Result<JsonReply<model::UserResp>, warp::Rejection> {
+    check_pin_format(&req.pin)?;
+    store_pin(&db, &settings, user_id, req.pin.as_deref()).await
+}
+
+fn check_pin_format(pin: &Option<String>) -> Result<(), ClientError> {
+    if let Some(pin) = pin {
+        if pin.len() < 4 || pin.len() > 12 || !pin.chars().all(|c| c.is_ascii_digit()) {
+            return Err(ClientError::ParameterInvalid(
+                "PIN must have 4 to 12 digits.".to_string(),
+            ));
+        }
+    }
+    return Ok(());
+}
+
+async fn store_pin(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    user_id: i32,
+    pin: Option<&str>,
+) -> Result<JsonReply<model::UserResp>, warp::Rejection> {
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    user_db::get_user_tx(&mut tx, settings, &user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+
+    match pin {
+        Some(pin) => {
+            let salt = credential_db::new_salt_tx(&mut tx).await?;
+            let hash = hash_pin(&salt, pin).await;
+            credential_db::set_pin_tx(&mut tx, user_id, Some((&salt, &hash))).await?;
+        }
+        None => credential_db::set_pin_tx(&mut tx, user_id, None).await?,
+    }
+    let user = user_db::get_user_tx(&mut tx, settings, &user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    Ok(json_reply(model::UserResp { user }))
+}
+
+pub async fn get_tokens(
+    db: SqlitePool,
+    user_id: i32,
+    pin: Option<String>,
+) -> Result<JsonReply<model::UserTokensResp>, warp::Rejection> {
+    check_pin(&db, user_id, pin.as_deref()).await?;
+    let tokens = credential_db::get_tokens(&db, user_id).await?;
+
+    Ok(json_reply(model::UserTokensResp {
+        count: tokens.len(),
+        tokens,
+    }))
+}
+
+pub async fn add_token(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    user_id: i32,
+    pin: Option<String>,
+    req: model::UserTokenAddReq,
+) -> Result<JsonReply<model::UserTokenResp>, warp::Rejection> {
+    let uid = normalize_uid(&req.uid)?;
+
+    check_pin(&db, user_id, pin.as_deref()).await?;
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    user_db::get_user_tx(&mut tx, &*settings, &user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    let token = credential_db::add_token_tx(&mut tx, user_id, &uid).await?;
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    Ok(json_reply(model::UserTokenResp { token }))
+}
+
+pub async fn delete_token(
+    db: SqlitePool,
+    user_id: i32,
+    token_id: i32,
+    pin: Option<String>,
+) -> Result<JsonReply<model::UserTokenResp>, warp::Rejection> {
+    check_pin(&db, user_id, pin.as_deref()).await?;
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    let token = credential_db::delete_token_tx(&mut tx, user_id, token_id).await?;
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    Ok(json_reply(model::UserTokenResp { token }))
+}
+
+/// The user a card belongs to, for card readers at the kiosk
+pub async fn find_user_by_token(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    uid: String,
+) -> Result<JsonReply<model::UserResp>, warp::Rejection> {
+    let uid = normalize_uid(&uid)?;
+
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    let token = credential_db::get_token_by_uid_tx(&mut tx, &uid)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User token".to_string()))?;
+    let user = user_db::get_user_tx(&mut tx, &*settings, &token.user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    Ok(json_reply(model::UserResp { user }))
+}
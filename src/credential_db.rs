@@ -0,0 +1,172 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::{error::DbError, model};
+
+/// Salt, hash and lockout of the PIN of the user, None if none is set
+pub async fn get_pin(
+    db: &SqlitePool,
+    user_id: i32,
+) -> std::result::Result<Option<model::UserPinEntity>, DbError> {
+    let pin = sqlx::query_as::<_, model::UserPinEntity>(
+        "SELECT salt, hash, failed_attempts,
+            COALESCE(MAX(0, strftime('%s', locked_until) - strftime('%s', 'now', 'localtime')), 0) AS locked_secs
+        FROM user_pin WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    return Ok(pin);
+}
+
+/// Counts an attempt at the PIN before it is checked, so concurrent guesses can't get past the limit.
+/// The attempt reaching `max_attempts` locks the PIN for `lockout_secs`, doubled with every further one
+/// up to `max_lockout_secs`. Returns false if the PIN is locked already or there is none.
+pub async fn claim_pin_attempt(
+    db: &SqlitePool,
+    user_id: i32,
+    max_attempts: i32,
+    lockout_secs: i64,
+    max_lockout_secs: i64,
+) -> std::result::Result<bool, DbError> {
+    let result = sqlx::query(
+        "UPDATE user_pin
+        SET failed_attempts = failed_attempts + 1,
+            locked_until = CASE WHEN failed_attempts + 1 < ? THEN locked_until
+                ELSE datetime('now', 'localtime',
+                    '+' || MIN(? << MIN(failed_attempts + 1 - ?, 16), ?) || ' seconds') END
+        WHERE user_id = ? AND (locked_until IS NULL OR locked_until <= datetime('now', 'localtime'))",
+    )
+    .bind(max_attempts)
+    .bind(lockout_secs)
+    .bind(max_attempts)
+    .bind(max_lockout_secs)
+    .bind(user_id)
+    .execute(db)
+    .await?;
+
+    return Ok(result.rows_affected() > 0);
+}
+
+pub async fn reset_pin_failures(
+    db: &SqlitePool,
+    user_id: i32,
+) -> std::result::Result<(), DbError> {
+    sqlx::query("UPDATE user_pin SET failed_attempts = 0, locked_until = NULL WHERE user_id = ?")
+        .bind(user_id)
+        .execute(db)
+        .await?;
+
+    return Ok(());
+}
+
+/// Random salt for a new PIN, as hex
+pub async fn new_salt_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+) -> std::result::Result<String, DbError> {
+    let salt: String = sqlx::query_scalar("SELECT lower(hex(randomblob(16)))")
+        .fetch_one(tx)
+        .await?;
+
+    return Ok(salt);
+}
+
+/// Replaces the PIN of the user, or removes it if `pin` is None.
+pub async fn set_pin_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: i32,
+    pin: Option<(&str, &str)>,
+) -> std::result::Result<(), DbError> {
+    sqlx::query("DELETE FROM user_pin WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    if let Some((salt, hash)) = pin {
+        sqlx::query(
+            "INSERT INTO user_pin (user_id, salt, hash, created)
+            VALUES (?, ?, ?, datetime('now', 'localtime'))",
+        )
+        .bind(user_id)
+        .bind(salt)
+        .bind(hash)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    return Ok(());
+}
+
+pub async fn get_tokens(
+    db: &SqlitePool,
+    user_id: i32,
+) -> std::result::Result<Vec<model::UserTokenEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let tokens = sqlx::query_as::<_, model::UserTokenEntity>(
+        "SELECT id, user_id, uid, created FROM user_token
+        WHERE user_id = ?
+        ORDER BY created, id",
+    )
+    .bind(user_id)
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(tokens);
+}
+
+pub async fn get_token_by_uid_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    uid: &str,
+) -> std::result::Result<Option<model::UserTokenEntity>, DbError> {
+    let token = sqlx::query_as::<_, model::UserTokenEntity>(
+        "SELECT id, user_id, uid, created FROM user_token WHERE uid = ?",
+    )
+    .bind(uid)
+    .fetch_optional(tx)
+    .await?;
+
+    return Ok(token);
+}
+
+pub async fn add_token_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: i32,
+    uid: &str,
+) -> std::result::Result<model::UserTokenEntity, DbError> {
+    if get_token_by_uid_tx(tx, uid).await?.is_some() {
+        return Err(DbError::DuplicateKey("User token".to_string()));
+    }
+    let token = sqlx::query_as::<_, model::UserTokenEntity>(
+        "INSERT INTO user_token (user_id, uid, created)
+        VALUES (?, ?, datetime('now', 'localtime'));
+
+        SELECT id, user_id, uid, created FROM user_token WHERE id = last_insert_rowid();",
+    )
+    .bind(user_id)
+    .bind(uid)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(token);
+}
+
+pub async fn delete_token_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: i32,
+    token_id: i32,
+) -> std::result::Result<model::UserTokenEntity, DbError> {
+    let token = sqlx::query_as::<_, model::UserTokenEntity>(
+        "SELECT id, user_id, uid, created FROM user_token WHERE id = ? AND user_id = ?;",
+    )
+    .bind(token_id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| DbError::EntityNotFound("User token".to_string()))?;
+    sqlx::query("DELETE FROM user_token WHERE id = ?")
+        .bind(token_id)
+        .execute(&mut *tx)
+        .await?;
+
+    return Ok(token);
+}
@@ -6,6 +6,9 @@ use sqlx::{
     Row,
 };
 
+/// Schema version reached after running all migrations
+pub const DB_VERSION: i32 = 20;
+
 pub async fn open_db(db_file: &str) -> Result<SqlitePool, sqlx::Error> {
     let opts = SqliteConnectOptions::new()
         .filename(db_file)
@@ -106,5 +109,522 @@ pub async fn migrate_db(db: &SqlitePool) -> Result<(), sqlx::Error> {
         tx.commit().await?;
     }
 
+    if cur_version == 1 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE webhook_outbox (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        url VARCHAR(2048) NOT NULL,
+                        event VARCHAR(64) NOT NULL,
+                        payload TEXT NOT NULL,
+                        attempts INTEGER NOT NULL,
+                        next_attempt DATETIME NOT NULL,
+                        last_error TEXT DEFAULT NULL,
+                        failed BOOLEAN NOT NULL,
+                        created DATETIME NOT NULL
+                    );
+                    CREATE INDEX idx_webhook_outbox_failed_next ON webhook_outbox (failed, next_attempt);
+
+                    PRAGMA user_version = 2;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 2 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE email_statement (
+                        user_id INTEGER NOT NULL,
+                        month VARCHAR(7) NOT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT pk_email_statement PRIMARY KEY (user_id, month),
+                        CONSTRAINT fk_email_statement_user_id FOREIGN KEY (user_id) REFERENCES user (id)
+                    );
+
+                    PRAGMA user_version = 3;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 3 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE transactions ADD COLUMN idempotency_key VARCHAR(255) DEFAULT NULL;
+                    CREATE INDEX idx_transactions_user_idempotency_key ON transactions (user_id, idempotency_key);
+
+                    PRAGMA user_version = 4;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 4 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE category (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        name VARCHAR(64) NOT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT uniq_category_name UNIQUE (name)
+                    );
+
+                    CREATE TABLE article_category (
+                        article_id INTEGER NOT NULL,
+                        category_id INTEGER NOT NULL,
+                        CONSTRAINT pk_article_category PRIMARY KEY (article_id, category_id),
+                        CONSTRAINT fk_article_category_article_id FOREIGN KEY (article_id) REFERENCES article (id),
+                        CONSTRAINT fk_article_category_category_id FOREIGN KEY (category_id) REFERENCES category (id)
+                    );
+                    CREATE INDEX idx_article_category_category_id ON article_category (category_id);
+
+                    PRAGMA user_version = 5;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 5 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE article ADD COLUMN deposit INTEGER NOT NULL DEFAULT 0;
+                    ALTER TABLE transactions ADD COLUMN deposit BOOLEAN NOT NULL DEFAULT FALSE;
+                    ALTER TABLE transactions ADD COLUMN deposit_transaction_id INTEGER DEFAULT NULL
+                        CONSTRAINT fk_transaction_deposit_id REFERENCES transactions (id);
+                    CREATE INDEX idx_transaction_deposit ON transactions (deposit);
+
+                    PRAGMA user_version = 6;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 6 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE article_price_change (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        article_id INTEGER NOT NULL,
+                        amount INTEGER NOT NULL,
+                        activates DATETIME NOT NULL,
+                        state VARCHAR(16) NOT NULL,
+                        applied_article_id INTEGER DEFAULT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT fk_price_change_article_id FOREIGN KEY (article_id) REFERENCES article (id),
+                        CONSTRAINT fk_price_change_applied_article_id FOREIGN KEY (applied_article_id) REFERENCES article (id)
+                    );
+                    CREATE INDEX idx_price_change_state_activates ON article_price_change (state, activates);
+
+                    PRAGMA user_version = 7;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 7 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE scheduled_transaction (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        user_id INTEGER NOT NULL,
+                        amount INTEGER NOT NULL,
+                        comment VARCHAR(255) DEFAULT NULL,
+                        interval VARCHAR(16) NOT NULL,
+                        next_run DATETIME NOT NULL,
+                        active BOOLEAN NOT NULL,
+                        skipped INTEGER NOT NULL,
+                        last_error TEXT DEFAULT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT fk_scheduled_transaction_user_id FOREIGN KEY (user_id) REFERENCES user (id)
+                    );
+                    CREATE INDEX idx_scheduled_transaction_active_next_run ON scheduled_transaction (active, next_run);
+
+                    PRAGMA user_version = 8;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 8 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE pending_transaction (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        user_id INTEGER NOT NULL,
+                        article_id INTEGER DEFAULT NULL,
+                        quantity INTEGER DEFAULT NULL,
+                        comment VARCHAR(255) DEFAULT NULL,
+                        amount INTEGER NOT NULL,
+                        state VARCHAR(16) NOT NULL,
+                        transaction_id INTEGER DEFAULT NULL,
+                        created DATETIME NOT NULL,
+                        decided DATETIME DEFAULT NULL,
+                        CONSTRAINT fk_pending_transaction_user_id FOREIGN KEY (user_id) REFERENCES user (id),
+                        CONSTRAINT fk_pending_transaction_article_id FOREIGN KEY (article_id) REFERENCES article (id),
+                        CONSTRAINT fk_pending_transaction_transaction_id FOREIGN KEY (transaction_id) REFERENCES transactions (id)
+                    );
+                    CREATE INDEX idx_pending_transaction_state ON pending_transaction (state, created);
+
+                    PRAGMA user_version = 9;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 9 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE user_pin (
+                        user_id INTEGER PRIMARY KEY NOT NULL,
+                        salt VARCHAR(32) NOT NULL,
+                        hash VARCHAR(64) NOT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT fk_user_pin_user_id FOREIGN KEY (user_id) REFERENCES user (id)
+                    );
+                    CREATE TABLE user_token (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        user_id INTEGER NOT NULL,
+                        uid VARCHAR(64) NOT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT fk_user_token_user_id FOREIGN KEY (user_id) REFERENCES user (id),
+                        CONSTRAINT uniq_user_token_uid UNIQUE (uid)
+                    );
+                    CREATE INDEX idx_user_token_user_id ON user_token (user_id);
+
+                    PRAGMA user_version = 10;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 10 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE api_token (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        name VARCHAR(255) NOT NULL,
+                        hash VARCHAR(64) NOT NULL,
+                        scope VARCHAR(16) NOT NULL,
+                        expires DATETIME DEFAULT NULL,
+                        last_used DATETIME DEFAULT NULL,
+                        revoked BOOLEAN NOT NULL DEFAULT FALSE,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT uniq_api_token_hash UNIQUE (hash)
+                    );
+
+                    PRAGMA user_version = 11;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 11 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE user ADD COLUMN display_name VARCHAR(255) DEFAULT NULL;
+                    ALTER TABLE user ADD COLUMN notes TEXT DEFAULT NULL;
+                    CREATE TABLE user_avatar (
+                        user_id INTEGER PRIMARY KEY NOT NULL,
+                        content_type VARCHAR(32) NOT NULL,
+                        data BLOB NOT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT fk_user_avatar_user_id FOREIGN KEY (user_id) REFERENCES user (id)
+                    );
+
+                    PRAGMA user_version = 12;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 12 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        // hooks were identified by their URL before
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE webhook_outbox ADD COLUMN hook VARCHAR(2048) NOT NULL DEFAULT '';
+                    UPDATE webhook_outbox SET hook = url;
+
+                    PRAGMA user_version = 13;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 13 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    CREATE TABLE email_reminder (
+                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                        user_id INTEGER NOT NULL,
+                        balance INTEGER NOT NULL,
+                        amount INTEGER NOT NULL,
+                        created DATETIME NOT NULL,
+                        CONSTRAINT fk_email_reminder_user_id FOREIGN KEY (user_id) REFERENCES user (id)
+                    );
+
+                    PRAGMA user_version = 14;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 14 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        // concurrent retries could store a key twice before, the latest booking keeps it
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    UPDATE transactions SET idempotency_key = NULL
+                    WHERE idempotency_key IS NOT NULL AND id NOT IN (
+                        SELECT MAX(id) FROM transactions WHERE idempotency_key IS NOT NULL
+                        GROUP BY user_id, idempotency_key);
+                    DROP INDEX idx_transactions_user_idempotency_key;
+                    CREATE UNIQUE INDEX idx_transactions_user_idempotency_key ON transactions (user_id, idempotency_key)
+                        WHERE idempotency_key IS NOT NULL;
+
+                    PRAGMA user_version = 15;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 15 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE transactions ADD COLUMN cart_transaction_id INTEGER DEFAULT NULL;
+
+                    PRAGMA user_version = 16;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 16 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE scheduled_transaction ADD COLUMN first_run DATETIME DEFAULT NULL;
+                    UPDATE scheduled_transaction SET first_run = next_run;
+
+                    PRAGMA user_version = 17;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 17 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE pending_transaction ADD COLUMN recipient_id INTEGER DEFAULT NULL;
+                    ALTER TABLE pending_transaction ADD COLUMN return_deposit BOOLEAN NOT NULL DEFAULT FALSE;
+                    ALTER TABLE pending_transaction ADD COLUMN cart_pending_id INTEGER DEFAULT NULL;
+                    ALTER TABLE pending_transaction ADD COLUMN idempotency_key VARCHAR(255) DEFAULT NULL;
+                    CREATE UNIQUE INDEX idx_pending_transaction_user_idempotency_key ON pending_transaction (user_id, idempotency_key)
+                        WHERE idempotency_key IS NOT NULL;
+
+                    PRAGMA user_version = 18;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 18 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE user_pin ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+                    ALTER TABLE user_pin ADD COLUMN locked_until DATETIME DEFAULT NULL;
+
+                    PRAGMA user_version = 19;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    if cur_version == 19 {
+        cur_version += 1;
+        info!("Running migration #{}", cur_version);
+
+        let mut tx = db.begin().await?;
+        sqlx::query("
+                    ALTER TABLE email_reminder ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+                    ALTER TABLE email_reminder ADD COLUMN next_attempt DATETIME DEFAULT NULL;
+                    ALTER TABLE email_reminder ADD COLUMN last_error TEXT DEFAULT NULL;
+                    ALTER TABLE email_reminder ADD COLUMN failed BOOLEAN NOT NULL DEFAULT FALSE;
+                    UPDATE email_reminder SET next_attempt = created;
+
+                    PRAGMA user_version = 20;")
+                    .execute(&mut tx).await?;
+        tx.commit().await?;
+    }
+
+    return Ok(());
+}
+
+/// Runs the consistency checks of SQLite and the bookkeeping and returns all problems found.
+pub async fn check_db(db: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let mut problems = Vec::new();
+
+    let version: i32 = sqlx::query("PRAGMA user_version;")
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_one(db)
+        .await?;
+    if version != DB_VERSION {
+        problems.push(format!(
+            "Schema version is {}, expected {}.",
+            version, DB_VERSION
+        ));
+    }
+
+    let integrity: Vec<String> = sqlx::query("PRAGMA integrity_check;")
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_all(db)
+        .await?;
+    problems.extend(
+        integrity
+            .into_iter()
+            .filter(|v| v != "ok")
+            .map(|v| format!("Integrity check: {}", v)),
+    );
+
+    let foreign_keys: Vec<(String, i64)> = sqlx::query("PRAGMA foreign_key_check;")
+        .map(|row: SqliteRow| (row.get(0), row.get(1)))
+        .fetch_all(db)
+        .await?;
+    problems.extend(
+        foreign_keys.into_iter().map(|(table, rowid)| {
+            format!("Foreign key violated in table {} row {}.", table, rowid)
+        }),
+    );
+
+    let balances: Vec<(i32, i32, i32)> = sqlx::query_as(
+        "SELECT u.id, u.balance, COALESCE(SUM(t.amount), 0)
+        FROM user AS u
+        LEFT JOIN transactions AS t ON t.user_id = u.id AND t.deleted IS FALSE
+        GROUP BY u.id
+        HAVING u.balance != COALESCE(SUM(t.amount), 0)",
+    )
+    .fetch_all(db)
+    .await?;
+    problems.extend(balances.into_iter().map(|(user_id, balance, sum)| {
+        format!(
+            "Balance of user {} is {}, but its transactions sum up to {}.",
+            user_id, balance, sum
+        )
+    }));
+
+    return Ok(problems);
+}
+
+/// Writes a consistent copy of the database to `target` while the database stays online.
+pub async fn backup_db(db: &SqlitePool, target: &str) -> Result<(), sqlx::Error> {
+    info!("Writing database backup to {} ...", target);
+    sqlx::query("VACUUM INTO ?;")
+        .bind(target)
+        .execute(db)
+        .await?;
+    return Ok(());
+}
+
+/// Replaces the database file with the backup `source` after validating it.
+/// All connections to `db_file` must be closed, the replaced file is kept with a `.pre-restore` suffix.
+pub async fn restore_db(db_file: &str, source: &str) -> Result<(), sqlx::Error> {
+    let is_file = tokio::fs::metadata(source).await.map(|v| v.is_file());
+    if !is_file.unwrap_or(false) {
+        return Err(sqlx::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Backup {} does not exist.", source),
+        )));
+    }
+    // the copy of an earlier restore may be the only one left of that database
+    let pre_restore = format!("{}.pre-restore", db_file);
+    if tokio::fs::metadata(&pre_restore).await.is_ok() {
+        return Err(sqlx::Error::Configuration(
+            format!("{} is left from an earlier restore, move it away first.", pre_restore).into(),
+        ));
+    }
+
+    let opts = SqliteConnectOptions::new().filename(source);
+    let backup = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(opts)
+        .await?;
+
+    let version: i32 = sqlx::query("PRAGMA user_version;")
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_one(&backup)
+        .await?;
+    let integrity: String = sqlx::query("PRAGMA integrity_check;")
+        .map(|row: SqliteRow| row.get(0))
+        .fetch_one(&backup)
+        .await?;
+    let problem = match (version, integrity.as_str()) {
+        (v, _) if v < 1 || v > DB_VERSION => Some(format!(
+            "Backup has schema version {}, supported are 1 to {}.",
+            v, DB_VERSION
+        )),
+        (_, "ok") => None,
+        (_, e) => Some(format!("Backup failed the integrity check: {}", e)),
+    };
+    if let Some(problem) = problem {
+        backup.close().await;
+        return Err(sqlx::Error::Configuration(problem.into()));
+    }
+
+    // write a clean copy next to the target first, so the final step is an atomic rename
+    let restore_file = format!("{}.restore", db_file);
+    let _ = tokio::fs::remove_file(&restore_file).await;
+    sqlx::query("VACUUM INTO ?;")
+        .bind(&restore_file)
+        .execute(&backup)
+        .await?;
+    backup.close().await;
+
+    // changes not yet checkpointed are in the write-ahead log, so it moves along with the file
+    for suffix in ["", "-wal", "-shm"].iter() {
+        let file = format!("{}{}", db_file, suffix);
+        if tokio::fs::metadata(&file).await.is_ok() {
+            tokio::fs::rename(&file, format!("{}{}", pre_restore, suffix)).await?;
+        }
+    }
+    tokio::fs::rename(&restore_file, db_file).await?;
+
+    info!(
+        "Restored database {} from {} with schema version {}.",
+        db_file, source, version
+    );
     return Ok(());
 }
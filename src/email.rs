@@ -0,0 +1,482 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc, time::Duration};
+
+use chrono::{Datelike, Local, NaiveDate};
+use lettre::{
+    smtp::authentication::Credentials, ClientSecurity, ClientTlsParameters, SmtpClient, Transport,
+};
+use lettre_email::EmailBuilder;
+use log::{error, info, warn};
+use native_tls::TlsConnector;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use tokio::sync::broadcast::{self, RecvError};
+
+use crate::{
+    email_db,
+    error::DbError,
+    events::Event,
+    metrics_db,
+    model::{TransactionObject, UserEntity},
+    settings::{EmailSetting, StrichlisteSetting},
+    transaction_db, user_db,
+};
+
+/// Period to check whether statements are due
+const STATEMENT_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+/// Period to look for due retries of reminders
+const REMINDER_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Delay of the first retry of a reminder, doubled with every further attempt
+const REMINDER_RETRY_DELAY: Duration = Duration::from_secs(60);
+/// Upper bound of the exponential backoff
+const MAX_REMINDER_RETRY_DELAY: Duration = Duration::from_secs(24 * 3600);
+/// Attempts after which a reminder is given up, it stays in the table with the last error
+const REMINDER_MAX_ATTEMPTS: i32 = 10;
+
+const REMINDER_TEMPLATE: &str = "reminder";
+const STATEMENT_TEMPLATE: &str = "statement";
+const DEFAULT_LANGUAGE: &str = "en";
+
+/// Built-in templates by language and name, the first line is the subject
+const TEMPLATES: &[(&str, &str, &str)] = &[
+    (
+        "en",
+        REMINDER_TEMPLATE,
+        "Your strichliste balance is {{balance}}
+
+Hello {{name}},
+
+your balance dropped to {{balance}} with your last booking of {{amount}}.
+Please charge your account soon.
+",
+    ),
+    (
+        "en",
+        STATEMENT_TEMPLATE,
+        "Your strichliste statement for {{month}}
+
+Hello {{name}},
+
+these are your bookings in {{month}}:
+
+{{transactions}}
+
+Charged: {{charged}}
+Spent: {{spent}}
+Balance at the end of the month: {{balance}}
+
+Articles:
+{{articles}}
+",
+    ),
+    (
+        "de",
+        REMINDER_TEMPLATE,
+        "Dein Strichliste-Kontostand beträgt {{balance}}
+
+Hallo {{name}},
+
+dein Kontostand ist mit deiner letzten Buchung über {{amount}} auf {{balance}} gefallen.
+Bitte lade dein Konto bald wieder auf.
+",
+    ),
+    (
+        "de",
+        STATEMENT_TEMPLATE,
+        "Dein Strichliste-Kontoauszug für {{month}}
+
+Hallo {{name}},
+
+das sind deine Buchungen im {{month}}:
+
+{{transactions}}
+
+Eingezahlt: {{charged}}
+Ausgegeben: {{spent}}
+Kontostand am Monatsende: {{balance}}
+
+Artikel:
+{{articles}}
+",
+    ),
+];
+
+/// Renders the templates and sends them via SMTP.
+pub struct Mailer {
+    settings: EmailSetting,
+    strichliste: Arc<StrichlisteSetting>,
+    /// templates by name, read once on startup
+    templates: HashMap<&'static str, String>,
+}
+
+impl Mailer {
+    pub fn new(settings: EmailSetting, strichliste: Arc<StrichlisteSetting>) -> Mailer {
+        let language = &strichliste.i18n.language;
+        let templates = [REMINDER_TEMPLATE, STATEMENT_TEMPLATE]
+            .iter()
+            .map(|name| {
+                let template = read_template(&settings.templates, language, name)
+                    .unwrap_or_else(|| builtin_template(language, name));
+                (*name, template)
+            })
+            .collect();
+        Mailer {
+            settings,
+            strichliste,
+            templates,
+        }
+    }
+
+    fn language(&self) -> &str {
+        &self.strichliste.i18n.language
+    }
+
+    /// Returns the subject and body of the template with the placeholders replaced.
+    fn render(&self, name: &str, values: &[(&str, String)]) -> (String, String) {
+        let template = self.templates.get(name).cloned().unwrap_or_default();
+        let text = values.iter().fold(template, |text, (key, value)| {
+            text.replace(&format!("{{{{{}}}}}", key), value)
+        });
+        let mut lines = text.splitn(2, '\n');
+        let subject = lines.next().unwrap_or("").trim().to_string();
+        let body = lines
+            .next()
+            .unwrap_or("")
+            .trim_start_matches('\n')
+            .to_string();
+        return (subject, body);
+    }
+
+    fn format_amount(&self, cents: i32) -> String {
+        let separator = match self.language() {
+            "de" => ",",
+            _ => ".",
+        };
+        let sign = if cents < 0 { "-" } else { "" };
+        let abs = (cents as i64).abs();
+        format!(
+            "{}{}{}{:02} {}",
+            sign,
+            abs / 100,
+            separator,
+            abs % 100,
+            self.strichliste.i18n.currency.symbol
+        )
+    }
+
+    async fn send(&self, user: &UserEntity, mail: (String, String)) -> Result<(), String> {
+        let to = match user.email.as_deref().filter(|v| !v.is_empty()) {
+            Some(v) => v.to_string(),
+            None => return Ok(()),
+        };
+        let (subject, body) = mail;
+        let email = EmailBuilder::new()
+            .to((to.clone(), user.name.clone()))
+            .from((
+                self.settings.sender.clone(),
+                self.settings.sender_name.clone(),
+            ))
+            .subject(subject)
+            .text(body)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        // the SMTP client of lettre is blocking
+        let settings = self.settings.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut transport = smtp_client(&settings)?.transport();
+            transport
+                .send(email.into())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())??;
+
+        info!("Sent email to {}", to);
+        return Ok(());
+    }
+}
+
+/// Reads a custom template, None if there is no template directory or the file can't be read.
+fn read_template(directory: &str, language: &str, name: &str) -> Option<String> {
+    if directory.is_empty() {
+        return None;
+    }
+    let file = Path::new(directory)
+        .join(language)
+        .join(format!("{}.txt", name));
+    fs::read_to_string(&file)
+        .map_err(|e| error!("Reading template {} failed: {}", file.display(), e))
+        .ok()
+}
+
+fn builtin_template(language: &str, name: &str) -> String {
+    let find = |lang: &str| {
+        TEMPLATES
+            .iter()
+            .find(|(l, n, _)| *l == lang && *n == name)
+            .map(|(_, _, t)| t.to_string())
+    };
+    find(language)
+        .or_else(|| find(DEFAULT_LANGUAGE))
+        .unwrap_or_default()
+}
+
+fn smtp_client(settings: &EmailSetting) -> Result<SmtpClient, String> {
+    let tls = || -> Result<ClientTlsParameters, String> {
+        let connector = TlsConnector::new().map_err(|e| e.to_string())?;
+        Ok(ClientTlsParameters::new(settings.host.clone(), connector))
+    };
+    let security = match settings.security.as_str() {
+        "none" => ClientSecurity::None,
+        "tls" => ClientSecurity::Wrapper(tls()?),
+        _ => ClientSecurity::Required(tls()?),
+    };
+
+    let mut client = SmtpClient::new((settings.host.as_str(), settings.port), security)
+        .map_err(|e| e.to_string())?;
+    if !settings.username.is_empty() {
+        client = client.credentials(Credentials::new(
+            settings.username.clone(),
+            settings.password.clone(),
+        ));
+    }
+    return Ok(client);
+}
+
+/// Queues a reminder if the booking took the balance of a user with an email address below `threshold`,
+/// within the transaction of the booking.
+pub async fn queue_reminder_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    threshold: i32,
+    event: &Event,
+) -> Result<(), DbError> {
+    let transaction = match event.balance_dropped_below(threshold) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    if transaction
+        .user
+        .email
+        .as_deref()
+        .map_or(true, str::is_empty)
+    {
+        return Ok(());
+    }
+    email_db::add_reminder_tx(
+        tx,
+        &transaction.user.id,
+        &transaction.user.balance,
+        &transaction.entity.amount,
+    )
+    .await
+}
+
+/// Sends the reminders queued with the bookings, right after each published event.
+/// Reminders left over from a previous run are sent right away, failed ones are retried with a backoff.
+pub async fn run_reminders(
+    db: SqlitePool,
+    mut receiver: broadcast::Receiver<Event>,
+    mailer: Arc<Mailer>,
+) {
+    loop {
+        if let Err(e) = send_reminders(&db, &mailer).await {
+            error!("Sending reminders failed: {}", e);
+        }
+        // the reminders are in the database already, missed events only delay them until the next poll
+        match tokio::time::timeout(REMINDER_POLL_INTERVAL, receiver.recv()).await {
+            Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) | Err(_) => (),
+            Ok(Err(RecvError::Closed)) => return,
+        };
+    }
+}
+
+async fn send_reminders(db: &SqlitePool, mailer: &Mailer) -> Result<(), DbError> {
+    for reminder in email_db::get_due_reminders(db).await? {
+        let user = match user_db::get_user(db, &mailer.strichliste, &reminder.user_id).await? {
+            Some(v) => v,
+            None => {
+                email_db::remove_reminder(db, reminder.id).await?;
+                continue;
+            }
+        };
+        let mail = mailer.render(
+            REMINDER_TEMPLATE,
+            &[
+                ("name", user.name.clone()),
+                ("balance", mailer.format_amount(reminder.balance)),
+                ("amount", mailer.format_amount(reminder.amount)),
+            ],
+        );
+        match mailer.send(&user, mail).await {
+            Ok(_) => email_db::remove_reminder(db, reminder.id).await?,
+            Err(e) => {
+                let next_attempt = next_reminder_attempt(reminder.attempts);
+                match next_attempt {
+                    Some(_) => warn!("Sending reminder to user {} failed: {}", user.id, e),
+                    None => error!("Giving up sending reminder to user {}: {}", user.id, e),
+                };
+                email_db::reschedule_reminder(db, reminder.id, &e, next_attempt.as_deref()).await?;
+            }
+        };
+    }
+
+    return Ok(());
+}
+
+/// Time of the next attempt after `attempts` failed ones, None if the reminder is given up.
+fn next_reminder_attempt(attempts: i32) -> Option<String> {
+    if attempts + 1 >= REMINDER_MAX_ATTEMPTS {
+        return None;
+    }
+    let delay = REMINDER_RETRY_DELAY
+        .checked_mul(2u32.saturating_pow(attempts as u32))
+        .unwrap_or(MAX_REMINDER_RETRY_DELAY)
+        .min(MAX_REMINDER_RETRY_DELAY);
+    let next = Local::now() + chrono::Duration::from_std(delay).ok()?;
+    return Some(next.naive_local().format("%Y-%m-%d %H:%M:%S").to_string());
+}
+
+/// Sends the statements of the previous month once the configured day is reached.
+pub async fn run_statements(db: SqlitePool, mailer: Arc<Mailer>) {
+    let mut interval = tokio::time::interval(STATEMENT_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let today = Local::today().naive_local();
+        if today.day() < mailer.settings.statement.day {
+            continue;
+        }
+        if let Err(e) = send_statements(&db, &mailer, today).await {
+            error!("Sending statements failed: {}", e);
+        }
+    }
+}
+
+async fn send_statements(
+    db: &SqlitePool,
+    mailer: &Mailer,
+    today: NaiveDate,
+) -> Result<(), DbError> {
+    let until = today.with_day(1).unwrap_or(today);
+    let from = until.pred().with_day(1).unwrap_or(until);
+    let month = from.format("%Y-%m").to_string();
+    let (from, until) = (
+        from.format("%Y-%m-%d 00:00:00").to_string(),
+        until.format("%Y-%m-%d 00:00:00").to_string(),
+    );
+
+    let users = user_db::get_users(db, mailer.strichliste.clone(), false, None).await?;
+    for user in users
+        .iter()
+        .filter(|u| u.email.as_deref().map_or(false, |v| !v.is_empty()))
+    {
+        if email_db::statement_sent(db, &user.id, &month).await? {
+            continue;
+        }
+        // users without bookings in that month get none, but are marked as done as well
+        if let Some(mail) = build_statement(db, mailer, user, &month, &from, &until).await? {
+            if let Err(e) = mailer.send(user, mail).await {
+                // retried with the next check
+                error!("Sending statement to user {} failed: {}", user.id, e);
+                continue;
+            }
+        }
+        email_db::mark_statement_sent(db, &user.id, &month).await?;
+    }
+
+    return Ok(());
+}
+
+async fn build_statement(
+    db: &SqlitePool,
+    mailer: &Mailer,
+    user: &UserEntity,
+    month: &str,
+    from: &str,
+    until: &str,
+) -> Result<Option<(String, String)>, DbError> {
+    let mut tx = db.begin().await?;
+    let transactions = transaction_db::get_transactions_between_tx(
+        &mut tx,
+        &mailer.strichliste,
+        &user.id,
+        from,
+        until,
+    )
+    .await?;
+    if transactions.is_empty() {
+        return Ok(None);
+    }
+    let articles = metrics_db::user_article_stats_between(&mut tx, &user.id, from, until).await?;
+    let balance = transaction_db::balance_before_tx(&mut tx, &user.id, until).await?;
+    tx.commit().await?;
+
+    let charged: i32 = transactions
+        .iter()
+        .map(|t| t.entity.amount)
+        .filter(|v| *v > 0)
+        .sum();
+    let spent: i32 = transactions
+        .iter()
+        .map(|t| t.entity.amount)
+        .filter(|v| *v < 0)
+        .sum();
+    let transaction_lines: Vec<String> = transactions
+        .iter()
+        .map(|t| {
+            format!(
+                "{}  {:>12}  {}",
+                t.entity.created,
+                mailer.format_amount(t.entity.amount),
+                describe(t)
+            )
+        })
+        .collect();
+    let article_lines: Vec<String> = articles
+        .iter()
+        .map(|a| {
+            format!(
+                "{:>4} x {}  {}",
+                a.count,
+                a.article.entity.name,
+                mailer.format_amount(a.amount)
+            )
+        })
+        .collect();
+
+    return Ok(Some(mailer.render(
+        STATEMENT_TEMPLATE,
+        &[
+            ("name", user.name.clone()),
+            ("month", month.to_string()),
+            ("transactions", transaction_lines.join("\n")),
+            ("articles", article_lines.join("\n")),
+            ("charged", mailer.format_amount(charged)),
+            ("spent", mailer.format_amount(-spent)),
+            ("balance", mailer.format_amount(balance)),
+        ],
+    )));
+}
+
+fn describe(transaction: &TransactionObject) -> String {
+    let mut parts = vec![];
+    if transaction.entity.deposit {
+        parts.push("Deposit".to_string());
+    }
+    if let Some(article) = &transaction.article {
+        parts.push(format!(
+            "{} x{}",
+            article.entity.name,
+            transaction.entity.quantity.unwrap_or(1)
+        ));
+    }
+    if let Some(recipient) = &transaction.recipient {
+        parts.push(format!("→ {}", recipient.name));
+    }
+    if let Some(sender) = &transaction.sender {
+        parts.push(format!("← {}", sender.name));
+    }
+    if let Some(comment) = &transaction.entity.comment {
+        parts.push(comment.clone());
+    }
+    parts.join(", ")
+}
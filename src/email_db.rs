@@ -0,0 +1,109 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::{error::DbError, model};
+
+/// Whether the statement of `month` (`YYYY-MM`) was sent to the user already.
+pub async fn statement_sent(
+    db: &SqlitePool,
+    user_id: &i32,
+    month: &str,
+) -> std::result::Result<bool, DbError> {
+    let mut tx = db.begin().await?;
+    let count = sqlx::query_scalar::<_, i32>(
+        "SELECT COUNT(*) FROM email_statement WHERE user_id = ? AND month = ?",
+    )
+    .bind(user_id)
+    .bind(month)
+    .fetch_one(&mut tx)
+    .await?;
+
+    return Ok(count > 0);
+}
+
+pub async fn mark_statement_sent(
+    db: &SqlitePool,
+    user_id: &i32,
+    month: &str,
+) -> std::result::Result<(), DbError> {
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "INSERT INTO email_statement (user_id, month, created)
+        VALUES (?, ?, datetime('now', 'localtime'))",
+    )
+    .bind(user_id)
+    .bind(month)
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(());
+}
+
+pub async fn add_reminder_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    balance: &i32,
+    amount: &i32,
+) -> std::result::Result<(), DbError> {
+    sqlx::query(
+        "INSERT INTO email_reminder (user_id, balance, amount, attempts, next_attempt, failed, created)
+        VALUES (?, ?, ?, 0, datetime('now', 'localtime'), FALSE, datetime('now', 'localtime'))",
+    )
+    .bind(user_id)
+    .bind(balance)
+    .bind(amount)
+    .execute(tx)
+    .await?;
+
+    return Ok(());
+}
+
+/// Returns the reminders which are due, oldest first.
+pub async fn get_due_reminders(
+    db: &SqlitePool,
+) -> std::result::Result<Vec<model::EmailReminderEntity>, DbError> {
+    let reminders = sqlx::query_as::<_, model::EmailReminderEntity>(
+        "SELECT id, user_id, balance, amount, attempts FROM email_reminder
+        WHERE failed IS FALSE AND next_attempt <= datetime('now', 'localtime')
+        ORDER BY id",
+    )
+    .fetch_all(db)
+    .await?;
+
+    return Ok(reminders);
+}
+
+pub async fn remove_reminder(db: &SqlitePool, id: i32) -> std::result::Result<(), DbError> {
+    let mut tx = db.begin().await?;
+    sqlx::query("DELETE FROM email_reminder WHERE id = ?")
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    return Ok(());
+}
+
+/// Records a failed attempt, the reminder is given up if there is no `next_attempt`.
+pub async fn reschedule_reminder(
+    db: &SqlitePool,
+    id: i32,
+    error: &str,
+    next_attempt: Option<&str>,
+) -> std::result::Result<(), DbError> {
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "UPDATE email_reminder
+        SET attempts = attempts + 1, last_error = ?, failed = ?, next_attempt = COALESCE(?, next_attempt)
+        WHERE id = ?",
+    )
+    .bind(error)
+    .bind(next_attempt.is_none())
+    .bind(next_attempt)
+    .bind(id)
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(());
+}
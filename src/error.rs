@@ -19,6 +19,12 @@ pub enum DbError {
 pub enum ClientError {
 	ParameterInvalid(String),
 	ParameterMissing(String),
+	/// missing or invalid API or admin token
+	Unauthorized(String),
+	/// e.g. a wrong or missing PIN
+	Forbidden(String),
+	/// seconds until the next request is allowed
+	TooManyRequests(u64),
 }
 
 
@@ -42,6 +48,9 @@ impl Display for ClientError {
         match self {
 			ClientError::ParameterInvalid(s) => write!(f, "Parameter value invalid: {}", s),
 			ClientError::ParameterMissing(s) => write!(f, "Parameter {} is missing in the request", s),
+			ClientError::Unauthorized(s) => write!(f, "Unauthorized: {}", s),
+			ClientError::Forbidden(s) => write!(f, "Forbidden: {}", s),
+			ClientError::TooManyRequests(s) => write!(f, "Too many requests, retry in {} s", s),
 		}
     }
 }
@@ -90,9 +99,15 @@ impl From<sqlx::Error> for DbError {
 	}
 }
 
-pub async fn handle_my_error(r: warp::Rejection) -> Result<impl Reply, warp::Rejection> {
+impl From<std::io::Error> for DbError {
+	fn from(e: std::io::Error) -> Self {
+		DbError::UnknownError(sqlx::Error::Io(e))
+	}
+}
+
+pub async fn handle_my_error(request_id: &str, r: warp::Rejection) -> Result<Box<dyn Reply>, warp::Rejection> {
 	if let Some(e) = r.find::<DbError>() {
-		info!("Request failed: {}", e);
+		info!("Request {} failed: {}", request_id, e);
 		return match e {
 			DbError::BoundaryViolation(_) => Ok(Box::new(warp::http::StatusCode::NOT_FOUND)),
 			DbError::DuplicateKey(_) => Ok(Box::new(warp::http::StatusCode::CONFLICT)),
@@ -104,13 +119,27 @@ pub async fn handle_my_error(r: warp::Rejection) -> Result<impl Reply, warp::Rej
 			// _ => Ok(Box::new(warp::http::StatusCode::INTERNAL_SERVER_ERROR)),
 		};
 	} else if let Some(e) = r.find::<ClientError>() {
-		info!("Request failed: {}", e);
+		info!("Request {} failed: {}", request_id, e);
 		return match e {
 			ClientError::ParameterInvalid(_) => Ok(Box::new(warp::http::StatusCode::BAD_REQUEST)),
 			ClientError::ParameterMissing(_) => Ok(Box::new(warp::http::StatusCode::BAD_REQUEST)),
+			ClientError::Unauthorized(_) => Ok(Box::new(warp::reply::with_header(
+				warp::http::StatusCode::UNAUTHORIZED,
+				"WWW-Authenticate",
+				"Bearer",
+			))),
+			ClientError::Forbidden(_) => Ok(Box::new(warp::http::StatusCode::FORBIDDEN)),
+			ClientError::TooManyRequests(secs) => Ok(Box::new(warp::reply::with_header(
+				warp::http::StatusCode::TOO_MANY_REQUESTS,
+				"Retry-After",
+				secs.to_string(),
+			))),
 		};
 	} else {
-		error!("Unknown error occurred.");
+		// routes not found are answered by warp
+		if !r.is_not_found() && r.find::<warp::reject::MethodNotAllowed>().is_none() {
+			error!("Request {} failed with an unknown error.", request_id);
+		}
 		return Err(r);
 	}
 }
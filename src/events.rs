@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use log::{debug, error};
+use serde_json::{json, Value};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use tokio::{
+    stream::{Stream, StreamExt},
+    sync::{broadcast, watch},
+};
+
+use crate::{
+    email,
+    error::DbError,
+    model::{ArticleObject, TransactionObject, UserEntity},
+    settings::{BackendSetting, WebhookSetting},
+    webhook,
+};
+
+/// Number of events buffered per subscriber, slower subscribers miss events
+const EVENT_BUFFER: usize = 256;
+
+/// Changes of the entities, published after they are committed to the database
+#[derive(Debug, Clone)]
+pub enum Event {
+    TransactionCreated(TransactionObject),
+    UserCreated(UserEntity),
+    UserUpdated(UserEntity),
+    ArticleCreated(ArticleObject),
+    ArticleUpdated(ArticleObject),
+    ArticleDeleted(ArticleObject),
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::TransactionCreated(_) => "transaction.created",
+            Event::UserCreated(_) => "user.created",
+            Event::UserUpdated(_) => "user.updated",
+            Event::ArticleCreated(_) => "article.created",
+            Event::ArticleUpdated(_) => "article.updated",
+            Event::ArticleDeleted(_) => "article.deleted",
+        }
+    }
+
+    /// Users affected by the event, empty if it concerns everybody
+    pub fn user_ids(&self) -> Vec<i32> {
+        match self {
+            Event::TransactionCreated(t) => std::iter::once(&t.user)
+                .chain(t.recipient.iter())
+                .chain(t.sender.iter())
+                .map(|u| u.id)
+                .collect(),
+            Event::UserCreated(u) | Event::UserUpdated(u) => vec![u.id],
+            Event::ArticleCreated(_) | Event::ArticleUpdated(_) | Event::ArticleDeleted(_) => {
+                vec![]
+            }
+        }
+    }
+
+    /// The booking if it took the balance of its user from at least `threshold` to below it
+    pub fn balance_dropped_below(&self, threshold: i32) -> Option<&TransactionObject> {
+        match self {
+            Event::TransactionCreated(t) => {
+                let new_balance = t.user.balance;
+                let deposit = t.deposit_transaction.as_ref().map_or(0, |d| d.amount);
+                let old_balance = new_balance - t.entity.amount - deposit;
+                match old_balance >= threshold && new_balance < threshold {
+                    true => Some(t),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Payload in the shape of the corresponding API response
+    pub fn payload(&self) -> Value {
+        match self {
+            Event::TransactionCreated(t) => json!({ "transaction": t }),
+            Event::UserCreated(u) | Event::UserUpdated(u) => json!({ "user": u }),
+            Event::ArticleCreated(a) | Event::ArticleUpdated(a) | Event::ArticleDeleted(a) => {
+                json!({ "article": a })
+            }
+        }
+    }
+}
+
+/// Distributes events to all current subscribers and queues them for the webhooks.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+    webhooks: Arc<WebhookSetting>,
+    /// balance below which email reminders are queued, None if they are disabled
+    reminder_threshold: Option<i32>,
+    // the sender lives as long as the routes, so long-lived streams end on this instead
+    closing: Arc<watch::Sender<bool>>,
+    closed: watch::Receiver<bool>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(&BackendSetting::default())
+    }
+}
+
+impl EventBus {
+    pub fn new(settings: &BackendSetting) -> Self {
+        let email = &settings.email;
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        let (closing, closed) = watch::channel(false);
+        EventBus {
+            sender,
+            webhooks: Arc::new(settings.webhooks.clone()),
+            reminder_threshold: match email.enabled && email.reminder.enabled {
+                true => Some(email.reminder.threshold),
+                false => None,
+            },
+            closing: Arc::new(closing),
+            closed,
+        }
+    }
+
+    /// Queues the webhook deliveries and email reminders of `event` in the transaction making the change,
+    /// so neither gets lost without the other. The event is published once committed.
+    pub async fn queue_tx(
+        &self,
+        tx: &mut Transaction<'static, Sqlite>,
+        event: &Event,
+    ) -> Result<(), DbError> {
+        webhook::queue_tx(tx, &self.webhooks, event).await?;
+        if let Some(threshold) = self.reminder_threshold {
+            email::queue_reminder_tx(tx, threshold, event).await?;
+        }
+        return Ok(());
+    }
+
+    /// Queues and publishes a change the database layer committed on its own.
+    pub async fn publish_committed(&self, db: &SqlitePool, event: Event) {
+        let result = match db.begin().await {
+            Ok(mut tx) => match self.queue_tx(&mut tx, &event).await {
+                Ok(()) => tx.commit().await.map_err(DbError::from),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Queueing {} failed: {}", event.name(), e);
+        }
+        self.publish(event);
+    }
+
+    pub fn publish(&self, event: Event) {
+        debug!("Publishing event {}", event.name());
+        // fails only if nobody is listening, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Signals the server shutting down, which ends the streams of `closed`.
+    pub fn close(&self) {
+        let _ = self.closing.broadcast(true);
+    }
+
+    /// Yields once when `close` is called.
+    pub fn closed(&self) -> impl Stream<Item = ()> {
+        self.closed
+            .clone()
+            .filter(|closed| *closed)
+            .map(|_| ())
+            .take(1)
+    }
+}
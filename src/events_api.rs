@@ -0,0 +1,53 @@
+use std::{collections::HashMap, convert::Infallible};
+
+use log::warn;
+use serde_json::json;
+use tokio::{stream::StreamExt, sync::broadcast::RecvError};
+
+use crate::{error::ClientError, events::EventBus};
+
+/// Streams the events as Server-Sent Events.
+/// With `user=<id>[,<id>...]` only events of these users and those concerning everybody are sent.
+/// The stream ends when the server shuts down, so it does not hold up the graceful shutdown.
+pub async fn subscribe(
+    events: EventBus,
+    query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let users: Vec<i32> = match query.get("user") {
+        Some(v) => v
+            .split(',')
+            .map(|id| id.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| ClientError::ParameterInvalid(format!("user '{}'", v)))?,
+        None => vec![],
+    };
+
+    let stream = events
+        .subscribe()
+        .into_stream()
+        .filter_map(move |received| match received {
+            Ok(event) => {
+                let affected = event.user_ids();
+                match users.is_empty()
+                    || affected.is_empty()
+                    || affected.iter().any(|id| users.contains(id))
+                {
+                    true => Some((event.name(), event.payload())),
+                    false => None,
+                }
+            }
+            // tell the client to reload, as it missed some changes
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Event subscriber lagged behind by {} events", missed);
+                Some(("lagged", json!({ "missed": missed })))
+            }
+            Err(RecvError::Closed) => None,
+        })
+        .map(Some)
+        .merge(events.closed().map(|_| None))
+        .take_while(Option::is_some)
+        .filter_map(|item| item)
+        .map(|(name, data)| Ok::<_, Infallible>((warp::sse::event(name), warp::sse::json(data))));
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use warp::{path::FullPath, Filter, Rejection, Reply};
+
+use crate::settings::FrontendSetting;
+
+/// Serves the static files of the frontend, unknown paths outside of the API get the `index.html`,
+/// so the routes of the single page app keep working on reload.
+/// Rejects all requests if no directory is configured.
+pub fn routes(
+    settings: &FrontendSetting,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let enabled = !settings.directory.is_empty();
+    let dir = PathBuf::from(&settings.directory);
+    let index = dir.join("index.html");
+    let asset_cache = format!("public, max-age={}", settings.max_age);
+
+    let assets = warp::path::full().and(warp::fs::dir(dir)).map(
+        move |path: FullPath, file: warp::fs::File| {
+            // build tools put the hashed, hence immutable, files below static/
+            let cache = match path.as_str().starts_with("/static/") {
+                true => asset_cache.clone(),
+                false => "no-cache".to_string(),
+            };
+            warp::reply::with_header(file, "cache-control", cache)
+        },
+    );
+    let fallback = warp::get()
+        .and(warp::path::full())
+        .and_then(|path: FullPath| async move {
+            match path.as_str().split('/').nth(1) {
+                Some("api") => Err(warp::reject::not_found()),
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(warp::fs::file(index))
+        .map(|file: warp::fs::File| {
+            warp::reply::with_header(file, "cache-control", "no-cache".to_string())
+        });
+
+    warp::any()
+        .and_then(move || async move {
+            match enabled {
+                true => Ok(()),
+                false => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+        .and(assets.or(fallback).unify())
+}
@@ -0,0 +1,223 @@
+use std::{
+    io::Write,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use serde_json::{json, Value};
+use simple_logger::SimpleLogger;
+use warp::{
+    http::{HeaderValue, Method},
+    path::FullPath,
+    reply::Response,
+    Filter, Rejection, Reply,
+};
+
+use crate::{common, error};
+
+/// Target of the access log lines
+pub const ACCESS_TARGET: &str = "access";
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Longest request id taken over from a client
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+/// Installs the logger, writing plain text lines or one JSON object per line.
+pub fn init(level: LevelFilter, json: bool) {
+    JSON_FORMAT.store(json, Ordering::Relaxed);
+    match json {
+        true => {
+            log::set_boxed_logger(Box::new(JsonLogger { level }))
+                .map(|_| log::set_max_level(level))
+                .unwrap();
+        }
+        false => SimpleLogger::new().with_level(level).init().unwrap(),
+    }
+}
+
+struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = json!({
+            "time": common::cur_datetime_str(),
+            "level": record.level().to_string(),
+            "target": record.target(),
+        });
+        let message = record.args().to_string();
+        // the fields of access log lines are kept on the top level
+        match serde_json::from_str::<Value>(&message) {
+            Ok(Value::Object(fields)) if record.target() == ACCESS_TARGET => {
+                for (key, value) in fields {
+                    line[key] = value;
+                }
+            }
+            _ => line["message"] = Value::String(message),
+        }
+
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+        let _ = writeln!(out, "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// A request as seen by the access log
+#[derive(Debug, Serialize)]
+struct Access {
+    #[serde(rename = "requestId")]
+    request_id: String,
+    method: String,
+    path: String,
+    status: Option<u16>,
+    /// milliseconds from receiving the request until the response was ready
+    latency: f64,
+    #[serde(rename = "userId")]
+    user_id: Option<i32>,
+    remote: Option<String>,
+}
+
+/// Request data collected before the routes run
+struct RequestInfo {
+    id: String,
+    method: Method,
+    path: FullPath,
+    remote: Option<SocketAddr>,
+    started: Instant,
+}
+
+impl RequestInfo {
+    fn log(&self, status: Option<u16>) {
+        let access = Access {
+            request_id: self.id.clone(),
+            method: self.method.to_string(),
+            path: self.path.as_str().to_string(),
+            status,
+            latency: self.started.elapsed().as_secs_f64() * 1000.0,
+            user_id: common::path_user_id(self.path.as_str()),
+            remote: self.remote.map(|v| v.ip().to_string()),
+        };
+        match JSON_FORMAT.load(Ordering::Relaxed) {
+            true => info!(
+                target: ACCESS_TARGET,
+                "{}",
+                serde_json::to_string(&access).unwrap_or_default()
+            ),
+            false => info!(
+                target: ACCESS_TARGET,
+                "{} {} {} {} {:.1}ms user={} id={}",
+                access.remote.as_deref().unwrap_or("-"),
+                access.method,
+                access.path,
+                access
+                    .status
+                    .map_or_else(|| "-".to_string(), |v| v.to_string()),
+                access.latency,
+                access
+                    .user_id
+                    .map_or_else(|| "-".to_string(), |v| v.to_string()),
+                access.request_id
+            ),
+        };
+    }
+}
+
+/// The id sent by the client in `X-Request-Id`, or a new one unique to this process
+fn request_id(header: Option<String>) -> String {
+    match header {
+        Some(v)
+            if !v.is_empty()
+                && v.len() <= MAX_REQUEST_ID_LEN
+                && v.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            v
+        }
+        _ => {
+            let started = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|v| v.as_secs())
+                .unwrap_or(0);
+            format!(
+                "{:x}-{:x}",
+                started,
+                NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+            )
+        }
+    }
+}
+
+fn request_info() -> impl Filter<Extract = (RequestInfo,), Error = std::convert::Infallible> + Clone
+{
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>(REQUEST_ID_HEADER))
+        .map(
+            |method: Method, path: FullPath, remote: Option<SocketAddr>, id: Option<String>| {
+                RequestInfo {
+                    id: request_id(id),
+                    method,
+                    path,
+                    remote,
+                    started: Instant::now(),
+                }
+            },
+        )
+}
+
+/// Answers the rejections of `routes` via `handle_my_error` with the request id at hand,
+/// writes the access log and returns the request id in `X-Request-Id`.
+pub fn with_access_log<F, R>(
+    routes: F,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let routes = routes
+        .map(|reply: R| Ok::<_, Rejection>(reply.into_response()))
+        .or_else(|r: Rejection| async move { Ok::<_, Rejection>((Err(r),)) });
+
+    request_info().and(routes).and_then(
+        |request: RequestInfo, result: Result<Response, Rejection>| async move {
+            let result = match result {
+                Ok(v) => Ok(v),
+                Err(r) => error::handle_my_error(&request.id, r)
+                    .await
+                    .map(|v| v.into_response()),
+            };
+            match result {
+                Ok(mut response) => {
+                    request.log(Some(response.status().as_u16()));
+                    if let Ok(id) = HeaderValue::from_str(&request.id) {
+                        response.headers_mut().insert(REQUEST_ID_HEADER, id);
+                    }
+                    Ok(response)
+                }
+                // answered by warp, which only tells the status of some of them
+                Err(r) => {
+                    request.log(None);
+                    Err(r)
+                }
+            }
+        },
+    )
+}
@@ -1,26 +1,58 @@
-use log::{info, LevelFilter};
-use simple_logger::SimpleLogger;
+use log::{error, info, LevelFilter};
 use sqlx::sqlite::SqlitePool;
 use std::collections::HashMap;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
 use warp::Filter;
 
-use crate::common::{with_db, with_settings};
+use crate::common::{with_db, with_settings, with_state};
 
+mod api_token;
+mod api_token_api;
+mod api_token_db;
 mod article_api;
 mod article_db;
+mod avatar_api;
+mod avatar_db;
+mod backup;
+mod backup_api;
+mod category_api;
+mod category_db;
+mod cli;
 mod common;
+mod credential_api;
+mod credential_db;
 mod db;
+mod email;
+mod email_db;
 mod error;
+mod events;
+mod events_api;
+mod frontend;
+mod logging;
 mod metrics_api;
 mod metrics_db;
 mod model;
+mod openapi;
+mod pending_api;
+mod pending_db;
+mod price_change;
+mod price_change_db;
+mod ratelimit;
+mod schedule;
+mod schedule_api;
+mod schedule_db;
 mod settings;
 mod settings_api;
+#[cfg(test)]
+mod tests;
 mod transaction_api;
+mod tls;
 mod transaction_db;
 mod user_api;
 mod user_db;
+mod webhook;
+mod webhook_db;
 
 const SETTINGS_FILE_ENV: &str = "SETTINGS_FILE";
 const SETTINGS_FILE_DEFAULT: &str = "/etc/strichliste.yaml";
@@ -31,39 +63,230 @@ const BIND_ADDR_DEFAULT: &str = "[::]:3030";
 const DB_FILE_ENV: &str = "DB_FILE";
 const DB_FILE_DEFAULT: &str = "/var/lib/strichliste/strichliste.sqlite";
 
+/// one of off, error, warn, info, debug or trace
+const LOG_LEVEL_ENV: &str = "LOG_LEVEL";
+/// text or json
+const LOG_FORMAT_ENV: &str = "LOG_FORMAT";
+
+// exit codes, so supervisors can tell the failures apart
+const EXIT_COMMAND_FAILED: i32 = 1;
+const EXIT_INVALID_SETTINGS: i32 = 2;
+const EXIT_DB_UNAVAILABLE: i32 = 3;
+const EXIT_MIGRATION_FAILED: i32 = 4;
+const EXIT_INVALID_BIND_ADDR: i32 = 5;
+const EXIT_BIND_FAILED: i32 = 6;
+const EXIT_TLS_FAILED: i32 = 7;
+
 #[tokio::main]
 async fn main() {
-    SimpleLogger::new()
-        .with_level(LevelFilter::Info)
-        .init()
-        .unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // validate the settings only, so deployment pipelines can check a config before rolling it out
+    let check_config = args.iter().any(|a| a == "--check-config");
+    // administrative commands print their results on stdout, keep it clean for scripts
+    let admin_command = !check_config && !args.is_empty();
+
+    let level = std::env::var(LOG_LEVEL_ENV).ok().filter(|v| !v.is_empty());
+    let format = common::env_or(LOG_FORMAT_ENV, "text");
+    logging::init(
+        level
+            .as_deref()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(match admin_command {
+                true => LevelFilter::Warn,
+                false => LevelFilter::Info,
+            }),
+        format == "json",
+    );
+    if let Some(level) = level.filter(|v| v.parse::<LevelFilter>().is_err()) {
+        exit_with(
+            EXIT_INVALID_SETTINGS,
+            format!("Invalid {} '{}'", LOG_LEVEL_ENV, level),
+        );
+    }
+    if format != "text" && format != "json" {
+        exit_with(
+            EXIT_INVALID_SETTINGS,
+            format!("Invalid {} '{}', expected text or json", LOG_FORMAT_ENV, format),
+        );
+    }
 
     let settings = match settings::load_settings(SETTINGS_FILE_ENV, SETTINGS_FILE_DEFAULT) {
         Ok(s) => s,
-        Err(e) => panic!("{}", e),
+        Err(e) => exit_with(EXIT_INVALID_SETTINGS, e),
     };
+    if check_config {
+        info!("Settings are valid.");
+        return;
+    }
 
     let db_file = common::env_or(DB_FILE_ENV, DB_FILE_DEFAULT);
     let db = match db::open_db(db_file.as_str()).await {
         Ok(db) => db,
-        Err(e) => panic!("{}", e),
-    };
-    match db::migrate_db(&db).await {
-        Ok(_) => (),
-        Err(e) => panic!("{}", e),
+        Err(e) => exit_with(
+            EXIT_DB_UNAVAILABLE,
+            format!("Failed to open database {}: {}", db_file, e),
+        ),
     };
 
-    // TODO: add error handling
+    if admin_command {
+        let result = cli::run(&args, &db, &db_file, Arc::new(settings.strichliste)).await;
+        db.close().await;
+        match result {
+            Ok(_) => return,
+            Err(e) => exit_with(EXIT_COMMAND_FAILED, e),
+        }
+    }
+
+    if let Err(e) = db::migrate_db(&db).await {
+        db.close().await;
+        exit_with(
+            EXIT_MIGRATION_FAILED,
+            format!("Failed to migrate database {}: {}", db_file, e),
+        );
+    }
+
     let addr_str = common::env_or(BIND_ADDR_ENV, BIND_ADDR_DEFAULT);
-    let mut addr_iter = addr_str.to_socket_addrs().unwrap();
-    let addr = addr_iter.next().unwrap();
+    let addr = match addr_str.to_socket_addrs().map(|mut v| v.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None) => exit_with(
+            EXIT_INVALID_BIND_ADDR,
+            format!("Bind address {} did not resolve to any address.", addr_str),
+        ),
+        Err(e) => exit_with(
+            EXIT_INVALID_BIND_ADDR,
+            format!("Invalid bind address {}: {}", addr_str, e),
+        ),
+    };
+
+    if settings.backend.backup.enabled {
+        tokio::spawn(backup::run_backup_job(
+            db.clone(),
+            settings.backend.backup.clone(),
+        ));
+    }
+
+    // changes are published here after they are committed
+    let events = events::EventBus::new(&settings.backend);
+
+    if !settings.backend.webhooks.hooks.is_empty() {
+        tokio::spawn(webhook::run_webhooks(
+            db.clone(),
+            events.subscribe(),
+            settings.backend.webhooks.clone(),
+        ));
+    }
+
+    if settings.backend.email.enabled {
+        let mailer = Arc::new(email::Mailer::new(
+            settings.backend.email.clone(),
+            Arc::new(settings.strichliste.clone()),
+        ));
+        if settings.backend.email.reminder.enabled {
+            tokio::spawn(email::run_reminders(
+                db.clone(),
+                events.subscribe(),
+                mailer.clone(),
+            ));
+        }
+        if settings.backend.email.statement.enabled {
+            tokio::spawn(email::run_statements(db.clone(), mailer));
+        }
+    }
 
-    start_webserver(addr, db, settings).await;
+    // after the subscribers above, so they see the changes caught up on startup
+    tokio::spawn(price_change::run_price_changes(db.clone(), events.clone()));
+    tokio::spawn(schedule::run_scheduled_transactions(
+        db.clone(),
+        Arc::new(settings.strichliste.clone()),
+        events.clone(),
+    ));
+
+    start_webserver(
+        addr,
+        db.clone(),
+        events,
+        settings.strichliste,
+        settings.backend,
+    )
+    .await;
+
+    // all requests are done at this point, so no transaction is cut off
+    db.close().await;
+    info!("Shutdown complete.");
+}
+
+fn exit_with<E: std::fmt::Display>(code: i32, e: E) -> ! {
+    error!("{}", e);
+    std::process::exit(code);
 }
 
-async fn start_webserver(addr: SocketAddr, db: SqlitePool, settings: settings::StrichlisteSetting) {
+/// Resolves on SIGINT or SIGTERM, the latter is what docker sends on stop.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = terminate.recv() => (),
+            },
+            Err(e) => {
+                error!(
+                    "Failed to listen for SIGTERM, only SIGINT is handled: {}",
+                    e
+                );
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        };
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+
+    info!("Shutting down, waiting for running requests to finish ...");
+}
+
+async fn start_webserver(
+    addr: SocketAddr,
+    db: SqlitePool,
+    events: events::EventBus,
+    settings: settings::StrichlisteSetting,
+    backend: settings::BackendSetting,
+) {
     info!("Starting webserver binding ...");
 
+    let routes = routes(db, events.clone(), settings, &backend);
+    // open event streams would keep the graceful shutdown waiting forever
+    let shutdown = async move {
+        shutdown_signal().await;
+        events.close();
+    };
+
+    if backend.tls.enabled {
+        if let Err(e) = tls::serve(routes, addr, backend.tls, shutdown).await {
+            exit_with(EXIT_TLS_FAILED, e);
+        }
+        return;
+    }
+
+    let (addr, server) =
+        match warp::serve(routes).try_bind_with_graceful_shutdown(addr, shutdown) {
+            Ok(v) => v,
+            Err(e) => exit_with(EXIT_BIND_FAILED, format!("Failed to bind {}: {}", addr, e)),
+        };
+    info!("Listening on http://{}", addr);
+    server.await;
+}
+
+/// Builds the filter tree of the API and the optional frontend.
+fn routes(
+    db: SqlitePool,
+    events: events::EventBus,
+    settings: settings::StrichlisteSetting,
+    backend: &settings::BackendSetting,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     // see next link how to add apis
     // https://blog.logrocket.com/creating-a-rest-api-in-rust-with-warp/
 
@@ -95,12 +318,14 @@ async fn start_webserver(addr: SocketAddr, db: SqlitePool, settings: settings::S
     let add_user = warp::post()
         .and(warp::path::end())
         .and(with_db(db.clone()))
+        .and(with_state(events.clone()))
         .and(warp::body::content_length_limit(1024 * 32))
         .and(warp::body::json())
         .and_then(user_api::add_user);
     let update_user = warp::post()
         .and(with_db(db.clone()))
         .and(with_settings(settings.clone()))
+        .and(with_state(events.clone()))
         .and(warp::path!(i32))
         .and(warp::body::content_length_limit(1024 * 32))
         .and(warp::body::json())
@@ -113,6 +338,66 @@ async fn start_webserver(addr: SocketAddr, db: SqlitePool, settings: settings::S
             .or(update_user),
     );
 
+    // credential API
+    let set_pin = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(warp::path!(i32 / "pin"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(credential_api::set_pin);
+    let get_tokens = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!(i32 / "token"))
+        .and(warp::header::optional::<String>("x-user-pin"))
+        .and_then(credential_api::get_tokens);
+    let add_token = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(warp::path!(i32 / "token"))
+        .and(warp::header::optional::<String>("x-user-pin"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(credential_api::add_token);
+    let delete_token = warp::delete()
+        .and(with_db(db.clone()))
+        .and(warp::path!(i32 / "token" / i32))
+        .and(warp::header::optional::<String>("x-user-pin"))
+        .and_then(credential_api::delete_token);
+    let find_user_by_token = warp::get()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(warp::path!("token" / String))
+        .and_then(credential_api::find_user_by_token);
+    let credential_api = user_path.and(
+        set_pin
+            .or(get_tokens)
+            .or(add_token)
+            .or(delete_token)
+            .or(find_user_by_token),
+    );
+
+    // avatar API
+    let get_avatar = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!(i32 / "avatar"))
+        .and_then(avatar_api::get_avatar);
+    let set_avatar = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(with_state(events.clone()))
+        .and(warp::path!(i32 / "avatar"))
+        .and(warp::body::content_length_limit(backend.avatar.max_size))
+        .and(warp::body::bytes())
+        .and_then(avatar_api::set_avatar);
+    let delete_avatar = warp::delete()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(with_state(events.clone()))
+        .and(warp::path!(i32 / "avatar"))
+        .and_then(avatar_api::delete_avatar);
+    let avatar_api = user_path.and(get_avatar.or(set_avatar).or(delete_avatar));
+
     // article API
     let article_path = warp::path("article");
     let get_articles = warp::get()
@@ -127,27 +412,58 @@ async fn start_webserver(addr: SocketAddr, db: SqlitePool, settings: settings::S
     let add_article = warp::post()
         .and(warp::path::end())
         .and(with_db(db.clone()))
+        .and(with_state(events.clone()))
         .and(warp::body::content_length_limit(1024 * 32))
         .and(warp::body::json())
         .and_then(article_api::add_article);
     let update_article = warp::post()
         .and(with_db(db.clone()))
+        .and(with_state(events.clone()))
         .and(warp::path!(i32))
         .and(warp::body::content_length_limit(1024 * 32))
         .and(warp::body::json())
         .and_then(article_api::update_article);
     let delete_article = warp::delete()
         .and(with_db(db.clone()))
+        .and(with_state(events.clone()))
         .and(warp::path!(i32))
         .and_then(article_api::delete_article);
+    let get_article_history = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!(i32 / "history"))
+        .and_then(article_api::get_article_history);
+    let get_price_changes = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!(i32 / "price"))
+        .and_then(article_api::get_price_changes);
+    let add_price_change = warp::post()
+        .and(with_db(db.clone()))
+        .and(warp::path!(i32 / "price"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(article_api::add_price_change);
+    let cancel_price_change = warp::delete()
+        .and(with_db(db.clone()))
+        .and(warp::path!("price" / i32))
+        .and_then(article_api::cancel_price_change);
     let article_api = article_path.and(
         get_articles
             .or(get_article)
             .or(add_article)
             .or(update_article)
-            .or(delete_article),
+            .or(delete_article)
+            .or(get_article_history)
+            .or(get_price_changes)
+            .or(add_price_change)
+            .or(cancel_price_change),
     );
 
+    // category API
+    let category_api = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!("category"))
+        .and_then(category_api::get_categories);
+
     // transaction API
     let get_user_transactions = warp::get()
         .and(with_db(db.clone()))
@@ -158,11 +474,53 @@ async fn start_webserver(addr: SocketAddr, db: SqlitePool, settings: settings::S
     let add_user_transaction = warp::post()
         .and(with_db(db.clone()))
         .and(with_settings(settings.clone()))
+        .and(with_state(events.clone()))
+        .and(with_settings(backend.idempotency.clone()))
+        .and(with_settings(backend.approval.clone()))
         .and(warp::path!(i32 / "transaction"))
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(warp::header::optional::<String>("x-user-pin"))
         .and(warp::body::content_length_limit(1024 * 32))
         .and(warp::body::json())
         .and_then(transaction_api::add_transaction);
-    let transaction_api = user_path.and(get_user_transactions.or(add_user_transaction));
+    let checkout_cart = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(with_state(events.clone()))
+        .and(with_settings(backend.idempotency.clone()))
+        .and(with_settings(backend.approval.clone()))
+        .and(warp::path!(i32 / "cart"))
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(warp::header::optional::<String>("x-user-pin"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(transaction_api::checkout_cart);
+    let transaction_api = user_path.and(
+        get_user_transactions
+            .or(add_user_transaction)
+            .or(checkout_cart),
+    );
+
+    // scheduled transaction API
+    let get_scheduled = warp::get()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(warp::path!(i32 / "scheduled"))
+        .and_then(schedule_api::get_scheduled_transactions);
+    let add_scheduled = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(warp::path!(i32 / "scheduled"))
+        .and(warp::header::optional::<String>("x-user-pin"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(schedule_api::add_scheduled_transaction);
+    let cancel_scheduled = warp::delete()
+        .and(with_db(db.clone()))
+        .and(warp::path!(i32 / "scheduled" / i32))
+        .and(warp::header::optional::<String>("x-user-pin"))
+        .and_then(schedule_api::cancel_scheduled_transaction);
+    let schedule_api = user_path.and(get_scheduled.or(add_scheduled).or(cancel_scheduled));
 
     // metrics API
     let system_metrics = warp::get()
@@ -177,15 +535,115 @@ async fn start_webserver(addr: SocketAddr, db: SqlitePool, settings: settings::S
         .and_then(metrics_api::get_user_metrics);
     let metrics_api = system_metrics.or(user_metrics);
 
-    // bind it together
-    let api = warp::path("api").and(
-        settings_api
-            .or(user_api)
-            .or(article_api)
-            .or(transaction_api)
-            .or(metrics_api)
-            .recover(error::handle_my_error),
+    // event API
+    let events_api = warp::get()
+        .and(with_state(events.clone()))
+        .and(warp::path!("events"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(events_api::subscribe);
+
+    // admin API
+    let admin_path = warp::path("admin");
+    let trigger_backup = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(backend.backup.clone()))
+        .and(warp::path!("backup"))
+        .and_then(backup_api::trigger_backup);
+    let download_backup = warp::get()
+        .and(with_db(db.clone()))
+        .and(with_settings(backend.backup.clone()))
+        .and(warp::path!("backup"))
+        .and_then(backup_api::download_backup);
+    let get_pending = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!("pending"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(pending_api::get_pending_transactions);
+    let approve_pending = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(with_state(events.clone()))
+        .and(warp::path!("pending" / i32 / "approve"))
+        .and_then(pending_api::approve_pending_transaction);
+    let reject_pending = warp::post()
+        .and(with_db(db.clone()))
+        .and(warp::path!("pending" / i32 / "reject"))
+        .and_then(pending_api::reject_pending_transaction);
+    let get_user_notes = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!("user" / i32 / "notes"))
+        .and_then(user_api::get_notes);
+    let set_user_notes = warp::post()
+        .and(with_db(db.clone()))
+        .and(warp::path!("user" / i32 / "notes"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(user_api::set_notes);
+    let reset_user_pin = warp::post()
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(warp::path!("user" / i32 / "pin"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(credential_api::reset_pin);
+    let get_api_tokens = warp::get()
+        .and(with_db(db.clone()))
+        .and(warp::path!("token"))
+        .and_then(api_token_api::get_api_tokens);
+    let add_api_token = warp::post()
+        .and(with_db(db.clone()))
+        .and(warp::path!("token"))
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and_then(api_token_api::add_api_token);
+    let revoke_api_token = warp::delete()
+        .and(with_db(db.clone()))
+        .and(warp::path!("token" / i32))
+        .and_then(api_token_api::revoke_api_token);
+    let admin_api = admin_path.and(
+        trigger_backup
+            .or(download_backup)
+            .or(get_pending)
+            .or(approve_pending)
+            .or(reject_pending)
+            .or(get_user_notes)
+            .or(set_user_notes)
+            .or(reset_user_pin)
+            .or(get_api_tokens)
+            .or(add_api_token)
+            .or(revoke_api_token),
     );
 
-    warp::serve(api).run(addr).await;
+    // OpenAPI document
+    let openapi_api = warp::get()
+        .and(with_settings(openapi::spec()))
+        .and(warp::path!("openapi.json"))
+        .and_then(openapi::get_spec);
+
+    // bind it together, rate limited before the token lookup so guessing tokens is limited as well
+    let api = warp::path("api")
+        .and(ratelimit::limit(backend.rate_limit.clone()))
+        .and(api_token::authorize(
+            db,
+            backend.api_tokens.clone(),
+            backend.admin_token.clone(),
+        ))
+        .and(
+            settings_api
+                .or(user_api)
+                .or(credential_api)
+                .or(avatar_api)
+                .or(article_api)
+                .or(category_api)
+                .or(transaction_api)
+                .or(schedule_api)
+                .or(metrics_api)
+                .or(events_api)
+                .or(admin_api)
+                .or(openapi_api),
+        );
+
+    // optionally serve the frontend as well, so no separate web server is needed,
+    // errors are answered by handle_my_error
+    logging::with_access_log(api.or(frontend::routes(&backend.frontend)))
 }
@@ -30,6 +30,8 @@ pub async fn get_sys_metrics(
     let transaction_count = metrics_db::num_transactions(&mut tx).await?;
     let user_count = metrics_db::num_users(&mut tx).await?;
     let articles = vec![];
+    let categories = metrics_db::category_stats(&mut tx, None).await?;
+    let deposits = metrics_db::deposit_stats(&mut tx).await?;
     let days = metrics_db::transactions_per_day(&mut tx, &*date_begin).await?;
 
     tx.commit().await.map_err(|e| -> DbError { e.into() })?;
@@ -39,6 +41,8 @@ pub async fn get_sys_metrics(
         transaction_count,
         user_count,
         articles,
+        categories,
+        deposits,
         days,
     };
 
@@ -63,12 +67,14 @@ pub async fn get_user_metrics(
 
     let article_stats = metrics_db::user_article_stats(&mut tx, &user_id).await?;
     let tx_stats = metrics_db::user_transaction_stats(&mut tx, &user_id).await?;
+    let category_stats = metrics_db::category_stats(&mut tx, Some(user_id)).await?;
 
     tx.commit().await.map_err(|e| -> DbError { e.into() })?;
 
     Ok(json_reply(UserMetrics {
         balance: user.balance,
         articles: article_stats,
+        categories: category_stats,
         transactions: tx_stats,
     }))
 }
@@ -4,7 +4,8 @@ use crate::{
     article_db,
     error::DbError,
     model::{
-        DailyTransaction, TransactionStatsEntity, TransactionSum, UserArticles, UserTransactions,
+        CategoryStats, DailyTransaction, DepositStats, TransactionStatsEntity, TransactionSum,
+        UserArticles, UserTransactions,
     },
 };
 
@@ -72,6 +73,46 @@ pub async fn transactions_per_day(
     Ok(metrics)
 }
 
+/// Purchases per category of all users, or only of `user_id`
+pub async fn category_stats(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: Option<i32>,
+) -> Result<Vec<CategoryStats>, DbError> {
+    let stats = sqlx::query_as::<_, CategoryStats>(
+        "SELECT c.name AS category, COUNT(t.id) AS count, SUM(t.amount) * -1 AS amount
+		FROM transactions AS t
+		INNER JOIN article_category AS ac ON ac.article_id = t.article_id
+		INNER JOIN category AS c ON c.id = ac.category_id
+		WHERE t.deleted IS FALSE AND t.deposit IS FALSE AND (? IS NULL OR t.user_id = ?)
+		GROUP BY c.id
+		ORDER BY COUNT(t.id) DESC, c.name",
+    )
+    .bind(user_id)
+    .bind(user_id)
+    .fetch_all(tx)
+    .await?;
+
+    Ok(stats)
+}
+
+/// Deposits of the active transactions, charged ones are counted as positive amounts
+pub async fn deposit_stats(tx: &mut Transaction<'static, Sqlite>) -> Result<DepositStats, DbError> {
+    let stats = sqlx::query_as::<_, DepositStats>(
+        "SELECT
+		COALESCE(SUM(CASE WHEN amount < 0 THEN amount * -1 ELSE 0 END), 0) AS charged,
+		COALESCE(SUM(CASE WHEN amount < 0 THEN quantity ELSE 0 END), 0) AS charged_quantity,
+		COALESCE(SUM(CASE WHEN amount > 0 THEN amount ELSE 0 END), 0) AS returned,
+		COALESCE(SUM(CASE WHEN amount > 0 THEN quantity ELSE 0 END), 0) AS returned_quantity,
+		COALESCE(SUM(amount) * -1, 0) AS outstanding
+		FROM transactions
+		WHERE deposit IS TRUE AND deleted IS FALSE",
+    )
+    .fetch_one(tx)
+    .await?;
+
+    Ok(stats)
+}
+
 pub async fn user_article_stats(
     tx: &mut Transaction<'static, Sqlite>,
     user_id: &i32,
@@ -80,7 +121,7 @@ pub async fn user_article_stats(
         "SELECT COUNT(a.id) as count, SUM(t.amount) * -1 as amount, a.id
 		FROM transactions AS t
 		INNER JOIN article AS a ON a.id = t.article_id
-		WHERE t.user_id = ?
+		WHERE t.user_id = ? AND t.deposit IS FALSE
 		GROUP BY a.id
 		ORDER BY COUNT(a.id) DESC",
     )
@@ -101,6 +142,41 @@ pub async fn user_article_stats(
     Ok(result)
 }
 
+/// Like `user_article_stats`, but only for the active transactions created in `[from, until)`
+pub async fn user_article_stats_between(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    from: &str,
+    until: &str,
+) -> Result<Vec<UserArticles>, DbError> {
+    let article_entries: Vec<(i32, i32, i32)> = sqlx::query_as(
+        "SELECT SUM(t.quantity) as count, SUM(t.amount) * -1 as amount, a.id
+		FROM transactions AS t
+		INNER JOIN article AS a ON a.id = t.article_id
+		WHERE t.user_id = ? AND t.deleted IS FALSE AND t.deposit IS FALSE
+		AND t.created >= ? AND t.created < ?
+		GROUP BY a.id
+		ORDER BY SUM(t.quantity) DESC",
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(until)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut result: Vec<UserArticles> = vec![];
+    for (count, amount, aid) in article_entries {
+        let article = article_db::get_article_or_error_tx(tx, aid).await?;
+        result.push(UserArticles {
+            count,
+            amount,
+            article,
+        });
+    }
+
+    Ok(result)
+}
+
 pub async fn user_transaction_stats(
     tx: &mut Transaction<'static, Sqlite>,
     user_id: &i32,
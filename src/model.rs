@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use warp::Reply;
 
@@ -22,21 +23,39 @@ impl<T: Serialize + Send> Reply for JsonReply<T> {
 // DB entities
 //
 
-#[derive(Debug, Deserialize, Serialize, Clone, sqlx::FromRow)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
 pub struct UserEntity {
     pub id: i32,
+    /// unique login name
     pub name: String,
+    /// name to show instead of `name`, e.g. the full name
+    #[serde(rename(serialize = "displayName", deserialize = "displayName"))]
+    pub display_name: Option<String>,
     pub email: Option<String>,
     pub balance: i32,
     #[serde(rename(serialize = "isActive", deserialize = "isActive"))]
     pub active: bool,
     #[serde(rename(serialize = "isDisabled", deserialize = "isDisabled"))]
     pub disabled: bool,
+    /// bookings of the user need the PIN
+    #[serde(rename(serialize = "hasPin", deserialize = "hasPin"))]
+    pub has_pin: bool,
+    /// the avatar is served at `/api/user/{id}/avatar`
+    #[serde(rename(serialize = "hasAvatar", deserialize = "hasAvatar"))]
+    pub has_avatar: bool,
     pub created: String,
     pub updated: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, sqlx::FromRow)]
+/// Free-form notes of the admins on a user, only served by the admin API
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct UserNotesEntity {
+    #[serde(rename(serialize = "userId", deserialize = "userId"))]
+    pub user_id: i32,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
 pub struct ArticleEntity {
     pub id: i32,
     #[serde(skip)]
@@ -44,6 +63,8 @@ pub struct ArticleEntity {
     pub name: String,
     pub barcode: Option<String>,
     pub amount: i32,
+    /// deposit charged per unit on top of `amount`, e.g. for bottles
+    pub deposit: i32,
     #[serde(rename(serialize = "isActive", deserialize = "isActive"))]
     pub active: bool,
     pub created: String,
@@ -51,7 +72,7 @@ pub struct ArticleEntity {
     pub usage_count: i32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, sqlx::FromRow)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
 pub struct TransactionEntity {
     pub id: i32,
     #[serde(skip)]
@@ -62,14 +83,184 @@ pub struct TransactionEntity {
     pub recipient_transaction_id: Option<i32>,
     #[serde(skip)]
     pub sender_transaction_id: Option<i32>,
+    #[serde(skip)]
+    pub deposit_transaction_id: Option<i32>,
     pub quantity: Option<i32>,
     pub comment: Option<String>,
     pub amount: i32,
+    /// books a deposit charge or a return of deposit
+    #[serde(rename(serialize = "isDeposit", deserialize = "isDeposit"))]
+    pub deposit: bool,
     pub deleted: bool,
     pub created: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, sqlx::FromRow)]
+/// Reminder of a balance dropping below the threshold, queued with the booking
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct EmailReminderEntity {
+    pub id: i32,
+    pub user_id: i32,
+    pub balance: i32,
+    pub amount: i32,
+    pub attempts: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct WebhookOutboxEntity {
+    pub id: i32,
+    pub hook: String,
+    pub url: String,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    #[serde(rename(serialize = "nextAttempt", deserialize = "nextAttempt"))]
+    pub next_attempt: String,
+    #[serde(rename(serialize = "lastError", deserialize = "lastError"))]
+    pub last_error: Option<String>,
+    pub failed: bool,
+    pub created: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct CategoryEntity {
+    pub id: i32,
+    pub name: String,
+    pub created: String,
+    /// number of active articles in the category
+    #[serde(rename(serialize = "articleCount", deserialize = "articleCount"))]
+    pub article_count: i32,
+}
+
+/// PIN of a user, only the salted hash is stored
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UserPinEntity {
+    pub salt: String,
+    pub hash: String,
+    /// wrong PINs in a row
+    pub failed_attempts: i32,
+    /// seconds until the PIN is unlocked again, 0 if it isn't locked
+    pub locked_secs: i64,
+}
+
+/// Booking beyond the account boundary held until an admin decides on it, not applied to the balance
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct PendingTransactionEntity {
+    pub id: i32,
+    #[serde(rename(serialize = "userId", deserialize = "userId"))]
+    pub user_id: i32,
+    #[serde(rename(serialize = "articleId", deserialize = "articleId"))]
+    pub article_id: Option<i32>,
+    pub quantity: Option<i32>,
+    /// recipient of a transfer
+    #[serde(rename(serialize = "recipientId", deserialize = "recipientId"))]
+    pub recipient_id: Option<i32>,
+    /// returned bottles of the article instead of a purchase
+    #[serde(rename(serialize = "returnDeposit", deserialize = "returnDeposit"))]
+    pub return_deposit: bool,
+    /// first line of the held cart, all lines are decided on together
+    #[serde(rename(serialize = "cartPendingId", deserialize = "cartPendingId"))]
+    pub cart_pending_id: Option<i32>,
+    pub comment: Option<String>,
+    pub amount: i32,
+    /// one of pending, approved or rejected
+    pub state: String,
+    /// booking created on approval
+    #[serde(rename(serialize = "transactionId", deserialize = "transactionId"))]
+    pub transaction_id: Option<i32>,
+    pub created: String,
+    pub decided: Option<String>,
+}
+
+/// Booking repeated every `interval`, e.g. a membership fee
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct ScheduledTransactionEntity {
+    pub id: i32,
+    #[serde(rename(serialize = "userId", deserialize = "userId"))]
+    pub user_id: i32,
+    pub amount: i32,
+    pub comment: Option<String>,
+    /// one of daily, weekly or monthly
+    pub interval: String,
+    #[serde(rename(serialize = "nextRun", deserialize = "nextRun"))]
+    pub next_run: String,
+    /// first run, monthly runs keep its day of the month where possible
+    #[serde(rename(serialize = "firstRun", deserialize = "firstRun"))]
+    pub first_run: String,
+    #[serde(rename(serialize = "isActive", deserialize = "isActive"))]
+    pub active: bool,
+    /// number of runs not booked, e.g. because the balance would leave the boundary
+    pub skipped: i32,
+    /// reason of the latest skipped run, cleared by the next booking
+    #[serde(rename(serialize = "lastError", deserialize = "lastError"))]
+    pub last_error: Option<String>,
+    pub created: String,
+}
+
+/// Price change of an article scheduled for `activates`
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct PriceChangeEntity {
+    pub id: i32,
+    /// version the change was scheduled on, it applies to the active version at that time
+    #[serde(rename(serialize = "articleId", deserialize = "articleId"))]
+    pub article_id: i32,
+    pub amount: i32,
+    pub activates: String,
+    /// one of pending, applied, cancelled or failed
+    pub state: String,
+    /// version created by the change
+    #[serde(rename(serialize = "appliedArticleId", deserialize = "appliedArticleId"))]
+    pub applied_article_id: Option<i32>,
+    pub created: String,
+}
+
+/// A version of an article with the bookings done while it was valid
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct ArticleVersion {
+    pub id: i32,
+    pub name: String,
+    pub barcode: Option<String>,
+    pub amount: i32,
+    pub deposit: i32,
+    #[serde(rename(serialize = "isActive", deserialize = "isActive"))]
+    pub active: bool,
+    #[serde(rename(serialize = "validFrom", deserialize = "validFrom"))]
+    pub valid_from: String,
+    /// creation of the successor, missing for the latest version
+    #[serde(rename(serialize = "validUntil", deserialize = "validUntil"))]
+    pub valid_until: Option<String>,
+    /// number of purchases
+    pub sales: i32,
+    /// number of units sold
+    pub quantity: i32,
+}
+
+/// NFC or RFID card of a user
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct UserTokenEntity {
+    pub id: i32,
+    #[serde(rename(serialize = "userId", deserialize = "userId"))]
+    pub user_id: i32,
+    /// id read from the card, upper case hex without separators
+    pub uid: String,
+    pub created: String,
+}
+
+/// Token of a machine client, only a hash of the secret is stored
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct ApiTokenEntity {
+    pub id: i32,
+    pub name: String,
+    /// one of read, book or admin
+    pub scope: String,
+    pub expires: Option<String>,
+    #[serde(rename(serialize = "lastUsed", deserialize = "lastUsed"))]
+    pub last_used: Option<String>,
+    #[serde(rename(serialize = "isRevoked", deserialize = "isRevoked"))]
+    pub revoked: bool,
+    pub created: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
 pub struct TransactionStatsEntity {
     pub count: i32,
     pub amount: i32,
@@ -80,14 +271,16 @@ pub struct TransactionStatsEntity {
 // complete data objects
 //
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct ArticleObject {
     #[serde(flatten)]
     pub entity: ArticleEntity,
+    /// names of the categories, sorted
+    pub categories: Vec<String>,
     pub precursor: Option<Box<ArticleObject>>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct TransactionObject {
     #[serde(flatten)]
     pub entity: TransactionEntity,
@@ -95,9 +288,12 @@ pub struct TransactionObject {
     pub article: Option<ArticleObject>,
     pub recipient: Option<UserEntity>,
     pub sender: Option<UserEntity>,
+    /// deposit charged along with the article
+    #[serde(rename(serialize = "depositTransaction", deserialize = "depositTransaction"))]
+    pub deposit_transaction: Option<TransactionEntity>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct DailyTransaction {
     pub date: String,
     pub transactions: i32,
@@ -108,7 +304,7 @@ pub struct DailyTransaction {
     pub spent: TransactionSum,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct TransactionSum {
     pub amount: i32,
     pub transactions: i32,
@@ -118,28 +314,64 @@ pub struct TransactionSum {
 // request objects
 //
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct UserAddReq {
     pub name: String,
     pub email: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct UserUpdateReq {
     pub name: String,
     pub email: Option<String>,
+    /// kept if missing, removed if empty
+    #[serde(rename(serialize = "displayName", deserialize = "displayName"))]
+    pub display_name: Option<String>,
     #[serde(rename(serialize = "isDisabled", deserialize = "isDisabled"))]
     pub is_disabled: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct UserNotesReq {
+    /// removed if missing or empty
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct UserPinReq {
+    /// new PIN of 4 to 12 digits, removes the PIN if missing
+    pub pin: Option<String>,
+    /// required if a PIN is set already, except for admins
+    #[serde(rename(serialize = "currentPin", deserialize = "currentPin"))]
+    pub current_pin: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct UserTokenAddReq {
+    pub uid: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct ApiTokenAddReq {
+    pub name: String,
+    /// one of read, book or admin
+    pub scope: String,
+    /// local time as `YYYY-MM-DD HH:MM:SS`, never if missing
+    pub expires: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct ArticleAddReq {
     pub name: String,
     pub barcode: Option<String>,
     pub amount: i32,
+    /// deposit per unit, 0 if missing; an update keeps the previous one if missing
+    pub deposit: Option<i32>,
+    /// names of the categories, created if unknown; an update keeps the previous ones if missing
+    pub categories: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct TransactionAddReq {
     pub amount: Option<f32>,
     pub quantity: Option<i32>,
@@ -148,46 +380,189 @@ pub struct TransactionAddReq {
     pub recipient_id: Option<i32>,
     #[serde(rename(serialize = "articleId", deserialize = "articleId"))]
     pub article_id: Option<i32>,
+    /// returns `quantity` bottles of the article and credits their deposit
+    #[serde(rename(serialize = "returnDeposit", deserialize = "returnDeposit"))]
+    pub return_deposit: Option<bool>,
+    /// alternative to the `Idempotency-Key` header
+    #[serde(rename(serialize = "idempotencyKey", deserialize = "idempotencyKey"))]
+    pub idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct CartLine {
+    #[serde(rename(serialize = "articleId", deserialize = "articleId"))]
+    pub article_id: i32,
+    pub quantity: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct CartReq {
+    pub lines: Vec<CartLine>,
+    pub comment: Option<String>,
+    /// alternative to the `Idempotency-Key` header
+    #[serde(rename(serialize = "idempotencyKey", deserialize = "idempotencyKey"))]
+    pub idempotency_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct ScheduledTransactionAddReq {
+    pub amount: i32,
+    pub comment: Option<String>,
+    /// one of daily, weekly or monthly
+    pub interval: String,
+    /// first run as local time `YYYY-MM-DD HH:MM:SS`, now if missing
+    pub start: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct PriceChangeAddReq {
+    pub amount: i32,
+    /// local time as `YYYY-MM-DD HH:MM:SS`, has to be in the future
+    pub activates: String,
 }
 
 //
 // response objects
 //
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct UsersResp {
     pub count: usize,
     pub users: Vec<UserEntity>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct UserResp {
     pub user: UserEntity,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct UserNotesResp {
+    #[serde(rename(serialize = "userNotes", deserialize = "userNotes"))]
+    pub user_notes: UserNotesEntity,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct UserTokenResp {
+    pub token: UserTokenEntity,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct UserTokensResp {
+    pub count: usize,
+    pub tokens: Vec<UserTokenEntity>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct ApiTokenResp {
+    pub token: ApiTokenEntity,
+    /// the token to send as `Authorization: Bearer`, only returned on creation
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct ApiTokensResp {
+    pub count: usize,
+    pub tokens: Vec<ApiTokenEntity>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct ArticleResp {
     pub article: ArticleObject,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct ArticlesResp {
     pub count: usize,
     pub articles: Vec<ArticleObject>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct PendingTransactionResp {
+    #[serde(rename(serialize = "pendingTransaction", deserialize = "pendingTransaction"))]
+    pub pending_transaction: PendingTransactionEntity,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct PendingTransactionsResp {
+    pub count: usize,
+    #[serde(rename(serialize = "pendingTransactions", deserialize = "pendingTransactions"))]
+    pub pending_transactions: Vec<PendingTransactionEntity>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct ScheduledTransactionResp {
+    #[serde(rename(serialize = "scheduledTransaction", deserialize = "scheduledTransaction"))]
+    pub scheduled_transaction: ScheduledTransactionEntity,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct ScheduledTransactionsResp {
+    pub count: usize,
+    #[serde(rename(serialize = "scheduledTransactions", deserialize = "scheduledTransactions"))]
+    pub scheduled_transactions: Vec<ScheduledTransactionEntity>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct ArticleHistoryResp {
+    pub count: usize,
+    pub versions: Vec<ArticleVersion>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct PriceChangeResp {
+    #[serde(rename(serialize = "priceChange", deserialize = "priceChange"))]
+    pub price_change: PriceChangeEntity,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct PriceChangesResp {
+    pub count: usize,
+    #[serde(rename(serialize = "priceChanges", deserialize = "priceChanges"))]
+    pub price_changes: Vec<PriceChangeEntity>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct TransactionResp {
     pub transaction: TransactionObject,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Bookings of a cart, lines of the same article are booked as one transaction
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct CartReceipt {
+    /// user after the checkout
+    pub user: UserEntity,
+    pub transactions: Vec<TransactionObject>,
+    /// sum of the prices
+    pub amount: i32,
+    /// sum of the deposits
+    pub deposit: i32,
+    pub total: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct CartResp {
+    pub receipt: CartReceipt,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct TransactionsResp {
     pub count: usize,
     pub transactions: Vec<TransactionObject>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct CategoriesResp {
+    pub count: usize,
+    pub categories: Vec<CategoryEntity>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+pub struct BackupResp {
+    pub file: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct SystemMetrics {
     pub balance: i32,
     #[serde(rename(serialize = "transactionCount", deserialize = "transactionCount"))]
@@ -195,26 +570,51 @@ pub struct SystemMetrics {
     #[serde(rename(serialize = "userCount", deserialize = "userCount"))]
     pub user_count: i32,
     pub articles: Vec<ArticleObject>,
+    pub categories: Vec<CategoryStats>,
+    pub deposits: DepositStats,
     pub days: Vec<DailyTransaction>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// Deposits charged and credited back, to reconcile the crates
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct DepositStats {
+    pub charged: i32,
+    #[serde(rename(serialize = "chargedQuantity", deserialize = "chargedQuantity"))]
+    pub charged_quantity: i32,
+    pub returned: i32,
+    #[serde(rename(serialize = "returnedQuantity", deserialize = "returnedQuantity"))]
+    pub returned_quantity: i32,
+    /// deposit of the bottles not returned yet
+    pub outstanding: i32,
+}
+
+/// Purchases of the articles of a category
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema, sqlx::FromRow)]
+pub struct CategoryStats {
+    pub category: String,
+    /// number of purchases
+    pub count: i32,
+    pub amount: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct UserArticles {
     pub article: ArticleObject,
     pub count: i32,
     pub amount: i32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct UserTransactions {
     pub count: i32,
     pub outgoing: TransactionStatsEntity,
     pub incoming: TransactionStatsEntity,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct UserMetrics {
     pub balance: i32,
     pub articles: Vec<UserArticles>,
+    pub categories: Vec<CategoryStats>,
     pub transactions: UserTransactions,
 }
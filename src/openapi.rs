@@ -0,0 +1,714 @@
+use std::sync::Arc;
+
+use schemars::{gen::SchemaGenerator, gen::SchemaSettings, schema::Schema, JsonSchema};
+use serde_json::{json, Map, Value};
+
+use crate::{model, settings};
+
+/// Content of a request or response body
+enum Body {
+    None,
+    Json(Schema),
+    Other(&'static str, &'static str),
+}
+
+/// A documented route, the paths are relative to `/api`
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    /// query parameters as name, type and description
+    query: &'static [(&'static str, &'static str, &'static str)],
+    /// header parameters as name and description
+    headers: &'static [(&'static str, &'static str)],
+    request: Body,
+    response: Body,
+    /// response if the request was accepted for later processing
+    accepted: Body,
+    /// status codes returned on errors besides 200
+    errors: &'static [u16],
+}
+
+fn json_schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Body {
+    Body::Json(gen.subschema_for::<T>())
+}
+
+fn operations(gen: &mut SchemaGenerator) -> Vec<Operation> {
+    vec![
+        Operation {
+            method: "get",
+            path: "/settings",
+            summary: "Settings for the frontend",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<settings::SettingsResp>(gen),
+            accepted: Body::None,
+            errors: &[],
+        },
+        Operation {
+            method: "get",
+            path: "/user",
+            summary: "List users",
+            query: &[
+                (
+                    "deleted",
+                    "boolean",
+                    "list disabled users instead, default false",
+                ),
+                (
+                    "active",
+                    "boolean",
+                    "only active or inactive users, both if missing",
+                ),
+            ],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::UsersResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user",
+            summary: "Create a user",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::UserAddReq>(gen),
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 409, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/search",
+            summary: "Search users by name",
+            query: &[
+                ("query", "string", "part of the name"),
+                ("limit", "integer", "maximum number of users, default 25"),
+            ],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::UsersResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/{userId}",
+            summary: "Get a user",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user/{userId}",
+            summary: "Update a user, the display name is kept if missing",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::UserUpdateReq>(gen),
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 409, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user/{userId}/pin",
+            summary: "Change or remove the PIN required for bookings of the user, the first one is set by an admin",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::UserPinReq>(gen),
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 403, 404, 429, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/{userId}/token",
+            summary: "List the NFC or RFID cards of a user",
+            query: &[],
+            headers: &[("X-User-Pin", "PIN of the user, required if one is set")],
+            request: Body::None,
+            response: json_schema::<model::UserTokensResp>(gen),
+            accepted: Body::None,
+            errors: &[403, 404, 429, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user/{userId}/token",
+            summary: "Register a card of a user",
+            query: &[],
+            headers: &[("X-User-Pin", "PIN of the user, required if one is set")],
+            request: json_schema::<model::UserTokenAddReq>(gen),
+            response: json_schema::<model::UserTokenResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 403, 404, 409, 429, 500],
+        },
+        Operation {
+            method: "delete",
+            path: "/user/{userId}/token/{tokenId}",
+            summary: "Remove a card of a user",
+            query: &[],
+            headers: &[("X-User-Pin", "PIN of the user, required if one is set")],
+            request: Body::None,
+            response: json_schema::<model::UserTokenResp>(gen),
+            accepted: Body::None,
+            errors: &[403, 404, 429, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/token/{uid}",
+            summary: "Find the user a card belongs to",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/{userId}/avatar",
+            summary: "Avatar image of a user",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: Body::Other("image/*", "binary"),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user/{userId}/avatar",
+            summary: "Upload the avatar of a user as PNG, JPEG, GIF or WebP, limited to backend.avatar.maxSize bytes",
+            query: &[],
+            headers: &[],
+            request: Body::Other("image/*", "binary"),
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 413, 500],
+        },
+        Operation {
+            method: "delete",
+            path: "/user/{userId}/avatar",
+            summary: "Remove the avatar of a user",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/{userId}/transaction",
+            summary: "List the transactions of a user, newest first",
+            query: &[
+                ("limit", "integer", "default 5"),
+                ("offset", "integer", "default 0"),
+            ],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::TransactionsResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user/{userId}/transaction",
+            summary: "Book a value, an article, returned bottles or a transfer to another user, bookings beyond the balance boundary are held for approval if enabled",
+            query: &[],
+            headers: &[
+                (
+                    "Idempotency-Key",
+                    "repeated requests with the same key return the first transaction instead of booking again",
+                ),
+                ("X-User-Pin", "PIN of the user, required if one is set"),
+            ],
+            request: json_schema::<model::TransactionAddReq>(gen),
+            response: json_schema::<model::TransactionResp>(gen),
+            accepted: json_schema::<model::PendingTransactionResp>(gen),
+            errors: &[400, 403, 404, 429, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user/{userId}/cart",
+            summary: "Book several articles at once, the boundaries apply to the total, a cart beyond the balance boundary is held for approval as a whole if enabled",
+            query: &[],
+            headers: &[
+                (
+                    "Idempotency-Key",
+                    "repeated requests with the same key return the first receipt instead of booking again",
+                ),
+                ("X-User-Pin", "PIN of the user, required if one is set"),
+            ],
+            request: json_schema::<model::CartReq>(gen),
+            response: json_schema::<model::CartResp>(gen),
+            accepted: json_schema::<model::PendingTransactionsResp>(gen),
+            errors: &[400, 403, 404, 429, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/{userId}/scheduled",
+            summary: "List the scheduled transactions of a user",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::ScheduledTransactionsResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/user/{userId}/scheduled",
+            summary: "Schedule a transaction repeated daily, weekly or monthly",
+            query: &[],
+            headers: &[("X-User-Pin", "PIN of the user, required if one is set")],
+            request: json_schema::<model::ScheduledTransactionAddReq>(gen),
+            response: json_schema::<model::ScheduledTransactionResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 403, 404, 429, 500],
+        },
+        Operation {
+            method: "delete",
+            path: "/user/{userId}/scheduled/{scheduledId}",
+            summary: "Stop a scheduled transaction",
+            query: &[],
+            headers: &[("X-User-Pin", "PIN of the user, required if one is set")],
+            request: Body::None,
+            response: json_schema::<model::ScheduledTransactionResp>(gen),
+            accepted: Body::None,
+            errors: &[403, 404, 429, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/user/{userId}/metrics",
+            summary: "Statistics of a user",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::UserMetrics>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/article",
+            summary: "List articles",
+            query: &[
+                (
+                    "active",
+                    "boolean",
+                    "active or retired articles, default true",
+                ),
+                (
+                    "ancestor",
+                    "boolean",
+                    "include the precursors, default true",
+                ),
+                ("limit", "integer", "default 999"),
+                ("offset", "integer", "default 0"),
+                ("category", "string", "only articles of this category"),
+            ],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::ArticlesResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/article",
+            summary: "Create an article",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::ArticleAddReq>(gen),
+            response: json_schema::<model::ArticleResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 409, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/article/{articleId}",
+            summary: "Get an article",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::ArticleResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/article/{articleId}",
+            summary: "Update an article, which creates a successor of it",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::ArticleAddReq>(gen),
+            response: json_schema::<model::ArticleResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 409, 500],
+        },
+        Operation {
+            method: "delete",
+            path: "/article/{articleId}",
+            summary: "Retire an article",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::ArticleResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/article/{articleId}/history",
+            summary: "List all versions of an article with their validity and sales",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::ArticleHistoryResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/article/{articleId}/price",
+            summary: "List the price changes scheduled on any version of an article",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::PriceChangesResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/article/{articleId}/price",
+            summary: "Schedule a price change, which updates the article once it activates",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::PriceChangeAddReq>(gen),
+            response: json_schema::<model::PriceChangeResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 500],
+        },
+        Operation {
+            method: "delete",
+            path: "/article/price/{priceChangeId}",
+            summary: "Cancel a pending price change",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::PriceChangeResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/category",
+            summary: "List the categories of articles",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::CategoriesResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/metrics",
+            summary: "Statistics of the whole system",
+            query: &[(
+                "days",
+                "integer",
+                "number of days in the history, default 30",
+            )],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::SystemMetrics>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/events",
+            summary: "Stream of changes as server-sent events",
+            query: &[(
+                "user",
+                "string",
+                "comma separated user ids to limit the events to",
+            )],
+            headers: &[],
+            request: Body::None,
+            response: Body::Other("text/event-stream", "string"),
+            accepted: Body::None,
+            errors: &[400],
+        },
+        Operation {
+            method: "post",
+            path: "/admin/backup",
+            summary: "Write a backup into the backup directory",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::BackupResp>(gen),
+            accepted: Body::None,
+            errors: &[401, 404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/admin/backup",
+            summary: "Download a snapshot of the database",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: Body::Other("application/vnd.sqlite3", "binary"),
+            accepted: Body::None,
+            errors: &[401, 404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/admin/pending",
+            summary: "List the bookings held for approval",
+            query: &[(
+                "state",
+                "string",
+                "pending, approved, rejected or all, default pending",
+            )],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::PendingTransactionsResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 401, 404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/admin/pending/{pendingId}/approve",
+            summary: "Approve a held booking, which books it regardless of the boundaries",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::PendingTransactionResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 401, 404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/admin/pending/{pendingId}/reject",
+            summary: "Reject a held booking",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::PendingTransactionResp>(gen),
+            accepted: Body::None,
+            errors: &[401, 404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/admin/user/{userId}/notes",
+            summary: "Get the notes of the admins on a user",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::UserNotesResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/admin/user/{userId}/notes",
+            summary: "Replace the notes of the admins on a user, removed if missing or empty",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::UserNotesReq>(gen),
+            response: json_schema::<model::UserNotesResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/admin/user/{userId}/pin",
+            summary: "Set or remove the PIN of a user without the current one",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::UserPinReq>(gen),
+            response: json_schema::<model::UserResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/admin/token",
+            summary: "List the API tokens",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::ApiTokensResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "post",
+            path: "/admin/token",
+            summary: "Create an API token, its secret is only part of this response",
+            query: &[],
+            headers: &[],
+            request: json_schema::<model::ApiTokenAddReq>(gen),
+            response: json_schema::<model::ApiTokenResp>(gen),
+            accepted: Body::None,
+            errors: &[400, 404, 500],
+        },
+        Operation {
+            method: "delete",
+            path: "/admin/token/{tokenId}",
+            summary: "Revoke an API token",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: json_schema::<model::ApiTokenResp>(gen),
+            accepted: Body::None,
+            errors: &[404, 500],
+        },
+        Operation {
+            method: "get",
+            path: "/openapi.json",
+            summary: "This document",
+            query: &[],
+            headers: &[],
+            request: Body::None,
+            response: Body::Other("application/json", "object"),
+            accepted: Body::None,
+            errors: &[],
+        },
+    ]
+}
+
+fn error_description(status: u16) -> &'static str {
+    match status {
+        400 => "Invalid parameters",
+        401 => "Admin token wrong, or API token missing, unknown, revoked or expired",
+        403 => "Wrong or missing PIN of the user, or API token without the required scope",
+        404 => "Entity not found or request not processable",
+        409 => "Duplicate name, barcode or card",
+        413 => "Request body too large",
+        429 => "Rate limit exceeded, retry after the seconds in the Retry-After header",
+        _ => "Internal error",
+    }
+}
+
+fn content(body: &Body) -> Value {
+    match body {
+        Body::None => Value::Null,
+        Body::Json(schema) => json!({ "application/json": { "schema": schema } }),
+        Body::Other(media_type, "binary") => {
+            json!({ media_type.to_string(): { "schema": { "type": "string", "format": "binary" } } })
+        }
+        Body::Other(media_type, typ) => {
+            json!({ media_type.to_string(): { "schema": { "type": typ } } })
+        }
+    }
+}
+
+fn path_parameters(path: &str) -> Vec<Value> {
+    path.split('/')
+        .filter(|s| s.starts_with('{') && s.ends_with('}'))
+        .map(|s| {
+            // ids are integers, anything else like the card uid a string
+            let typ = match s.ends_with("Id}") {
+                true => "integer",
+                false => "string",
+            };
+            json!({
+                "name": &s[1..s.len() - 1],
+                "in": "path",
+                "required": true,
+                "schema": { "type": typ },
+            })
+        })
+        .collect()
+}
+
+/// Builds the OpenAPI 3 document of the API.
+pub fn spec() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let operations = operations(&mut gen);
+
+    let mut paths = Map::new();
+    for op in operations.iter() {
+        let mut parameters = path_parameters(op.path);
+        parameters.extend(op.query.iter().map(|(name, typ, description)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": false,
+                "description": description,
+                "schema": { "type": typ },
+            })
+        }));
+        parameters.extend(op.headers.iter().map(|(name, description)| {
+            json!({
+                "name": name,
+                "in": "header",
+                "required": false,
+                "description": description,
+                "schema": { "type": "string" },
+            })
+        }));
+
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            json!({ "description": "Success", "content": content(&op.response) }),
+        );
+        if !matches!(op.accepted, Body::None) {
+            responses.insert(
+                "202".to_string(),
+                json!({ "description": "Accepted", "content": content(&op.accepted) }),
+            );
+        }
+        // any route may be rate limited or need an API token with another scope
+        for status in op.errors.iter().chain([401, 403, 429].iter()) {
+            responses.insert(
+                status.to_string(),
+                json!({ "description": error_description(*status) }),
+            );
+        }
+
+        let mut operation = json!({
+            "summary": op.summary,
+            "parameters": parameters,
+            "responses": responses,
+        });
+        if !matches!(op.request, Body::None) {
+            operation["requestBody"] = json!({ "required": true, "content": content(&op.request) });
+        }
+
+        let path = format!("/api{}", op.path);
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[op.method] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "strichliste-rs",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        // requests without a token get the scope of `backend.apiTokens.anonymous`
+        "security": [{ "apiToken": [] }, {}],
+        "components": {
+            "schemas": gen.definitions(),
+            "securitySchemes": { "apiToken": { "type": "http", "scheme": "bearer" } },
+        },
+    })
+}
+
+pub async fn get_spec(spec: Arc<Value>) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(spec.as_ref()))
+}
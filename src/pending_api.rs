@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::Arc};
+
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::{
+    article_db,
+    error::{ClientError, DbError},
+    events::{Event, EventBus},
+    model::{self, json_reply, JsonReply},
+    pending_db,
+    settings::StrichlisteSetting,
+    transaction_db, user_db,
+};
+
+const STATES: &[&str] = &["pending", "approved", "rejected", "all"];
+
+pub async fn get_pending_transactions(
+    db: SqlitePool,
+    query: HashMap<String, String>,
+) -> Result<JsonReply<model::PendingTransactionsResp>, warp::Rejection> {
+    let state = query.get("state").map(|v| v.as_str()).unwrap_or("pending");
+    if !STATES.contains(&state) {
+        return Err(ClientError::ParameterInvalid(format!(
+            "State '{}' is none of {}.",
+            state,
+            STATES.join(", ")
+        ))
+        .into());
+    }
+
+    let pending_transactions = pending_db::get_pending_transactions(
+        &db,
+        match state {
+            "all" => None,
+            v => Some(v),
+        },
+    )
+    .await?;
+
+    Ok(json_reply(model::PendingTransactionsResp {
+        count: pending_transactions.len(),
+        pending_transactions,
+    }))
+}
+
+/// Books the pending transaction, the boundaries don't apply anymore.
+/// All lines of a held cart are booked together.
+/// Holds of a disabled user or of an article retired or repriced since are refused, they can only be rejected.
+pub async fn approve_pending_transaction(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    events: EventBus,
+    pending_id: i32,
+) -> Result<JsonReply<model::PendingTransactionResp>, warp::Rejection> {
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    let pending = pending_db::get_undecided_tx(&mut tx, pending_id).await?;
+    let lines = match pending.cart_pending_id {
+        Some(first) => pending_db::get_cart_tx(&mut tx, first).await?,
+        None => vec![pending.clone()],
+    };
+
+    let mut pending_transaction = pending;
+    let mut booked = vec![];
+    for line in lines.iter() {
+        let transaction = book_tx(&mut tx, &settings, line).await?;
+        let decided =
+            pending_db::decide_tx(&mut tx, line.id, "approved", Some(transaction.entity.id))
+                .await?;
+        if line.id == pending_id {
+            pending_transaction = decided;
+        }
+        booked.push(transaction);
+    }
+    if pending_transaction.cart_pending_id.is_some() {
+        let ids: Vec<i32> = booked.iter().map(|t| t.entity.id).collect();
+        transaction_db::link_cart_tx(&mut tx, &ids).await?;
+    }
+    let booked: Vec<Event> = booked.into_iter().map(Event::TransactionCreated).collect();
+    for event in booked.iter() {
+        events.queue_tx(&mut tx, event).await?;
+    }
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    for event in booked.into_iter() {
+        events.publish(event);
+    }
+
+    Ok(json_reply(model::PendingTransactionResp {
+        pending_transaction,
+    }))
+}
+
+/// Books a single held line the way it was requested.
+async fn book_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    settings: &StrichlisteSetting,
+    pending: &model::PendingTransactionEntity,
+) -> Result<model::TransactionObject, warp::Rejection> {
+    let user = user_db::get_user_tx(tx, settings, &pending.user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    if user.disabled {
+        return Err(stale("its user is disabled").into());
+    }
+    let quantity = pending.quantity.unwrap_or(1);
+
+    let transaction = match (pending.article_id, pending.recipient_id) {
+        (Some(article_id), _) if pending.return_deposit => {
+            let article = article_db::get_article_or_error_tx(tx, article_id).await?;
+            if !article.entity.active || article.entity.deposit * quantity != pending.amount {
+                return Err(stale("the deposit of its article changed").into());
+            }
+            transaction_db::add_deposit_return_tx(
+                tx,
+                user,
+                &quantity,
+                &pending.amount,
+                article,
+                pending.comment.as_deref(),
+            )
+            .await?
+        }
+        (Some(article_id), _) => {
+            let article = article_db::get_article_or_error_tx(tx, article_id).await?;
+            if !article.entity.active || article.entity.amount * quantity * -1 != pending.amount {
+                return Err(stale("the price of its article changed").into());
+            }
+            transaction_db::add_transaction_with_article_tx(
+                tx,
+                user,
+                &quantity,
+                &pending.amount,
+                article,
+                pending.comment.as_deref(),
+            )
+            .await?
+        }
+        (None, Some(recipient_id)) => {
+            let recipient = user_db::get_user_tx(tx, settings, &recipient_id)
+                .await?
+                .ok_or_else(|| DbError::EntityNotFound("Recipient".to_string()))?;
+            if recipient.disabled {
+                return Err(stale("its recipient is disabled").into());
+            }
+            transaction_db::add_transaction_with_recipient_tx(
+                tx,
+                user,
+                &pending.amount,
+                recipient,
+                pending.comment.as_deref(),
+            )
+            .await?
+        }
+        (None, None) => {
+            transaction_db::add_transaction_with_value_tx(
+                tx,
+                user,
+                &pending.amount,
+                pending.comment.as_deref(),
+            )
+            .await?
+        }
+    };
+
+    return Ok(transaction);
+}
+
+fn stale(reason: &str) -> ClientError {
+    ClientError::ParameterInvalid(format!(
+        "The held booking can't be approved anymore, {}.",
+        reason
+    ))
+}
+
+/// Rejects the pending transaction, together with the other lines of a held cart.
+pub async fn reject_pending_transaction(
+    db: SqlitePool,
+    pending_id: i32,
+) -> Result<JsonReply<model::PendingTransactionResp>, warp::Rejection> {
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    let pending = pending_db::get_undecided_tx(&mut tx, pending_id).await?;
+    if let Some(first) = pending.cart_pending_id {
+        for line in pending_db::get_cart_tx(&mut tx, first).await?.iter() {
+            if line.id != pending_id {
+                pending_db::decide_tx(&mut tx, line.id, "rejected", None).await?;
+            }
+        }
+    }
+    let pending_transaction = pending_db::decide_tx(&mut tx, pending_id, "rejected", None).await?;
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    Ok(json_reply(model::PendingTransactionResp {
+        pending_transaction,
+    }))
+}
@@ -0,0 +1,235 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::{error::DbError, model};
+
+pub async fn add_pending_transaction_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    article_id: Option<i32>,
+    quantity: Option<i32>,
+    amount: &i32,
+    comment: Option<&str>,
+) -> std::result::Result<model::PendingTransactionEntity, DbError> {
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "INSERT INTO pending_transaction (user_id, article_id, quantity, comment, amount, state, created)
+        VALUES (?, ?, ?, ?, ?, 'pending', datetime('now', 'localtime'));
+
+        SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction WHERE id = last_insert_rowid();",
+    )
+    .bind(user_id)
+    .bind(article_id)
+    .bind(quantity)
+    .bind(comment)
+    .bind(amount)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(pending);
+}
+
+pub async fn add_pending_deposit_return_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    article_id: i32,
+    quantity: i32,
+    amount: &i32,
+    comment: Option<&str>,
+) -> std::result::Result<model::PendingTransactionEntity, DbError> {
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "INSERT INTO pending_transaction (user_id, article_id, quantity, return_deposit, comment, amount, state, created)
+        VALUES (?, ?, ?, TRUE, ?, ?, 'pending', datetime('now', 'localtime'));
+
+        SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction WHERE id = last_insert_rowid();",
+    )
+    .bind(user_id)
+    .bind(article_id)
+    .bind(quantity)
+    .bind(comment)
+    .bind(amount)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(pending);
+}
+
+pub async fn add_pending_transfer_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    amount: &i32,
+    recipient_id: i32,
+    comment: Option<&str>,
+) -> std::result::Result<model::PendingTransactionEntity, DbError> {
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "INSERT INTO pending_transaction (user_id, recipient_id, comment, amount, state, created)
+        VALUES (?, ?, ?, ?, 'pending', datetime('now', 'localtime'));
+
+        SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction WHERE id = last_insert_rowid();",
+    )
+    .bind(user_id)
+    .bind(recipient_id)
+    .bind(comment)
+    .bind(amount)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(pending);
+}
+
+/// Marks the lines of a held cart as decided on together, by the id of the first one.
+pub async fn link_cart_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    pending_ids: &[i32],
+) -> std::result::Result<(), DbError> {
+    let first = match pending_ids.first() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    for id in pending_ids.iter() {
+        sqlx::query("UPDATE pending_transaction SET cart_pending_id = ? WHERE id = ?")
+            .bind(first)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    return Ok(());
+}
+
+/// Lines of the held cart starting with `first_id`, in booking order
+pub async fn get_cart_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    first_id: i32,
+) -> std::result::Result<Vec<model::PendingTransactionEntity>, DbError> {
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction
+        WHERE cart_pending_id = ?
+        ORDER BY id",
+    )
+    .bind(first_id)
+    .fetch_all(tx)
+    .await?;
+
+    return Ok(pending);
+}
+
+/// The latest pending transaction of the user held with `key` within the window
+pub async fn get_idempotent_pending_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> std::result::Result<Option<model::PendingTransactionEntity>, DbError> {
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction
+        WHERE user_id = ? AND idempotency_key = ? AND created >= datetime('now', 'localtime', ?)
+        ORDER BY created DESC, id DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(format!("-{} seconds", window_secs))
+    .fetch_optional(tx)
+    .await?;
+
+    return Ok(pending);
+}
+
+/// Stores the key on the held booking, keys older than the window are released first.
+/// Fails with DuplicateKey if a concurrent request holds the key already.
+pub async fn set_idempotency_key_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    pending_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> std::result::Result<(), DbError> {
+    let result = sqlx::query(
+        "UPDATE pending_transaction SET idempotency_key = NULL
+        WHERE user_id = ? AND idempotency_key = ? AND created < datetime('now', 'localtime', ?);
+
+        UPDATE pending_transaction SET idempotency_key = ? WHERE id = ?",
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(format!("-{} seconds", window_secs))
+    .bind(key)
+    .bind(pending_id)
+    .execute(tx)
+    .await;
+
+    return match result {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(e)) if e.message().contains("UNIQUE constraint failed") => {
+            Err(DbError::DuplicateKey("Idempotency key".to_string()))
+        }
+        Err(e) => Err(e.into()),
+    };
+}
+
+/// Pending transactions in `state`, or all if None, oldest first
+pub async fn get_pending_transactions(
+    db: &SqlitePool,
+    state: Option<&str>,
+) -> std::result::Result<Vec<model::PendingTransactionEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction
+        WHERE ? IS NULL OR state = ?
+        ORDER BY created, id",
+    )
+    .bind(state)
+    .bind(state)
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(pending);
+}
+
+/// The pending transaction if no decision was made on it yet
+pub async fn get_undecided_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    pending_id: i32,
+) -> std::result::Result<model::PendingTransactionEntity, DbError> {
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction
+        WHERE id = ? AND state = 'pending'",
+    )
+    .bind(pending_id)
+    .fetch_optional(tx)
+    .await?
+    .ok_or_else(|| DbError::EntityNotFound("Pending transaction".to_string()))?;
+
+    return Ok(pending);
+}
+
+/// Records the decision, `transaction_id` is the booking created on approval.
+pub async fn decide_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    pending_id: i32,
+    state: &str,
+    transaction_id: Option<i32>,
+) -> std::result::Result<model::PendingTransactionEntity, DbError> {
+    let pending = sqlx::query_as::<_, model::PendingTransactionEntity>(
+        "UPDATE pending_transaction
+        SET state = ?, transaction_id = ?, decided = datetime('now', 'localtime')
+        WHERE id = ?;
+
+        SELECT id, user_id, article_id, quantity, recipient_id, return_deposit, cart_pending_id, comment, amount, state, transaction_id, created, decided
+        FROM pending_transaction WHERE id = ?;",
+    )
+    .bind(state)
+    .bind(transaction_id)
+    .bind(pending_id)
+    .bind(pending_id)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(pending);
+}
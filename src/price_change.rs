@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use log::{error, info, warn};
+use sqlx::SqlitePool;
+
+use crate::{
+    error::DbError,
+    events::{Event, EventBus},
+    price_change_db,
+};
+
+const PRICE_CHANGE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Applies the scheduled price changes once they are due.
+/// Changes that became due while the server was down are applied with the first check.
+pub async fn run_price_changes(db: SqlitePool, events: EventBus) {
+    let mut interval = tokio::time::interval(PRICE_CHANGE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = apply_due_price_changes(&db, &events).await {
+            error!("Applying price changes failed: {}", e);
+        }
+    }
+}
+
+pub async fn apply_due_price_changes(db: &SqlitePool, events: &EventBus) -> Result<(), DbError> {
+    for price_change in price_change_db::get_due_price_changes(db).await? {
+        match price_change_db::apply_price_change(db, &price_change).await? {
+            Some(article) => {
+                info!(
+                    "Price change {} set article {} to {}",
+                    price_change.id, article.entity.id, price_change.amount
+                );
+                events
+                    .publish_committed(db, Event::ArticleUpdated(article))
+                    .await;
+            }
+            None => warn!(
+                "Price change {} failed, article {} was retired",
+                price_change.id, price_change.article_id
+            ),
+        }
+    }
+
+    return Ok(());
+}
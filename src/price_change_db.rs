@@ -0,0 +1,153 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::{article_db, error::DbError, model};
+
+pub async fn add_price_change(
+    db: &SqlitePool,
+    article_id: i32,
+    amount: i32,
+    activates: &str,
+) -> std::result::Result<model::PriceChangeEntity, DbError> {
+    let mut tx = db.begin().await?;
+    let price_change = sqlx::query_as::<_, model::PriceChangeEntity>(
+        "INSERT INTO article_price_change (article_id, amount, activates, state, created)
+        VALUES (?, ?, ?, 'pending', datetime('now', 'localtime'));
+
+        SELECT id, article_id, amount, activates, state, applied_article_id, created
+        FROM article_price_change WHERE id = last_insert_rowid();",
+    )
+    .bind(article_id)
+    .bind(amount)
+    .bind(activates)
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(price_change);
+}
+
+/// Price changes scheduled on any version of the article, latest activation first
+pub async fn get_price_changes(
+    db: &SqlitePool,
+    article_id: i32,
+) -> std::result::Result<Vec<model::PriceChangeEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let price_changes = sqlx::query_as::<_, model::PriceChangeEntity>(
+        "WITH successors AS (
+			SELECT id FROM article WHERE id = ?
+			UNION
+			SELECT a.id FROM article AS a
+				INNER JOIN successors AS s ON a.precursor_id = s.id
+		), versions AS (
+			SELECT id, precursor_id FROM article WHERE id = (SELECT MAX(id) FROM successors)
+			UNION
+			SELECT a.id, a.precursor_id FROM article AS a
+				INNER JOIN versions AS v ON a.id = v.precursor_id
+		)
+		SELECT id, article_id, amount, activates, state, applied_article_id, created
+		FROM article_price_change
+		WHERE article_id IN (SELECT id FROM versions)
+		ORDER BY activates DESC, id DESC",
+    )
+    .bind(article_id)
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(price_changes);
+}
+
+/// Cancels the price change if it is still pending.
+pub async fn cancel_price_change(
+    db: &SqlitePool,
+    price_change_id: i32,
+) -> std::result::Result<model::PriceChangeEntity, DbError> {
+    let mut tx = db.begin().await?;
+    let price_change = sqlx::query_as::<_, model::PriceChangeEntity>(
+        "UPDATE article_price_change SET state = 'cancelled'
+        WHERE id = ? AND state = 'pending';
+
+        SELECT id, article_id, amount, activates, state, applied_article_id, created
+        FROM article_price_change WHERE id = ? AND changes() > 0;",
+    )
+    .bind(price_change_id)
+    .bind(price_change_id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| DbError::EntityNotFound("Pending price change".to_string()))?;
+    tx.commit().await?;
+
+    return Ok(price_change);
+}
+
+/// Returns the pending price changes which are due, oldest activation first.
+pub async fn get_due_price_changes(
+    db: &SqlitePool,
+) -> std::result::Result<Vec<model::PriceChangeEntity>, DbError> {
+    let price_changes = sqlx::query_as::<_, model::PriceChangeEntity>(
+        "SELECT id, article_id, amount, activates, state, applied_article_id, created
+        FROM article_price_change
+        WHERE state = 'pending' AND activates <= datetime('now', 'localtime')
+        ORDER BY activates, id",
+    )
+    .fetch_all(db)
+    .await?;
+
+    return Ok(price_changes);
+}
+
+/// Updates the active version of the article to the new price, returns None if the article was retired.
+pub async fn apply_price_change(
+    db: &SqlitePool,
+    price_change: &model::PriceChangeEntity,
+) -> std::result::Result<Option<model::ArticleObject>, DbError> {
+    let mut tx = db.begin().await?;
+    let latest_id = article_db::get_latest_version_tx(&mut tx, price_change.article_id).await?;
+    let article = match latest_id {
+        Some(id) => {
+            let latest = article_db::get_article_or_error_tx(&mut tx, id).await?;
+            Some(
+                article_db::update_article_tx(
+                    &mut tx,
+                    id,
+                    &latest.entity.name,
+                    latest.entity.barcode.as_deref(),
+                    price_change.amount,
+                    None,
+                    None,
+                )
+                .await?,
+            )
+        }
+        None => None,
+    };
+    set_state_tx(
+        &mut tx,
+        price_change.id,
+        match article {
+            Some(_) => "applied",
+            None => "failed",
+        },
+        article.as_ref().map(|a| a.entity.id),
+    )
+    .await?;
+    tx.commit().await?;
+
+    return Ok(article);
+}
+
+async fn set_state_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    price_change_id: i32,
+    state: &str,
+    applied_article_id: Option<i32>,
+) -> std::result::Result<(), DbError> {
+    sqlx::query("UPDATE article_price_change SET state = ?, applied_article_id = ? WHERE id = ?")
+        .bind(state)
+        .bind(applied_article_id)
+        .bind(price_change_id)
+        .execute(tx)
+        .await?;
+
+    return Ok(());
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use log::warn;
+use warp::{http::Method, path::FullPath, Filter, Rejection};
+
+use crate::{
+    common,
+    error::ClientError,
+    settings::{BucketSetting, RateLimitSetting},
+};
+
+/// Number of buckets above which the refilled ones are dropped
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    User(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Read,
+    Write,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, settings: &BucketSetting, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * settings.rate).min(settings.burst as f64);
+        self.updated = now;
+    }
+
+    fn is_full(&self, settings: &BucketSetting, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * settings.rate >= settings.burst as f64
+    }
+}
+
+/// Token buckets per client address and per user, each with a budget for reads and one for writes.
+struct RateLimiter {
+    settings: RateLimitSetting,
+    buckets: Mutex<HashMap<(Client, Kind), Bucket>>,
+}
+
+impl RateLimiter {
+    fn bucket_setting(&self, client: &Client, kind: Kind) -> &BucketSetting {
+        let budget = match client {
+            Client::Ip(_) => &self.settings.per_ip,
+            Client::User(_) => &self.settings.per_user,
+        };
+        match kind {
+            Kind::Read => &budget.read,
+            Kind::Write => &budget.write,
+        }
+    }
+
+    /// Takes a token from every bucket of the request, or none if one of them is empty.
+    /// Returns the seconds to wait in that case.
+    fn check(&self, clients: &[Client], kind: Kind) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|(client, kind), bucket| {
+                !bucket.is_full(self.bucket_setting(client, *kind), now)
+            });
+        }
+
+        let mut wait: f64 = 0.0;
+        for client in clients.iter() {
+            let settings = self.bucket_setting(client, kind);
+            if settings.burst == 0 {
+                continue;
+            }
+            let bucket = buckets.entry((*client, kind)).or_insert(Bucket {
+                tokens: settings.burst as f64,
+                updated: now,
+            });
+            bucket.refill(settings, now);
+            if bucket.tokens < 1.0 {
+                wait = wait.max((1.0 - bucket.tokens) / settings.rate);
+            }
+        }
+        if wait > 0.0 {
+            return Err(wait.ceil().max(1.0) as u64);
+        }
+
+        for client in clients.iter() {
+            if let Some(bucket) = buckets.get_mut(&(*client, kind)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Rejects requests exceeding the budget of their client address or user with 429.
+pub fn limit(settings: RateLimitSetting) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let limiter = Arc::new(RateLimiter {
+        settings,
+        buckets: Mutex::new(HashMap::new()),
+    });
+
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and_then(
+            move |method: Method,
+                  path: FullPath,
+                  remote: Option<SocketAddr>,
+                  forwarded: Option<String>| {
+                let limiter = limiter.clone();
+                async move {
+                    if !limiter.settings.enabled {
+                        return Ok(());
+                    }
+
+                    let kind = match method {
+                        Method::GET | Method::HEAD | Method::OPTIONS => Kind::Read,
+                        _ => Kind::Write,
+                    };
+                    let ip = match limiter.settings.trust_proxy {
+                        true => forwarded_for(forwarded.as_deref()).or(remote.map(|v| v.ip())),
+                        false => remote.map(|v| v.ip()),
+                    };
+                    let clients: Vec<Client> = ip
+                        .map(Client::Ip)
+                        .into_iter()
+                        .chain(common::path_user_id(path.as_str()).map(Client::User))
+                        .collect();
+
+                    limiter.check(&clients, kind).map_err(|secs| {
+                        warn!(
+                            "Rate limit of {:?} exceeded by {} {}",
+                            clients,
+                            method,
+                            path.as_str()
+                        );
+                        Rejection::from(ClientError::TooManyRequests(secs))
+                    })
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// The original client, the first address of the header
+fn forwarded_for(header: Option<&str>) -> Option<IpAddr> {
+    header?.split(',').next()?.trim().parse().ok()
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use log::{error, info, warn};
+use sqlx::SqlitePool;
+
+use crate::{
+    common,
+    error::DbError,
+    events::{Event, EventBus},
+    model, schedule_db,
+    settings::StrichlisteSetting,
+    transaction_db, user_db,
+};
+
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub const INTERVALS: [&str; 3] = ["daily", "weekly", "monthly"];
+
+/// The run after `next_run`, None if the interval is unknown.
+/// Monthly runs are counted from `first_run`, so the 31st falls on the last day of shorter months.
+pub fn following_run(interval: &str, first_run: &str, next_run: &str) -> Option<String> {
+    let first = NaiveDateTime::parse_from_str(first_run, common::DATETIME_FORMAT).ok()?;
+    let next = NaiveDateTime::parse_from_str(next_run, common::DATETIME_FORMAT).ok()?;
+    let following = match interval {
+        "daily" => next + chrono::Duration::days(1),
+        "weekly" => next + chrono::Duration::weeks(1),
+        "monthly" => {
+            let months = next.year() * 12 + next.month0() as i32 + 1;
+            let (year, month) = (months.div_euclid(12), months.rem_euclid(12) as u32 + 1);
+            let date = (1..=first.day())
+                .rev()
+                .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))?;
+            date.and_time(first.time())
+        }
+        _ => return None,
+    };
+
+    return Some(following.format(common::DATETIME_FORMAT).to_string());
+}
+
+/// Books the scheduled transactions once they are due.
+/// Runs missed while the server was down are booked one by one with the first check.
+pub async fn run_scheduled_transactions(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    events: EventBus,
+) {
+    let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = book_due_transactions(&db, &settings, &events).await {
+            error!("Booking scheduled transactions failed: {}", e);
+        }
+    }
+}
+
+pub async fn book_due_transactions(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    events: &EventBus,
+) -> Result<(), DbError> {
+    // every run moves the next one forward, so this ends once all are caught up,
+    // failed ones are left due and retried with the next check
+    let mut failed = HashSet::new();
+    loop {
+        let due: Vec<_> = schedule_db::get_due_scheduled_transactions(db)
+            .await?
+            .into_iter()
+            .filter(|v| !failed.contains(&v.id))
+            .collect();
+        if due.is_empty() {
+            return Ok(());
+        }
+        for scheduled in due.iter() {
+            match book(db, settings, events, scheduled).await {
+                Ok(Some(event)) => events.publish(event),
+                Ok(None) => (),
+                Err(e) => {
+                    error!(
+                        "Booking scheduled transaction {} of user {} due {} failed: {}",
+                        scheduled.id, scheduled.user_id, scheduled.next_run, e
+                    );
+                    failed.insert(scheduled.id);
+                    schedule_db::set_error(db, scheduled.id, &e.to_string()).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Books a single run, or skips it if the user can't be charged.
+/// Returns the event of the booking to publish once it is committed.
+async fn book(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    events: &EventBus,
+    scheduled: &model::ScheduledTransactionEntity,
+) -> Result<Option<Event>, DbError> {
+    let following = following_run(
+        &scheduled.interval,
+        &scheduled.first_run,
+        &scheduled.next_run,
+    )
+    .ok_or_else(|| {
+        sqlx::Error::Protocol(format!("Interval '{}' is unknown.", scheduled.interval))
+    })?;
+
+    let mut tx = db.begin().await?;
+    let user = user_db::get_user_tx(&mut tx, settings, &scheduled.user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    let new_balance = user.balance + scheduled.amount;
+    let problem = if user.disabled {
+        Some("User is disabled.".to_string())
+    } else if new_balance < settings.account.boundary.lower
+        || new_balance > settings.account.boundary.upper
+    {
+        Some(format!(
+            "Balance {} would be out of the allowed boundary.",
+            new_balance
+        ))
+    } else if scheduled.amount < settings.payment.boundary.lower
+        || scheduled.amount > settings.payment.boundary.upper
+    {
+        Some(format!(
+            "Amount {} is out of the allowed payment boundary.",
+            scheduled.amount
+        ))
+    } else {
+        None
+    };
+
+    let event = match problem {
+        Some(problem) => {
+            warn!(
+                "Skipped scheduled transaction {} of user {} due {}: {}",
+                scheduled.id, scheduled.user_id, scheduled.next_run, problem
+            );
+            schedule_db::advance_tx(&mut tx, scheduled.id, &following, Some(&problem)).await?;
+            None
+        }
+        None => {
+            let transaction = transaction_db::add_transaction_with_value_tx(
+                &mut tx,
+                user,
+                &scheduled.amount,
+                scheduled.comment.as_deref(),
+            )
+            .await?;
+            schedule_db::advance_tx(&mut tx, scheduled.id, &following, None).await?;
+            info!(
+                "Booked scheduled transaction {} of user {} due {}",
+                scheduled.id, scheduled.user_id, scheduled.next_run
+            );
+            let event = Event::TransactionCreated(transaction);
+            events.queue_tx(&mut tx, &event).await?;
+            Some(event)
+        }
+    };
+    tx.commit().await?;
+
+    return Ok(event);
+}
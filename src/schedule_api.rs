@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use sqlx::SqlitePool;
+
+use crate::{
+    common, credential_api,
+    error::{ClientError, DbError},
+    model::{self, json_reply, JsonReply},
+    schedule, schedule_db,
+    settings::StrichlisteSetting,
+    user_db,
+};
+
+pub async fn get_scheduled_transactions(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    user_id: i32,
+) -> Result<JsonReply<model::ScheduledTransactionsResp>, warp::Rejection> {
+    user_db::get_user(&db, &settings, &user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    let scheduled_transactions = schedule_db::get_scheduled_transactions(&db, user_id).await?;
+
+    Ok(json_reply(model::ScheduledTransactionsResp {
+        count: scheduled_transactions.len(),
+        scheduled_transactions,
+    }))
+}
+
+pub async fn add_scheduled_transaction(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    user_id: i32,
+    pin: Option<String>,
+    req: model::ScheduledTransactionAddReq,
+) -> Result<JsonReply<model::ScheduledTransactionResp>, warp::Rejection> {
+    credential_api::check_pin(&db, user_id, pin.as_deref()).await?;
+    if !schedule::INTERVALS.contains(&req.interval.as_str()) {
+        return Err(ClientError::ParameterInvalid(format!(
+            "Interval '{}' is none of daily, weekly or monthly.",
+            req.interval
+        ))
+        .into());
+    }
+    if req.amount == 0 {
+        return Err(ClientError::ParameterInvalid("Amount must not be 0.".to_string()).into());
+    }
+    let next_run = match &req.start {
+        Some(start) => common::parse_future_datetime(start, "Start")?,
+        None => common::cur_datetime_str(),
+    };
+    user_db::get_user(&db, &settings, &user_id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("User".to_string()))?;
+    let comment = req
+        .comment
+        .as_deref()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty());
+
+    let scheduled_transaction = schedule_db::add_scheduled_transaction(
+        &db,
+        user_id,
+        req.amount,
+        comment,
+        &req.interval,
+        &next_run,
+    )
+    .await?;
+
+    Ok(json_reply(model::ScheduledTransactionResp {
+        scheduled_transaction,
+    }))
+}
+
+pub async fn cancel_scheduled_transaction(
+    db: SqlitePool,
+    user_id: i32,
+    scheduled_id: i32,
+    pin: Option<String>,
+) -> Result<JsonReply<model::ScheduledTransactionResp>, warp::Rejection> {
+    credential_api::check_pin(&db, user_id, pin.as_deref()).await?;
+    let scheduled_transaction =
+        schedule_db::cancel_scheduled_transaction(&db, user_id, scheduled_id).await?;
+
+    Ok(json_reply(model::ScheduledTransactionResp {
+        scheduled_transaction,
+    }))
+}
@@ -0,0 +1,131 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::{error::DbError, model};
+
+pub async fn add_scheduled_transaction(
+    db: &SqlitePool,
+    user_id: i32,
+    amount: i32,
+    comment: Option<&str>,
+    interval: &str,
+    next_run: &str,
+) -> std::result::Result<model::ScheduledTransactionEntity, DbError> {
+    let mut tx = db.begin().await?;
+    let scheduled = sqlx::query_as::<_, model::ScheduledTransactionEntity>(
+        "INSERT INTO scheduled_transaction (user_id, amount, comment, interval, next_run, first_run, active, skipped, created)
+        VALUES (?, ?, ?, ?, ?, ?, TRUE, 0, datetime('now', 'localtime'));
+
+        SELECT id, user_id, amount, comment, interval, next_run, first_run, active, skipped, last_error, created
+        FROM scheduled_transaction WHERE id = last_insert_rowid();",
+    )
+    .bind(user_id)
+    .bind(amount)
+    .bind(comment)
+    .bind(interval)
+    .bind(next_run)
+    .bind(next_run)
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(scheduled);
+}
+
+pub async fn get_scheduled_transactions(
+    db: &SqlitePool,
+    user_id: i32,
+) -> std::result::Result<Vec<model::ScheduledTransactionEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let scheduled = sqlx::query_as::<_, model::ScheduledTransactionEntity>(
+        "SELECT id, user_id, amount, comment, interval, next_run, first_run, active, skipped, last_error, created
+        FROM scheduled_transaction
+        WHERE user_id = ?
+        ORDER BY active DESC, next_run, id",
+    )
+    .bind(user_id)
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(scheduled);
+}
+
+/// Stops an active scheduled transaction of the user, it is kept for reference.
+pub async fn cancel_scheduled_transaction(
+    db: &SqlitePool,
+    user_id: i32,
+    scheduled_id: i32,
+) -> std::result::Result<model::ScheduledTransactionEntity, DbError> {
+    let mut tx = db.begin().await?;
+    let scheduled = sqlx::query_as::<_, model::ScheduledTransactionEntity>(
+        "UPDATE scheduled_transaction SET active = FALSE
+        WHERE id = ? AND user_id = ? AND active IS TRUE;
+
+        SELECT id, user_id, amount, comment, interval, next_run, first_run, active, skipped, last_error, created
+        FROM scheduled_transaction WHERE id = ? AND changes() > 0;",
+    )
+    .bind(scheduled_id)
+    .bind(user_id)
+    .bind(scheduled_id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| DbError::EntityNotFound("Active scheduled transaction".to_string()))?;
+    tx.commit().await?;
+
+    return Ok(scheduled);
+}
+
+/// Returns the active scheduled transactions which are due, oldest run first.
+pub async fn get_due_scheduled_transactions(
+    db: &SqlitePool,
+) -> std::result::Result<Vec<model::ScheduledTransactionEntity>, DbError> {
+    let scheduled = sqlx::query_as::<_, model::ScheduledTransactionEntity>(
+        "SELECT id, user_id, amount, comment, interval, next_run, first_run, active, skipped, last_error, created
+        FROM scheduled_transaction
+        WHERE active IS TRUE AND next_run <= datetime('now', 'localtime')
+        ORDER BY next_run, id",
+    )
+    .fetch_all(db)
+    .await?;
+
+    return Ok(scheduled);
+}
+
+/// Moves the run to `next_run` and records whether this one was skipped.
+pub async fn advance_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    scheduled_id: i32,
+    next_run: &str,
+    skipped: Option<&str>,
+) -> std::result::Result<(), DbError> {
+    sqlx::query(
+        "UPDATE scheduled_transaction
+        SET next_run = ?,
+            skipped = skipped + (CASE WHEN ? IS NULL THEN 0 ELSE 1 END),
+            last_error = ?
+        WHERE id = ?",
+    )
+    .bind(next_run)
+    .bind(skipped)
+    .bind(skipped)
+    .bind(scheduled_id)
+    .execute(tx)
+    .await?;
+
+    return Ok(());
+}
+
+/// Records why a run could not be booked, it stays due and is retried with the next check.
+pub async fn set_error(
+    db: &SqlitePool,
+    scheduled_id: i32,
+    error: &str,
+) -> std::result::Result<(), DbError> {
+    sqlx::query("UPDATE scheduled_transaction SET last_error = ? WHERE id = ?")
+        .bind(error)
+        .bind(scheduled_id)
+        .execute(db)
+        .await?;
+
+    return Ok(());
+}
@@ -1,24 +1,419 @@
-use crate::common;
-use log::error;
+use log::{error, info};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
+use serde_yaml::Value;
+use std::{env, fs::File, net::ToSocketAddrs, path::Path, time::Duration};
 
+/// Separator of the path segments in settings environment variables, e.g. `STRICHLISTE__ACCOUNT__BOUNDARY__LOWER`
+const ENV_PATH_SEPARATOR: &str = "__";
+
+/// Loads the settings in three layers: built-in defaults, the YAML file and environment variables.
+/// The file is optional unless its location is given explicitly in `settings_env`.
 pub fn load_settings(
     settings_env: &str,
     settings_file_default: &str,
-) -> Result<StrichlisteSetting, std::io::Error> {
-    let settings_file = common::env_or(settings_env, settings_file_default);
-    let file = File::open(&settings_file)?;
-
-    return match serde_yaml::from_reader::<_, SettingsWrapper>(file) {
-        Ok(v) => Ok(v.parameters.strichliste),
-        Err(e) => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            format!("Failed to parse YAML data: {}", e),
-        )),
+) -> Result<Settings, std::io::Error> {
+    let (settings_file, file_required) = match env::var(settings_env) {
+        Ok(v) => (v, true),
+        Err(_) => (settings_file_default.to_string(), false),
+    };
+
+    let mut tree = serde_yaml::to_value(SettingsWrapper::default()).map_err(invalid_data)?;
+    match File::open(&settings_file) {
+        Ok(file) => {
+            let file_tree = serde_yaml::from_reader::<_, Value>(file)
+                .map_err(|e| invalid_data(format!("Failed to parse YAML data: {}", e)))?;
+            merge_values(&mut tree, file_tree);
+        }
+        Err(e) if !file_required && e.kind() == std::io::ErrorKind::NotFound => {
+            info!(
+                "Settings file {} not found, using defaults and environment.",
+                settings_file
+            );
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut problems = apply_env_overrides(&mut tree, env::vars());
+
+    let settings = match serde_yaml::from_value::<SettingsWrapper>(tree) {
+        Ok(v) => v.parameters,
+        Err(e) => return Err(invalid_data(format!("Failed to parse settings: {}", e))),
+    };
+
+    if let Err(mut p) = validate_settings(&settings) {
+        problems.append(&mut p);
+    }
+
+    return match problems.is_empty() {
+        true => Ok(settings),
+        false => Err(invalid_data(format!(
+            "Invalid settings in {} or environment:\n  - {}",
+            settings_file,
+            problems.join("\n  - ")
+        ))),
+    };
+}
+
+fn invalid_data<E: ToString>(e: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Recursively merges `other` into `base`, values in `other` take precedence.
+fn merge_values(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Mapping(base_map), Value::Mapping(other_map)) => {
+            for (k, v) in other_map {
+                match base_map.get_mut(&k) {
+                    Some(base_val) => merge_values(base_val, v),
+                    None => {
+                        base_map.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+/// Applies all environment variables whose first path segment names a section below `parameters`.
+/// Segments are matched case insensitive and ignoring underscores, so `STALE_PERIOD` matches `stalePeriod`.
+/// Values are parsed as YAML, so numbers, booleans and lists (`[50, 100]`) work as expected,
+/// except for string settings, which take the value as is, e.g. a numeric PayPal recipient.
+pub(crate) fn apply_env_overrides(
+    tree: &mut Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut vars: Vec<(String, String)> = vars
+        .filter(|(k, _)| k.contains(ENV_PATH_SEPARATOR))
+        .collect();
+    vars.sort();
+
+    for (key, value) in vars {
+        let segments: Vec<&str> = key.split(ENV_PATH_SEPARATOR).collect();
+        let parameters = match tree.get_mut("parameters") {
+            Some(v) => v,
+            None => continue,
+        };
+        // not meant for us
+        if find_key_mut(parameters, segments[0]).is_none() {
+            continue;
+        }
+
+        match find_path_mut(parameters, "parameters".to_string(), &segments) {
+            Ok(node) => {
+                *node = match node {
+                    Value::String(_) => Value::String(value),
+                    _ => serde_yaml::from_str::<Value>(&value).unwrap_or(Value::String(value)),
+                };
+            }
+            Err(e) => problems.push(format!("{}: {}", key, e)),
+        }
+    }
+
+    return problems;
+}
+
+fn find_path_mut<'a>(
+    node: &'a mut Value,
+    path: String,
+    segments: &[&str],
+) -> Result<&'a mut Value, String> {
+    return match segments.split_first() {
+        None => Ok(node),
+        Some((segment, rest)) => match find_key_mut(node, segment) {
+            Some((name, child)) => find_path_mut(child, format!("{}.{}", path, name), rest),
+            None => Err(format!("no setting {} below {}", segment, path)),
+        },
+    };
+}
+
+fn find_key_mut<'a>(node: &'a mut Value, segment: &str) -> Option<(String, &'a mut Value)> {
+    let normalize = |s: &str| s.replace('_', "").to_lowercase();
+    let wanted = normalize(segment);
+
+    return node.as_mapping_mut().and_then(|m| {
+        m.iter_mut()
+            .filter_map(|(k, v)| k.as_str().map(|k| (k.to_string(), v)))
+            .find(|(k, _)| normalize(k.as_str()) == wanted)
+    });
+}
+
+/// Checks the semantic constraints of the settings which are not covered by deserialization.
+/// All problems are collected, so the whole file can be fixed in one go.
+pub fn validate_settings(settings: &Settings) -> Result<(), Vec<String>> {
+    let mut problems = Vec::new();
+
+    validate_strichliste(&mut problems, &settings.strichliste);
+    validate_backend(&mut problems, &settings.backend);
+
+    return match problems.is_empty() {
+        true => Ok(()),
+        false => Err(problems),
     };
 }
 
+fn validate_strichliste(problems: &mut Vec<String>, settings: &StrichlisteSetting) {
+    let root = "parameters.strichliste";
+
+    if settings.common.idle_timeout < 0 {
+        problems.push(format!("{}.common.idleTimeout: must not be negative", root));
+    }
+    if settings.paypal.fee < 0 {
+        problems.push(format!("{}.paypal.fee: must not be negative", root));
+    }
+    check_period(
+        problems,
+        &format!("{}.user.stalePeriod", root),
+        &settings.user.stale_period,
+    );
+    if !CURRENCY_CODES.contains(&settings.i18n.currency.alpha3.as_str()) {
+        problems.push(format!(
+            "{}.i18n.currency.alpha3: '{}' is not an ISO 4217 currency code",
+            root, settings.i18n.currency.alpha3
+        ));
+    }
+    check_boundary(
+        problems,
+        &format!("{}.account.boundary", root),
+        &settings.account.boundary,
+    );
+    check_period(
+        problems,
+        &format!("{}.payment.undo.timeout", root),
+        &settings.payment.undo.timeout,
+    );
+    check_boundary(
+        problems,
+        &format!("{}.payment.boundary", root),
+        &settings.payment.boundary,
+    );
+    check_steps(
+        problems,
+        &format!("{}.payment.deposit", root),
+        &settings.payment.deposit,
+    );
+    check_steps(
+        problems,
+        &format!("{}.payment.dispense", root),
+        &settings.payment.dispense,
+    );
+}
+
+fn validate_backend(problems: &mut Vec<String>, settings: &BackendSetting) {
+    let root = "parameters.backend";
+
+    let backup = &settings.backup;
+    if backup.enabled {
+        check_period(
+            problems,
+            &format!("{}.backup.interval", root),
+            &backup.interval,
+        );
+        if to_duration(&backup.interval).as_secs() == 0 {
+            problems.push(format!(
+                "{}.backup.interval: must be at least a second",
+                root
+            ));
+        }
+        if backup.retain < 1 {
+            problems.push(format!("{}.backup.retain: must be at least 1", root));
+        }
+    }
+    if (backup.enabled || backup.download) && backup.directory.is_empty() {
+        problems.push(format!("{}.backup.directory: must not be empty", root));
+    }
+
+    let frontend = &settings.frontend;
+    if !frontend.directory.is_empty()
+        && !Path::new(&frontend.directory).join("index.html").is_file()
+    {
+        problems.push(format!(
+            "{}.frontend.directory: '{}' contains no index.html",
+            root, frontend.directory
+        ));
+    }
+
+    let tls = &settings.tls;
+    if tls.enabled {
+        for (name, file) in [("cert", &tls.cert), ("key", &tls.key)].iter() {
+            if !Path::new(file).is_file() {
+                problems.push(format!("{}.tls.{}: '{}' is not a file", root, name, file));
+            }
+        }
+        check_period(
+            problems,
+            &format!("{}.tls.reloadInterval", root),
+            &tls.reload_interval,
+        );
+        if to_duration(&tls.reload_interval).as_secs() == 0 {
+            problems.push(format!(
+                "{}.tls.reloadInterval: must be at least a second",
+                root
+            ));
+        }
+        if !tls.redirect_address.is_empty()
+            && !matches!(
+                tls.redirect_address.to_socket_addrs().map(|mut v| v.next()),
+                Ok(Some(_))
+            )
+        {
+            problems.push(format!(
+                "{}.tls.redirectAddress: '{}' is not a valid address",
+                root, tls.redirect_address
+            ));
+        }
+    }
+
+    let webhooks = &settings.webhooks;
+    if webhooks.max_attempts < 1 {
+        problems.push(format!("{}.webhooks.maxAttempts: must be at least 1", root));
+    }
+    check_period(
+        problems,
+        &format!("{}.webhooks.retryDelay", root),
+        &webhooks.retry_delay,
+    );
+    check_period(
+        problems,
+        &format!("{}.webhooks.timeout", root),
+        &webhooks.timeout,
+    );
+    if to_duration(&webhooks.timeout).as_millis() == 0 {
+        problems.push(format!(
+            "{}.webhooks.timeout: must be greater than zero",
+            root
+        ));
+    }
+    for (idx, hook) in webhooks.hooks.iter().enumerate() {
+        let path = format!("{}.webhooks.hooks[{}]", root, idx);
+        if webhooks.hooks[..idx].iter().any(|h| h.id() == hook.id()) {
+            problems.push(format!(
+                "{}.id: '{}' is used by another hook, hooks sharing a URL need distinct ids",
+                path,
+                hook.id()
+            ));
+        }
+        if !(hook.url.starts_with("http://") || hook.url.starts_with("https://"))
+            || hook.url.parse::<warp::http::Uri>().is_err()
+        {
+            problems.push(format!("{}.url: '{}' is not a HTTP(S) URL", path, hook.url));
+        }
+        for event in hook.events.iter() {
+            if !crate::webhook::EVENT_NAMES.contains(&event.as_str()) {
+                problems.push(format!(
+                    "{}.events: unknown event '{}', expected one of {}",
+                    path,
+                    event,
+                    crate::webhook::EVENT_NAMES.join(", ")
+                ));
+            }
+        }
+    }
+
+    let email = &settings.email;
+    if email.enabled {
+        if email.host.is_empty() {
+            problems.push(format!("{}.email.host: must not be empty", root));
+        }
+        if !["starttls", "tls", "none"].contains(&email.security.as_str()) {
+            problems.push(format!(
+                "{}.email.security: '{}' is none of starttls, tls or none",
+                root, email.security
+            ));
+        }
+        if crate::common::assert_email(&email.sender).is_err() {
+            problems.push(format!(
+                "{}.email.sender: '{}' is not an email address",
+                root, email.sender
+            ));
+        }
+        if !email.templates.is_empty() && !Path::new(&email.templates).is_dir() {
+            problems.push(format!(
+                "{}.email.templates: '{}' is not a directory",
+                root, email.templates
+            ));
+        }
+        if !(1..=28).contains(&email.statement.day) {
+            problems.push(format!(
+                "{}.email.statement.day: must be between 1 and 28",
+                root
+            ));
+        }
+    }
+
+    check_period(
+        problems,
+        &format!("{}.idempotency.window", root),
+        &settings.idempotency.window,
+    );
+
+    let rate_limit = &settings.rate_limit;
+    for (name, budget) in [
+        ("perIp", &rate_limit.per_ip),
+        ("perUser", &rate_limit.per_user),
+    ]
+    .iter()
+    {
+        for (kind, bucket) in [("read", &budget.read), ("write", &budget.write)].iter() {
+            if bucket.burst > 0 && bucket.rate <= 0.0 {
+                problems.push(format!(
+                    "{}.rateLimit.{}.{}.rate: must be greater than zero",
+                    root, name, kind
+                ));
+            }
+        }
+    }
+
+    let anonymous = settings.api_tokens.anonymous.as_str();
+    if anonymous != "none" && !crate::api_token::SCOPES.contains(&anonymous) {
+        problems.push(format!(
+            "{}.apiTokens.anonymous: '{}' is none of none, {}",
+            root,
+            anonymous,
+            crate::api_token::SCOPES.join(", ")
+        ));
+    }
+
+    if settings.avatar.max_size == 0 {
+        problems.push(format!("{}.avatar.maxSize: must be at least 1", root));
+    }
+}
+
+fn check_period(problems: &mut Vec<String>, path: &str, value: &str) {
+    if let Err(e) = ms_converter::ms(value) {
+        problems.push(format!("{}: '{}' is not a valid period ({})", path, value, e));
+    }
+}
+
+fn check_boundary(problems: &mut Vec<String>, path: &str, boundary: &BoundarySetting) {
+    if boundary.lower > boundary.upper {
+        problems.push(format!(
+            "{}: lower ({}) must not be greater than upper ({})",
+            path, boundary.lower, boundary.upper
+        ));
+    }
+}
+
+fn check_steps(problems: &mut Vec<String>, path: &str, deposit: &DepositSetting) {
+    if deposit.enabled && deposit.steps.is_empty() {
+        problems.push(format!("{}.steps: must not be empty when enabled", path));
+    }
+    for (idx, step) in deposit.steps.iter().enumerate() {
+        if *step <= 0 {
+            problems.push(format!("{}.steps[{}]: must be positive", path, idx));
+        }
+    }
+}
+
+/// Converts a period like `5 minute` into a duration, invalid periods are rejected by the validation
+pub fn to_duration(period: &str) -> Duration {
+    let millis = ms_converter::ms(period).unwrap_or(0).max(0);
+    return Duration::from_millis(millis as u64);
+}
+
 pub fn get_stale_period(settings: &StrichlisteSetting) -> i64 {
     // seconds until user is counted as inactive
     let stale_val = settings.user.stale_period.as_str();
@@ -36,22 +431,30 @@ pub fn get_stale_period(settings: &StrichlisteSetting) -> i64 {
     return stale_period;
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
 pub struct SettingsWrapper {
     pub parameters: Settings,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
 pub struct Settings {
     pub strichliste: StrichlisteSetting,
+    pub backend: BackendSetting,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct SettingsResp {
     pub settings: StrichlisteSetting,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+//
+// Missing sections and values fall back to the defaults below, which mirror docker/strichliste.yaml
+//
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
+#[serde(default)]
 pub struct StrichlisteSetting {
     pub article: ArticleSettings,
     pub common: CommonSettings,
@@ -62,33 +465,63 @@ pub struct StrichlisteSetting {
     pub payment: PaymentSetting,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct ArticleSettings {
     pub enabled: bool,
     #[serde(rename(serialize = "autoOpen", deserialize = "autoOpen"))]
     pub auto_open: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for ArticleSettings {
+    fn default() -> Self {
+        ArticleSettings {
+            enabled: true,
+            auto_open: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct CommonSettings {
     #[serde(rename(serialize = "idleTimeout", deserialize = "idleTimeout"))]
     pub idle_timeout: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for CommonSettings {
+    fn default() -> Self {
+        CommonSettings {
+            idle_timeout: 30000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
+#[serde(default)]
 pub struct PaypalSetting {
     pub enabled: bool,
     pub recipient: String,
     pub fee: i32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct UserSetting {
     #[serde(rename(serialize = "stalePeriod", deserialize = "stalePeriod"))]
     pub stale_period: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for UserSetting {
+    fn default() -> Self {
+        UserSetting {
+            stale_period: "10 day".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct I18nSetting {
     #[serde(rename(serialize = "dateFormat", deserialize = "dateFormat"))]
     pub date_format: String,
@@ -97,25 +530,60 @@ pub struct I18nSetting {
     pub currency: CurrencySetting,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for I18nSetting {
+    fn default() -> Self {
+        I18nSetting {
+            date_format: "YYYY-MM-DD HH:mm:ss".to_string(),
+            timezone: "auto".to_string(),
+            language: "en".to_string(),
+            currency: CurrencySetting::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct CurrencySetting {
     pub name: String,
     pub symbol: String,
     pub alpha3: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for CurrencySetting {
+    fn default() -> Self {
+        CurrencySetting {
+            name: "Euro".to_string(),
+            symbol: "€".to_string(),
+            alpha3: "EUR".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct AccountSetting {
     pub boundary: BoundarySetting,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for AccountSetting {
+    fn default() -> Self {
+        AccountSetting {
+            boundary: BoundarySetting {
+                upper: 20000,
+                lower: -20000,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
 pub struct BoundarySetting {
     pub upper: i32,
     pub lower: i32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct PaymentSetting {
     pub undo: UndoSetting,
     pub boundary: BoundarySetting,
@@ -126,26 +594,425 @@ pub struct PaymentSetting {
     pub dispense: DepositSetting,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for PaymentSetting {
+    fn default() -> Self {
+        PaymentSetting {
+            undo: UndoSetting::default(),
+            boundary: BoundarySetting {
+                upper: 15000,
+                lower: -2000,
+            },
+            transactions: TransactionSetting::default(),
+            split_invoice: SplitInvoiceSetting::default(),
+            deposit: DepositSetting::default(),
+            dispense: DepositSetting::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct UndoSetting {
     pub enabled: bool,
     pub delete: bool,
     pub timeout: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for UndoSetting {
+    fn default() -> Self {
+        UndoSetting {
+            enabled: true,
+            delete: false,
+            timeout: "5 minute".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct TransactionSetting {
     pub enabled: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for TransactionSetting {
+    fn default() -> Self {
+        TransactionSetting { enabled: true }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct SplitInvoiceSetting {
     pub enabled: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Default for SplitInvoiceSetting {
+    fn default() -> Self {
+        SplitInvoiceSetting { enabled: true }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(default)]
 pub struct DepositSetting {
     pub enabled: bool,
     pub custom: bool,
     pub steps: Vec<i32>,
 }
+
+impl Default for DepositSetting {
+    fn default() -> Self {
+        DepositSetting {
+            enabled: true,
+            custom: true,
+            steps: vec![50, 100, 200, 500, 1000],
+        }
+    }
+}
+
+//
+// Settings of the backend only, these are not exposed to the frontend
+//
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct BackendSetting {
+    /// secret accepted in the `X-Admin-Token` header like an admin API token, empty disables it
+    #[serde(rename(serialize = "adminToken", deserialize = "adminToken"))]
+    pub admin_token: String,
+    pub backup: BackupSetting,
+    pub frontend: FrontendSetting,
+    pub tls: TlsSetting,
+    pub webhooks: WebhookSetting,
+    pub email: EmailSetting,
+    pub idempotency: IdempotencySetting,
+    #[serde(rename(serialize = "rateLimit", deserialize = "rateLimit"))]
+    pub rate_limit: RateLimitSetting,
+    pub approval: ApprovalSetting,
+    #[serde(rename(serialize = "apiTokens", deserialize = "apiTokens"))]
+    pub api_tokens: ApiTokenSetting,
+    pub avatar: AvatarSetting,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct BackupSetting {
+    /// run scheduled backups
+    pub enabled: bool,
+    pub directory: String,
+    pub interval: String,
+    /// number of scheduled backups to keep
+    pub retain: usize,
+    /// enable the admin endpoints to trigger and download backups
+    pub download: bool,
+}
+
+impl Default for BackupSetting {
+    fn default() -> Self {
+        BackupSetting {
+            enabled: false,
+            directory: "/var/lib/strichliste-rs/backup".to_string(),
+            interval: "1 day".to_string(),
+            retain: 7,
+            download: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct FrontendSetting {
+    /// directory with the frontend build, not served if empty
+    pub directory: String,
+    /// seconds the hashed assets may be cached
+    #[serde(rename(serialize = "maxAge", deserialize = "maxAge"))]
+    pub max_age: u32,
+}
+
+impl Default for FrontendSetting {
+    fn default() -> Self {
+        FrontendSetting {
+            directory: "".to_string(),
+            max_age: 365 * 24 * 3600,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TlsSetting {
+    /// serve HTTPS instead of plain HTTP
+    pub enabled: bool,
+    /// PEM file with the certificate chain
+    pub cert: String,
+    /// PEM file with the private key
+    pub key: String,
+    /// period to check the files for renewed certificates
+    #[serde(rename(serialize = "reloadInterval", deserialize = "reloadInterval"))]
+    pub reload_interval: String,
+    /// address of a plain HTTP listener redirecting to HTTPS, none if empty
+    #[serde(rename(serialize = "redirectAddress", deserialize = "redirectAddress"))]
+    pub redirect_address: String,
+}
+
+impl Default for TlsSetting {
+    fn default() -> Self {
+        TlsSetting {
+            enabled: false,
+            cert: "".to_string(),
+            key: "".to_string(),
+            reload_interval: "1 minute".to_string(),
+            redirect_address: "".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct WebhookSetting {
+    /// balance below which `user.balance.low` is sent
+    #[serde(rename(serialize = "lowBalance", deserialize = "lowBalance"))]
+    pub low_balance: i32,
+    /// attempts before a delivery is given up
+    #[serde(rename(serialize = "maxAttempts", deserialize = "maxAttempts"))]
+    pub max_attempts: i32,
+    /// delay before the first retry, doubled for each further one
+    #[serde(rename(serialize = "retryDelay", deserialize = "retryDelay"))]
+    pub retry_delay: String,
+    pub timeout: String,
+    pub hooks: Vec<HookSetting>,
+}
+
+impl Default for WebhookSetting {
+    fn default() -> Self {
+        WebhookSetting {
+            low_balance: 0,
+            max_attempts: 10,
+            retry_delay: "10 seconds".to_string(),
+            timeout: "10 seconds".to_string(),
+            hooks: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct HookSetting {
+    /// identifies the deliveries of the hook, the URL if empty
+    pub id: String,
+    pub url: String,
+    /// key of the HMAC-SHA256 signature, unsigned if empty
+    pub secret: String,
+    /// names of the events to send, all if empty
+    pub events: Vec<String>,
+}
+
+impl HookSetting {
+    pub fn id(&self) -> &str {
+        match self.id.is_empty() {
+            true => &self.url,
+            false => &self.id,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct EmailSetting {
+    /// send reminders and statements to users with an email address
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    /// `starttls`, `tls` or `none`
+    pub security: String,
+    /// SMTP login, no authentication if empty
+    pub username: String,
+    pub password: String,
+    pub sender: String,
+    #[serde(rename(serialize = "senderName", deserialize = "senderName"))]
+    pub sender_name: String,
+    /// directory with `<language>/reminder.txt` and `<language>/statement.txt`, built-in ones if empty
+    pub templates: String,
+    pub reminder: ReminderSetting,
+    pub statement: StatementSetting,
+}
+
+impl Default for EmailSetting {
+    fn default() -> Self {
+        EmailSetting {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 25,
+            security: "starttls".to_string(),
+            username: "".to_string(),
+            password: "".to_string(),
+            sender: "".to_string(),
+            sender_name: "Strichliste".to_string(),
+            templates: "".to_string(),
+            reminder: ReminderSetting::default(),
+            statement: StatementSetting::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ReminderSetting {
+    pub enabled: bool,
+    /// a reminder is sent when a booking takes the balance below this value
+    pub threshold: i32,
+}
+
+impl Default for ReminderSetting {
+    fn default() -> Self {
+        ReminderSetting {
+            enabled: true,
+            threshold: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct StatementSetting {
+    pub enabled: bool,
+    /// day of the month on which the statement of the previous month is sent
+    pub day: u32,
+}
+
+impl Default for StatementSetting {
+    fn default() -> Self {
+        StatementSetting {
+            enabled: false,
+            day: 1,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct IdempotencySetting {
+    /// period in which a repeated `Idempotency-Key` returns the original transaction
+    pub window: String,
+}
+
+impl Default for IdempotencySetting {
+    fn default() -> Self {
+        IdempotencySetting {
+            window: "1 day".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RateLimitSetting {
+    pub enabled: bool,
+    /// take the client address from `X-Forwarded-For`, only safe behind a reverse proxy
+    #[serde(rename(serialize = "trustProxy", deserialize = "trustProxy"))]
+    pub trust_proxy: bool,
+    #[serde(rename(serialize = "perIp", deserialize = "perIp"))]
+    pub per_ip: BudgetSetting,
+    /// applies to the routes below `/api/user/{userId}`
+    #[serde(rename(serialize = "perUser", deserialize = "perUser"))]
+    pub per_user: BudgetSetting,
+}
+
+impl Default for RateLimitSetting {
+    fn default() -> Self {
+        RateLimitSetting {
+            enabled: false,
+            trust_proxy: false,
+            per_ip: BudgetSetting {
+                read: BucketSetting {
+                    rate: 20.0,
+                    burst: 60,
+                },
+                write: BucketSetting {
+                    rate: 5.0,
+                    burst: 20,
+                },
+            },
+            per_user: BudgetSetting {
+                read: BucketSetting {
+                    rate: 10.0,
+                    burst: 30,
+                },
+                write: BucketSetting {
+                    rate: 2.0,
+                    burst: 10,
+                },
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ApprovalSetting {
+    /// hold bookings exceeding `account.boundary` for approval instead of rejecting them
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ApiTokenSetting {
+    /// scope of requests without an API token, one of none, read, book or admin
+    pub anonymous: String,
+}
+
+impl Default for ApiTokenSetting {
+    fn default() -> Self {
+        ApiTokenSetting {
+            anonymous: "book".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AvatarSetting {
+    /// largest accepted image in bytes
+    #[serde(rename(serialize = "maxSize", deserialize = "maxSize"))]
+    pub max_size: u64,
+}
+
+impl Default for AvatarSetting {
+    fn default() -> Self {
+        AvatarSetting {
+            max_size: 256 * 1024,
+        }
+    }
+}
+
+/// Separate budgets for GET requests and all others
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BudgetSetting {
+    pub read: BucketSetting,
+    pub write: BucketSetting,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BucketSetting {
+    /// requests per second
+    pub rate: f64,
+    /// requests allowed at once, no limit if 0
+    pub burst: u32,
+}
+
+/// Active ISO 4217 currency codes
+const CURRENCY_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD",
+    "CDF", "CHF", "CLP", "CNY", "COP", "CRC", "CUC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP",
+    "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF",
+    "GTQ", "GYD", "HKD", "HNL", "HRK", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK",
+    "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK",
+    "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU",
+    "MUR", "MVR", "MWK", "MXN", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD", "OMR",
+    "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB", "RWF", "SAR",
+    "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLL", "SOS", "SRD", "SSP", "STN", "SVC", "SYP",
+    "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD",
+    "UYU", "UZS", "VES", "VND", "VUV", "WST", "XAF", "XCD", "XOF", "XPF", "YER", "ZAR", "ZMW",
+    "ZWL",
+];
@@ -0,0 +1,283 @@
+use serde_json::{json, Value};
+
+use super::TestApp;
+use crate::{api_token, settings::ApiTokenSetting};
+
+fn bearer(token: &str) -> String {
+    format!("Bearer {}", token)
+}
+
+#[tokio::test]
+async fn manage_tokens() {
+    let app = TestApp::new().await;
+
+    let (status, body) = app
+        .call(
+            "POST",
+            "/api/admin/token",
+            Some(json!({ "name": "vending machine", "scope": "book" })),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["token"]["scope"], "book");
+    assert!(body["secret"].as_str().unwrap().starts_with("sl_"));
+    let id = body["token"]["id"].clone();
+
+    let (status, _) = app
+        .call(
+            "POST",
+            "/api/admin/token",
+            Some(json!({ "name": "chat bot", "scope": "root" })),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    let (_, body) = app.call("GET", "/api/admin/token", None).await;
+    assert_eq!(body["count"], 1);
+    // the secret is not stored
+    assert_eq!(body["tokens"][0].get("secret"), None);
+
+    let (status, body) = app
+        .call("DELETE", &format!("/api/admin/token/{}", id), None)
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["token"]["isRevoked"], true);
+    assert_eq!(body["secret"], Value::Null);
+    let (status, _) = app
+        .call("DELETE", &format!("/api/admin/token/{}", id), None)
+        .await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn scopes_are_enforced() {
+    let mut app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    app.backend.api_tokens.anonymous = "read".to_string();
+    let (_, book) = api_token::create_token(&app.db, "bridge", "book", None)
+        .await
+        .unwrap();
+    let (_, admin) = api_token::create_token(&app.db, "admin", "admin", None)
+        .await
+        .unwrap();
+    let transaction_path = format!("/api/user/{}/transaction", user["id"]);
+    let booking = json!({ "amount": -100 });
+
+    // anonymous requests may read only
+    let (status, _) = app.call("GET", "/api/user", None).await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .call("POST", &transaction_path, Some(booking.clone()))
+        .await;
+    assert_eq!(status, 401);
+
+    let (status, _) = app
+        .call_with_headers(
+            "POST",
+            &transaction_path,
+            Some(booking.clone()),
+            &[("Authorization", &bearer(&book))],
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .call_with_headers(
+            "POST",
+            "/api/user",
+            Some(json!({ "name": "bob" })),
+            &[("Authorization", &bearer(&book))],
+        )
+        .await;
+    assert_eq!(status, 200);
+    // the stock frontend manages articles as well, scheduling price changes is up to admins
+    let (status, body) = app
+        .call_with_headers(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Club Mate", "amount": 150 })),
+            &[("Authorization", &bearer(&book))],
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .call_with_headers(
+            "POST",
+            &format!("/api/article/{}/price", body["article"]["id"]),
+            Some(json!({ "amount": 180, "activates": "2999-01-01 00:00:00" })),
+            &[("Authorization", &bearer(&book))],
+        )
+        .await;
+    assert_eq!(status, 403);
+    let (status, _) = app
+        .call_with_headers(
+            "GET",
+            "/api/admin/token",
+            None,
+            &[("Authorization", &bearer(&book))],
+        )
+        .await;
+    assert_eq!(status, 403);
+
+    let (status, body) = app
+        .call_with_headers(
+            "GET",
+            "/api/admin/token",
+            None,
+            &[("Authorization", &bearer(&admin))],
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert!(body["tokens"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|t| t["lastUsed"].is_string()));
+
+    let (status, _) = app
+        .call_with_headers(
+            "GET",
+            "/api/user",
+            None,
+            &[("Authorization", &bearer("sl_guessed"))],
+        )
+        .await;
+    assert_eq!(status, 401);
+}
+
+#[tokio::test]
+async fn revoked_and_expired_tokens_are_rejected() {
+    let app = TestApp::new().await;
+    let (token, secret) = api_token::create_token(&app.db, "bot", "read", None)
+        .await
+        .unwrap();
+    let (_, expired) =
+        api_token::create_token(&app.db, "old bot", "read", Some("2000-01-01 00:00:00"))
+            .await
+            .unwrap();
+
+    let (status, _) = app
+        .call_with_headers(
+            "GET",
+            "/api/user",
+            None,
+            &[("Authorization", &bearer(&secret))],
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .call_with_headers(
+            "GET",
+            "/api/user",
+            None,
+            &[("Authorization", &bearer(&expired))],
+        )
+        .await;
+    assert_eq!(status, 401);
+
+    app.call("DELETE", &format!("/api/admin/token/{}", token.id), None)
+        .await;
+    let (status, _) = app
+        .call_with_headers(
+            "GET",
+            "/api/user",
+            None,
+            &[("Authorization", &bearer(&secret))],
+        )
+        .await;
+    assert_eq!(status, 401);
+}
+
+#[tokio::test]
+async fn anonymous_kiosk_by_default() {
+    let mut app = TestApp::new().await;
+    app.backend.api_tokens = ApiTokenSetting::default();
+    let (_, admin) = api_token::create_token(&app.db, "admin", "admin", None)
+        .await
+        .unwrap();
+    let user = app.add_user("alice").await;
+    let user_path = format!("/api/user/{}", user["id"]);
+
+    // the self-service writes of the kiosk
+    for (method, path, body) in [
+        (
+            "POST",
+            format!("{}/transaction", user_path),
+            Some(json!({ "amount": -100 })),
+        ),
+        (
+            "POST",
+            format!("{}/token", user_path),
+            Some(json!({ "uid": "04A23B1C" })),
+        ),
+        (
+            "POST",
+            format!("{}/scheduled", user_path),
+            Some(json!({ "amount": -100, "interval": "weekly" })),
+        ),
+        ("DELETE", format!("{}/scheduled/1", user_path), None),
+        ("DELETE", format!("{}/token/1", user_path), None),
+        (
+            "POST",
+            user_path.clone(),
+            Some(json!({ "name": "alicia", "isDisabled": false })),
+        ),
+        (
+            "POST",
+            "/api/article".to_string(),
+            Some(json!({ "name": "Club Mate", "amount": 150 })),
+        ),
+        ("DELETE", "/api/article/1".to_string(), None),
+    ]
+    .iter()
+    {
+        let (status, body) = app.call(method, path, body.clone()).await;
+        assert_eq!(status, 200, "{} {}: {}", method, path, body);
+    }
+
+    // the admin API and price changes need an admin token
+    let (status, _) = app
+        .call_with_headers("GET", "/api/admin/backup", None, &[])
+        .await;
+    assert_eq!(status, 401);
+    let (status, _) = app
+        .call(
+            "POST",
+            "/api/article/1/price",
+            Some(json!({ "amount": 180, "activates": "2999-01-01 00:00:00" })),
+        )
+        .await;
+    assert_eq!(status, 401);
+    let (status, _) = app
+        .call_with_headers(
+            "GET",
+            "/api/admin/token",
+            None,
+            &[("Authorization", &bearer(&admin))],
+        )
+        .await;
+    assert_eq!(status, 200);
+
+    // unknown routes are not found, whatever the scope
+    let (status, _) = app.call("POST", "/api/frobnicate", Some(json!({}))).await;
+    assert_eq!(status, 404);
+
+    // other schemes, e.g. Basic auth of a proxy, count as no token
+    let (status, _) = app
+        .call_with_headers(
+            "POST",
+            &format!("{}/transaction", user_path),
+            Some(json!({ "amount": -100 })),
+            &[("Authorization", "Basic a2lvc2s6c2VjcmV0")],
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .call_with_headers(
+            "GET",
+            "/api/admin/token",
+            None,
+            &[("Authorization", "Basic a2lvc2s6c2VjcmV0")],
+        )
+        .await;
+    assert_eq!(status, 401);
+}
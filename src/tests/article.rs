@@ -0,0 +1,326 @@
+use serde_json::json;
+
+use super::TestApp;
+use crate::price_change;
+
+#[tokio::test]
+async fn create_article() {
+    let app = TestApp::new().await;
+    let article = app.add_article("Club Mate", 150).await;
+
+    assert_eq!(article["name"], "Club Mate");
+    assert_eq!(article["amount"], 150);
+    assert_eq!(article["isActive"], true);
+    assert_eq!(article["precursor"], json!(null));
+
+    let (status, body) = app
+        .call("GET", &format!("/api/article/{}", article["id"]), None)
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["article"]["id"], article["id"]);
+
+    let (status, _) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Refund", "amount": -150 })),
+        )
+        .await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn update_builds_precursor_chain() {
+    let app = TestApp::new().await;
+    let first = app.add_article("Club Mate", 150).await;
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &format!("/api/article/{}", first["id"]),
+            Some(json!({ "name": "Club Mate", "amount": 160 })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let second = body["article"].clone();
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &format!("/api/article/{}", second["id"]),
+            Some(json!({ "name": "Club-Mate", "amount": 170 })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let third = &body["article"];
+
+    assert_eq!(third["amount"], 170);
+    assert_eq!(third["isActive"], true);
+    assert_eq!(third["precursor"]["id"], second["id"]);
+    assert_eq!(third["precursor"]["isActive"], false);
+    assert_eq!(third["precursor"]["precursor"]["id"], first["id"]);
+    assert_eq!(third["precursor"]["precursor"]["precursor"], json!(null));
+
+    // only the newest version is listed, along with its history
+    let (_, body) = app.call("GET", "/api/article", None).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["articles"][0]["id"], third["id"]);
+    assert_eq!(body["articles"][0]["precursor"]["id"], second["id"]);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/article/{}", first["id"]), None)
+        .await;
+    assert_eq!(body["article"]["isActive"], false);
+}
+
+#[tokio::test]
+async fn outdated_article_cannot_be_updated() {
+    let app = TestApp::new().await;
+    let first = app.add_article("Club Mate", 150).await;
+    let path = format!("/api/article/{}", first["id"]);
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "name": "Club Mate", "amount": 160 })),
+        )
+        .await;
+    assert_eq!(status, 200);
+
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "name": "Club Mate", "amount": 170 })),
+        )
+        .await;
+    assert_ne!(status, 200);
+
+    let (_, body) = app.call("GET", "/api/article", None).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["articles"][0]["amount"], 160);
+}
+
+#[tokio::test]
+async fn retire_article() {
+    let app = TestApp::new().await;
+    let article = app.add_article("Club Mate", 150).await;
+
+    let (status, body) = app
+        .call("DELETE", &format!("/api/article/{}", article["id"]), None)
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["article"]["isActive"], false);
+
+    let (_, body) = app.call("GET", "/api/article", None).await;
+    assert_eq!(body["articles"], json!([]));
+
+    let (_, body) = app.call("GET", "/api/article?active=false", None).await;
+    assert_eq!(body["articles"][0]["id"], article["id"]);
+}
+
+#[tokio::test]
+async fn categories_follow_the_precursor_chain() {
+    let app = TestApp::new().await;
+    let (status, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Club Mate", "amount": 150, "categories": [" Drinks", "Mate", "Drinks", ""] })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let first = body["article"].clone();
+    assert_eq!(first["categories"], json!(["Drinks", "Mate"]));
+    app.call(
+        "POST",
+        "/api/article",
+        Some(json!({ "name": "Chips", "amount": 100, "categories": ["Snacks"] })),
+    )
+    .await;
+
+    // categories are kept when an update doesn't name any
+    let (_, body) = app
+        .call(
+            "POST",
+            &format!("/api/article/{}", first["id"]),
+            Some(json!({ "name": "Club Mate", "amount": 160 })),
+        )
+        .await;
+    let second = body["article"].clone();
+    assert_eq!(second["categories"], json!(["Drinks", "Mate"]));
+    assert_eq!(second["precursor"]["categories"], json!(["Drinks", "Mate"]));
+
+    let (_, body) = app
+        .call(
+            "POST",
+            &format!("/api/article/{}", second["id"]),
+            Some(json!({ "name": "Club Mate", "amount": 160, "categories": ["Drinks"] })),
+        )
+        .await;
+    assert_eq!(body["article"]["categories"], json!(["Drinks"]));
+    assert_eq!(
+        body["article"]["precursor"]["categories"],
+        json!(["Drinks", "Mate"])
+    );
+
+    let (_, body) = app.call("GET", "/api/article?category=Drinks", None).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["articles"][0]["name"], "Club Mate");
+    let (_, body) = app.call("GET", "/api/article?category=Mate", None).await;
+    assert_eq!(body["count"], 0);
+
+    let (status, body) = app.call("GET", "/api/category", None).await;
+    assert_eq!(status, 200);
+    let categories: Vec<(String, i64)> = body["categories"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| {
+            (
+                c["name"].as_str().unwrap().to_string(),
+                c["articleCount"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        categories,
+        vec![
+            ("Drinks".to_string(), 1),
+            ("Mate".to_string(), 0),
+            ("Snacks".to_string(), 1)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn history_lists_all_versions() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let first = app.add_article("Club Mate", 150).await;
+    app.book(
+        &user["id"],
+        json!({ "articleId": first["id"], "quantity": 2 }),
+    )
+    .await;
+    let (_, body) = app
+        .call(
+            "POST",
+            &format!("/api/article/{}", first["id"]),
+            Some(json!({ "name": "Club Mate", "amount": 160 })),
+        )
+        .await;
+    let second = body["article"].clone();
+    app.book(&user["id"], json!({ "articleId": second["id"] }))
+        .await;
+
+    // any version leads to the whole history
+    for id in [&first["id"], &second["id"]].iter() {
+        let (status, body) = app
+            .call("GET", &format!("/api/article/{}/history", id), None)
+            .await;
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body["count"], 2);
+        let versions = &body["versions"];
+        assert_eq!(versions[0]["id"], second["id"]);
+        assert_eq!(versions[0]["amount"], 160);
+        assert_eq!(versions[0]["validUntil"], json!(null));
+        assert_eq!(versions[0]["sales"], 1);
+        assert_eq!(versions[0]["quantity"], 1);
+        assert_eq!(versions[1]["id"], first["id"]);
+        assert_eq!(versions[1]["amount"], 150);
+        assert_eq!(versions[1]["validUntil"], versions[0]["validFrom"]);
+        assert_eq!(versions[1]["sales"], 1);
+        assert_eq!(versions[1]["quantity"], 2);
+    }
+
+    let (status, _) = app.call("GET", "/api/article/42/history", None).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn scheduled_price_change() {
+    let app = TestApp::new().await;
+    let article = app.add_article("Club Mate", 150).await;
+    let path = format!("/api/article/{}/price", article["id"]);
+
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": 180, "activates": "2000-01-01 00:00:00" })),
+        )
+        .await;
+    assert_eq!(status, 400);
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": -180, "activates": "2999-01-01 00:00:00" })),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": 180, "activates": "2999-01-01 00:00:00" })),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let change = body["priceChange"].clone();
+    assert_eq!(change["state"], "pending");
+    let (_, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": 190, "activates": "2999-01-01 00:00:00" })),
+        )
+        .await;
+    let cancelled = body["priceChange"].clone();
+    let (status, body) = app
+        .call(
+            "DELETE",
+            &format!("/api/article/price/{}", cancelled["id"]),
+            None,
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["priceChange"]["state"], "cancelled");
+
+    // the article was updated in between, the change applies to the newest version
+    let (_, body) = app
+        .call(
+            "POST",
+            &format!("/api/article/{}", article["id"]),
+            Some(json!({ "name": "Club-Mate", "amount": 160 })),
+        )
+        .await;
+    let second = body["article"].clone();
+    sqlx::query(
+        "UPDATE article_price_change SET activates = datetime('now', 'localtime', '-1 minute')",
+    )
+    .execute(&app.db)
+    .await
+    .unwrap();
+    price_change::apply_due_price_changes(&app.db, &app.events)
+        .await
+        .unwrap();
+
+    let (_, body) = app.call("GET", "/api/article", None).await;
+    assert_eq!(body["count"], 1);
+    let third = &body["articles"][0];
+    assert_eq!(third["name"], "Club-Mate");
+    assert_eq!(third["amount"], 180);
+    assert_eq!(third["precursor"]["id"], second["id"]);
+
+    let (_, body) = app.call("GET", &path, None).await;
+    assert_eq!(body["count"], 2);
+    let changes = body["priceChanges"].as_array().unwrap();
+    let applied = changes.iter().find(|c| c["id"] == change["id"]).unwrap();
+    assert_eq!(applied["state"], "applied");
+    assert_eq!(applied["appliedArticleId"], third["id"]);
+    let not_applied = changes.iter().find(|c| c["id"] == cancelled["id"]).unwrap();
+    assert_eq!(not_applied["state"], "cancelled");
+}
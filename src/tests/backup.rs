@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use super::TestApp;
+use crate::{backup, db, user_db};
+
+fn file_names(directory: &str) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn backups_are_named_by_time_and_pruned() {
+    let app = TestApp::new().await;
+    let directory = &app.backend.backup.directory;
+
+    let mut files = Vec::new();
+    for _ in 0..3 {
+        files.push(backup::create_backup(&app.db, directory).await.unwrap());
+    }
+
+    // the names sort in the order the backups were written
+    let names = file_names(directory);
+    assert_eq!(names.len(), 3);
+    for (file, name) in files.iter().zip(names.iter()) {
+        assert!(name.starts_with("strichliste-") && name.ends_with(".sqlite"));
+        assert!(file.ends_with(name.as_str()), "{} {}", file, name);
+    }
+
+    std::fs::write(Path::new(directory).join("notes.txt"), "keep me").unwrap();
+    backup::apply_retention(directory, 2).await.unwrap();
+    let names = file_names(directory);
+    assert_eq!(names.len(), 3);
+    assert!(!Path::new(&files[0]).exists());
+    assert!(files[1..].iter().all(|f| Path::new(f).exists()));
+    assert!(names.contains(&"notes.txt".to_string()));
+}
+
+#[tokio::test]
+async fn restore_keeps_the_replaced_database() {
+    let app = TestApp::new().await;
+    user_db::create_user(&app.db, "alice", None).await.unwrap();
+    let source = backup::create_backup(&app.db, &app.backend.backup.directory)
+        .await
+        .unwrap();
+
+    let target = app.dir.join("restored.sqlite");
+    let target = target.to_string_lossy();
+    std::fs::write(target.as_ref(), "replaced").unwrap();
+    std::fs::write(format!("{}-wal", target), "log").unwrap();
+
+    db::restore_db(&target, &source).await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.pre-restore", target)).unwrap(),
+        "replaced"
+    );
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.pre-restore-wal", target)).unwrap(),
+        "log"
+    );
+    assert!(!Path::new(&format!("{}-wal", target)).exists());
+    let restored = db::open_db(&target).await.unwrap();
+    let users = sqlx::query("SELECT id FROM user WHERE name = 'alice';")
+        .fetch_all(&restored)
+        .await
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    restored.close().await;
+
+    // the copy of the first restore is not overwritten
+    let result = db::restore_db(&target, &source).await;
+    assert!(result.is_err());
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.pre-restore", target)).unwrap(),
+        "replaced"
+    );
+}
@@ -0,0 +1,254 @@
+use proptest::prelude::*;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use super::TestApp;
+use crate::{
+    article_db,
+    model::{TransactionEntity, TransactionObject},
+    settings::StrichlisteSetting,
+    transaction_api::check_limit,
+    transaction_db, user_db,
+};
+
+/// Operation on the ledger, users, articles and transactions are referred to by their index
+#[derive(Debug, Clone)]
+enum Op {
+    AddUser,
+    Value {
+        user: usize,
+        amount: i32,
+    },
+    Article {
+        user: usize,
+        article: usize,
+        quantity: i32,
+    },
+    Transfer {
+        user: usize,
+        recipient: usize,
+        amount: i32,
+    },
+    Undo {
+        transaction: usize,
+    },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        1 => Just(Op::AddUser),
+        4 => (any::<usize>(), -3000..4000).prop_map(|(user, amount)| Op::Value { user, amount }),
+        4 => (any::<usize>(), any::<usize>(), 1..5).prop_map(|(user, article, quantity)| Op::Article {
+            user,
+            article,
+            quantity,
+        }),
+        3 => (any::<usize>(), any::<usize>(), -3000..0).prop_map(|(user, recipient, amount)| {
+            Op::Transfer {
+                user,
+                recipient,
+                amount,
+            }
+        }),
+        2 => any::<usize>().prop_map(|transaction| Op::Undo { transaction }),
+    ]
+}
+
+/// Expected state, kept alongside the database
+#[derive(Default)]
+struct Model {
+    users: Vec<i32>,
+    balances: Vec<i32>,
+    /// balance changes per booking by user index, and whether it was undone
+    bookings: Vec<(Vec<(usize, i32)>, bool)>,
+    /// transaction ids that can be reverted along with their booking
+    transactions: Vec<(i32, usize)>,
+}
+
+/// Narrow boundaries, so that the generated bookings run into them regularly
+fn settings() -> StrichlisteSetting {
+    let mut settings = StrichlisteSetting::default();
+    settings.account.boundary.lower = -5000;
+    settings.account.boundary.upper = 6000;
+    settings.payment.boundary.lower = -2000;
+    settings.payment.boundary.upper = 3000;
+    settings
+}
+
+async fn add_user(app: &TestApp, model: &mut Model) {
+    let name = format!("user{}", model.users.len());
+    let user = user_db::create_user(&app.db, &name, None).await.unwrap();
+    model.users.push(user.id);
+    model.balances.push(0);
+}
+
+/// Books through `add_transaction`, which has to accept exactly the bookings the model allows.
+async fn book(app: &TestApp, model: &mut Model, articles: &[(i32, i32)], op: &Op) {
+    let (user, amount, req, recipient) = match *op {
+        Op::Value { user, amount } => (user, amount, json!({ "amount": amount }), None),
+        Op::Article {
+            user,
+            article,
+            quantity,
+        } => {
+            let (id, price) = articles[article % articles.len()];
+            let req = json!({ "articleId": id, "quantity": quantity });
+            (user, price * quantity * -1, req, None)
+        }
+        Op::Transfer {
+            user,
+            recipient,
+            amount,
+        } => {
+            let recipient = recipient % model.users.len();
+            let req = json!({ "amount": amount, "recipientId": model.users[recipient] });
+            (user, amount, req, Some(recipient))
+        }
+        _ => unreachable!(),
+    };
+    let user = user % model.users.len();
+    let settings = &app.settings;
+    let boundary = &settings.account.boundary;
+    let allowed = check_limit(settings, &(model.balances[user] + amount), &amount).is_ok()
+        && recipient.map_or(true, |recipient| {
+            let balance = model.balances[recipient] - amount;
+            recipient != user && boundary.lower <= balance && balance <= boundary.upper
+        });
+
+    let (status, body) = app.book(&json!(model.users[user]), req).await;
+    if !allowed {
+        assert_eq!(status, 400, "{:?} was booked: {}", op, body);
+        return;
+    }
+    assert_eq!(status, 200, "{:?} was rejected: {}", op, body);
+    let result: TransactionObject = serde_json::from_value(body["transaction"].clone()).unwrap();
+
+    let mut effects = vec![(user, amount)];
+    if let Some(recipient) = recipient {
+        effects.push((recipient, -amount));
+    }
+    for (user, amount) in effects.iter() {
+        model.balances[*user] += amount;
+    }
+    assert_eq!(result.user.balance, model.balances[user]);
+    let booking = model.bookings.len();
+    model.bookings.push((effects, false));
+    model.transactions.push((result.entity.id, booking));
+    if let Some(id) = result.entity.recipient_transaction_id {
+        model.transactions.push((id, booking));
+    }
+}
+
+async fn undo(app: &TestApp, model: &mut Model, transaction: usize) {
+    if model.transactions.is_empty() {
+        return;
+    }
+    let (id, booking) = model.transactions[transaction % model.transactions.len()];
+    let result = transaction_db::revert_transaction(&app.db, &app.settings, &id).await;
+
+    let (effects, deleted) = &mut model.bookings[booking];
+    match deleted {
+        true => assert!(result.is_err(), "transaction {} was reverted twice", id),
+        false => {
+            let result = result.unwrap();
+            assert!(result.entity.deleted);
+            for (user, amount) in effects.iter() {
+                model.balances[*user] -= amount;
+            }
+            *deleted = true;
+        }
+    }
+}
+
+async fn check_invariants(db: &SqlitePool, model: &Model) {
+    let balances: i64 = sqlx::query_scalar("SELECT COALESCE(SUM(balance), 0) FROM user")
+        .fetch_one(db)
+        .await
+        .unwrap();
+    let amounts: i64 =
+        sqlx::query_scalar("SELECT COALESCE(SUM(amount), 0) FROM transactions WHERE NOT deleted")
+            .fetch_one(db)
+            .await
+            .unwrap();
+    assert_eq!(balances, amounts, "balances differ from the bookings");
+
+    for (idx, id) in model.users.iter().enumerate() {
+        let balance: i32 = sqlx::query_scalar("SELECT balance FROM user WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .unwrap();
+        assert_eq!(balance, model.balances[idx], "balance of user {}", id);
+    }
+
+    let transfers = sqlx::query_as::<_, TransactionEntity>(
+        "SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+        FROM transactions WHERE recipient_transaction_id IS NOT NULL",
+    )
+    .fetch_all(db)
+    .await
+    .unwrap();
+    for sender in transfers.iter() {
+        let recipient = sqlx::query_as::<_, TransactionEntity>(
+            "SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+            FROM transactions WHERE id = ?",
+        )
+        .bind(sender.recipient_transaction_id)
+        .fetch_one(db)
+        .await
+        .unwrap();
+        assert_eq!(recipient.sender_transaction_id, Some(sender.id));
+        assert_eq!(
+            sender.amount + recipient.amount,
+            0,
+            "transfer {}",
+            sender.id
+        );
+        assert_eq!(sender.deleted, recipient.deleted, "transfer {}", sender.id);
+    }
+}
+
+async fn run(users: usize, articles: Vec<i32>, ops: Vec<Op>) {
+    let mut app = TestApp::new().await;
+    app.settings = settings();
+    let mut model = Model::default();
+
+    for _ in 0..users {
+        add_user(&app, &mut model).await;
+    }
+    let mut article_ids = vec![];
+    for (idx, amount) in articles.into_iter().enumerate() {
+        let article =
+            article_db::add_article(&app.db, &format!("article{}", idx), None, amount, 0, &[])
+                .await
+                .unwrap();
+        article_ids.push((article.entity.id, amount));
+    }
+
+    for op in ops.iter() {
+        match op {
+            Op::AddUser => add_user(&app, &mut model).await,
+            Op::Undo { transaction } => undo(&app, &mut model, *transaction).await,
+            _ => book(&app, &mut model, &article_ids, op).await,
+        }
+        check_invariants(&app.db, &model).await;
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn balances_match_bookings(
+        users in 1..5usize,
+        articles in prop::collection::vec(1..800i32, 1..4),
+        ops in prop::collection::vec(op(), 1..40),
+    ) {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(run(users, articles, ops));
+    }
+}
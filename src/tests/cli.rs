@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use super::TestApp;
+use crate::{api_token_db, article_db, cli, transaction_db, user_db};
+
+/// Runs an administrative command against the database of the test server
+async fn run(app: &TestApp, args: &[&str]) -> Result<(), String> {
+    let args: Vec<String> = args.iter().map(|v| v.to_string()).collect();
+    let db_file = app.dir.join("strichliste.sqlite");
+    cli::run(
+        &args,
+        &app.db,
+        &db_file.to_string_lossy(),
+        Arc::new(app.settings.clone()),
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tokio::test]
+async fn manage_users() {
+    let app = TestApp::new().await;
+
+    run(&app, &["user", "add", "alice", "alice@example.com"])
+        .await
+        .unwrap();
+    let users = user_db::get_users(&app.db, Arc::new(app.settings.clone()), false, None)
+        .await
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    let user = &users[0];
+    assert_eq!(user.name, "alice");
+    assert_eq!(user.email.as_deref(), Some("alice@example.com"));
+
+    let id = user.id.to_string();
+    run(&app, &["user", "disable", &id]).await.unwrap();
+    let user = user_db::get_user(&app.db, &app.settings, &user.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(user.disabled);
+    run(&app, &["user", "enable", &id]).await.unwrap();
+    let user = user_db::get_user(&app.db, &app.settings, &user.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!user.disabled);
+
+    assert!(run(&app, &["user", "disable", "999"]).await.is_err());
+    assert!(run(&app, &["user", "disable", "alice"]).await.is_err());
+}
+
+#[tokio::test]
+async fn manage_articles() {
+    let app = TestApp::new().await;
+
+    run(
+        &app,
+        &["article", "add", "Club Mate", "150", "4029764001807"],
+    )
+    .await
+    .unwrap();
+    let articles = article_db::get_articles(&app.db, None, 0, true, false, None)
+        .await
+        .unwrap();
+    assert_eq!(articles.len(), 1);
+    let id = articles[0].entity.id.to_string();
+
+    run(&app, &["article", "update", &id, "Club Mate", "180"])
+        .await
+        .unwrap();
+    let articles = article_db::get_articles(&app.db, None, 0, true, false, None)
+        .await
+        .unwrap();
+    assert_eq!(articles.len(), 1);
+    assert_eq!(articles[0].entity.amount, 180);
+
+    let id = articles[0].entity.id.to_string();
+    run(&app, &["article", "retire", &id]).await.unwrap();
+    let articles = article_db::get_articles(&app.db, None, 0, true, false, None)
+        .await
+        .unwrap();
+    assert!(articles.is_empty());
+
+    assert!(run(&app, &["article", "add", "Tschunk", "much"])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn book_and_revert() {
+    let app = TestApp::new().await;
+    let user = user_db::create_user(&app.db, "alice", None).await.unwrap();
+    let id = user.id.to_string();
+
+    run(&app, &["tx", "book", &id, "-500", "Membership fee"])
+        .await
+        .unwrap();
+    let transactions = transaction_db::get_transactions(&app.db, &app.settings, &user.id, &25, &0)
+        .await
+        .unwrap();
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].entity.amount, -500);
+    assert_eq!(transactions[0].user.balance, -500);
+
+    let transaction_id = transactions[0].entity.id.to_string();
+    run(&app, &["tx", "revert", &transaction_id]).await.unwrap();
+    let user = user_db::get_user(&app.db, &app.settings, &user.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user.balance, 0);
+
+    assert!(run(&app, &["tx", "book", "999", "-500"]).await.is_err());
+}
+
+#[tokio::test]
+async fn manage_tokens() {
+    let app = TestApp::new().await;
+
+    run(&app, &["token", "add", "bridge", "book"])
+        .await
+        .unwrap();
+    let tokens = api_token_db::get_api_tokens(&app.db).await.unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].scope, "book");
+
+    run(&app, &["token", "revoke", &tokens[0].id.to_string()])
+        .await
+        .unwrap();
+    assert!(run(&app, &["token", "add", "bridge", "root"])
+        .await
+        .is_err());
+    assert!(run(
+        &app,
+        &["token", "add", "bridge", "book", "2001-01-01 00:00:00"]
+    )
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn check_database_and_reject_unknown_commands() {
+    let app = TestApp::new().await;
+
+    run(&app, &["db", "check"]).await.unwrap();
+    run(&app, &["db", "migrate"]).await.unwrap();
+    assert!(run(&app, &["user"]).await.is_err());
+    assert!(run(&app, &["frobnicate"]).await.is_err());
+}
@@ -0,0 +1,258 @@
+use serde_json::json;
+
+use super::TestApp;
+
+#[tokio::test]
+async fn pin_required_for_bookings() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let article = app.add_article("Club Mate", 150).await;
+    let pin_path = format!("/api/user/{}/pin", user["id"]);
+    let transaction_path = format!("/api/user/{}/transaction", user["id"]);
+
+    let admin_pin_path = format!("/api/admin/user/{}/pin", user["id"]);
+
+    // the first PIN is set by an admin
+    let (status, _) = app
+        .call("POST", &pin_path, Some(json!({ "pin": "1234" })))
+        .await;
+    assert_eq!(status, 403);
+    let (status, _) = app
+        .call("POST", &admin_pin_path, Some(json!({ "pin": "12ab" })))
+        .await;
+    assert_eq!(status, 400);
+    let (status, body) = app
+        .call("POST", &admin_pin_path, Some(json!({ "pin": "1234" })))
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["user"]["hasPin"], true);
+
+    let (status, _) = app.book(&user["id"], json!({ "amount": -100 })).await;
+    assert_eq!(status, 403);
+    let (status, _) = app
+        .call_with_headers(
+            "POST",
+            &transaction_path,
+            Some(json!({ "amount": -100 })),
+            &[("X-User-Pin", "4321")],
+        )
+        .await;
+    assert_eq!(status, 403);
+    let (status, body) = app
+        .call_with_headers(
+            "POST",
+            &transaction_path,
+            Some(json!({ "amount": -100 })),
+            &[("X-User-Pin", "1234")],
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+
+    let cart = json!({ "lines": [{ "articleId": article["id"] }] });
+    let cart_path = format!("/api/user/{}/cart", user["id"]);
+    let (status, _) = app.call("POST", &cart_path, Some(cart.clone())).await;
+    assert_eq!(status, 403);
+    let (status, _) = app
+        .call_with_headers("POST", &cart_path, Some(cart), &[("X-User-Pin", "1234")])
+        .await;
+    assert_eq!(status, 200);
+
+    // changing the PIN needs the current one
+    let (status, _) = app
+        .call("POST", &pin_path, Some(json!({ "pin": "5678" })))
+        .await;
+    assert_eq!(status, 403);
+    let (status, body) = app
+        .call("POST", &pin_path, Some(json!({ "currentPin": "1234" })))
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["user"]["hasPin"], false);
+
+    let (status, _) = app.book(&user["id"], json!({ "amount": -100 })).await;
+    assert_eq!(status, 200);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -350);
+}
+
+#[tokio::test]
+async fn find_user_by_token() {
+    let app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+    let token_path = format!("/api/user/{}/token", alice["id"]);
+
+    let (status, body) = app
+        .call("POST", &token_path, Some(json!({ "uid": "04:a2:3b:1c" })))
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["token"]["uid"], "04A23B1C");
+    let token_id = body["token"]["id"].clone();
+
+    let (status, body) = app.call("GET", "/api/user/token/04a23b1c", None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["user"]["id"], alice["id"]);
+
+    // a card belongs to one user only
+    let (status, _) = app
+        .call(
+            "POST",
+            &format!("/api/user/{}/token", bob["id"]),
+            Some(json!({ "uid": "04A23B1C" })),
+        )
+        .await;
+    assert_eq!(status, 409);
+    let (status, _) = app
+        .call("POST", &token_path, Some(json!({ "uid": "04?a2" })))
+        .await;
+    assert_eq!(status, 400);
+
+    let (_, body) = app.call("GET", &token_path, None).await;
+    assert_eq!(body["count"], 1);
+
+    let (status, _) = app
+        .call("DELETE", &format!("{}/{}", token_path, token_id), None)
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app.call("GET", "/api/user/token/04A23B1C", None).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn pin_required_for_cards_and_scheduled_transactions() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    app.call(
+        "POST",
+        &format!("/api/admin/user/{}/pin", user["id"]),
+        Some(json!({ "pin": "1234" })),
+    )
+    .await;
+    let token_path = format!("/api/user/{}/token", user["id"]);
+    let scheduled_path = format!("/api/user/{}/scheduled", user["id"]);
+
+    let (status, _) = app.call("GET", &token_path, None).await;
+    assert_eq!(status, 403);
+    let (status, body) = app
+        .call_with_headers("GET", &token_path, None, &[("X-User-Pin", "1234")])
+        .await;
+    assert_eq!(status, 200, "{}", body);
+
+    let scheduled = json!({ "amount": -1500, "interval": "monthly" });
+    let (status, _) = app
+        .call("POST", &scheduled_path, Some(scheduled.clone()))
+        .await;
+    assert_eq!(status, 403);
+    let (status, body) = app
+        .call_with_headers(
+            "POST",
+            &scheduled_path,
+            Some(scheduled),
+            &[("X-User-Pin", "1234")],
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+
+    let cancel_path = format!("{}/{}", scheduled_path, body["scheduledTransaction"]["id"]);
+    let (status, _) = app.call("DELETE", &cancel_path, None).await;
+    assert_eq!(status, 403);
+    let (status, _) = app
+        .call_with_headers("DELETE", &cancel_path, None, &[("X-User-Pin", "1234")])
+        .await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn wrong_pins_lock_the_pin() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    app.call(
+        "POST",
+        &format!("/api/admin/user/{}/pin", user["id"]),
+        Some(json!({ "pin": "1234" })),
+    )
+    .await;
+    let transaction_path = format!("/api/user/{}/transaction", user["id"]);
+    let booking = json!({ "amount": -100 });
+
+    for _ in 0..5 {
+        let (status, _) = app
+            .call_with_headers(
+                "POST",
+                &transaction_path,
+                Some(booking.clone()),
+                &[("X-User-Pin", "0000")],
+            )
+            .await;
+        assert_eq!(status, 403);
+    }
+    // locked, even for the right PIN
+    let (status, _) = app
+        .call_with_headers(
+            "POST",
+            &transaction_path,
+            Some(booking.clone()),
+            &[("X-User-Pin", "1234")],
+        )
+        .await;
+    assert_eq!(status, 429);
+
+    // once the lockout is over, the right PIN works and clears the count
+    sqlx::query("UPDATE user_pin SET locked_until = datetime('now', 'localtime', '-1 seconds')")
+        .execute(&app.db)
+        .await
+        .unwrap();
+    let (status, body) = app
+        .call_with_headers(
+            "POST",
+            &transaction_path,
+            Some(booking.clone()),
+            &[("X-User-Pin", "1234")],
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let failed: i32 = sqlx::query_scalar("SELECT failed_attempts FROM user_pin")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(failed, 0);
+}
+
+#[tokio::test]
+async fn concurrent_wrong_pins_are_all_counted() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    app.call(
+        "POST",
+        &format!("/api/admin/user/{}/pin", user["id"]),
+        Some(json!({ "pin": "1234" })),
+    )
+    .await;
+    let path = format!("/api/user/{}/token", user["id"]);
+    let (app, path) = (&app, &path);
+    let guess = move |pin: &'static str| async move {
+        app.call_with_headers("GET", path, None, &[("X-User-Pin", pin)])
+            .await
+            .0
+    };
+
+    let statuses = tokio::join!(
+        guess("0000"),
+        guess("0001"),
+        guess("0002"),
+        guess("0003"),
+        guess("0004"),
+        guess("0005"),
+        guess("0006"),
+        guess("0007"),
+    );
+    let statuses = vec![
+        statuses.0, statuses.1, statuses.2, statuses.3, statuses.4, statuses.5, statuses.6,
+        statuses.7,
+    ];
+    // no more PINs are tried than allowed before the lockout
+    assert_eq!(statuses.iter().filter(|s| **s == 403).count(), 5);
+    assert_eq!(statuses.iter().filter(|s| **s == 429).count(), 3);
+}
@@ -0,0 +1,171 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use serde_json::json;
+
+use super::TestApp;
+use crate::{
+    email::{self, Mailer},
+    events::EventBus,
+};
+
+/// Local SMTP server accepting every message, passes on the data of the messages
+fn smtp_stand_in() -> (u16, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(v) => v,
+                Err(_) => return,
+            };
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let _ = writer.write_all(b"220 localhost ESMTP\r\n");
+            let mut data: Option<String> = None;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                match reader.read_line(&mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => (),
+                }
+                if let Some(message) = data.as_mut() {
+                    if line.trim_end() == "." {
+                        let _ = sender.send(data.take().unwrap());
+                        let _ = writer.write_all(b"250 OK\r\n");
+                    } else {
+                        message.push_str(&line);
+                    }
+                    continue;
+                }
+                let command = line.to_uppercase();
+                if command.starts_with("QUIT") {
+                    let _ = writer.write_all(b"221 Bye\r\n");
+                    break;
+                }
+                let reply: &[u8] = match command.starts_with("DATA") {
+                    true => {
+                        data = Some(String::new());
+                        b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                    }
+                    false => b"250 OK\r\n",
+                };
+                let _ = writer.write_all(reply);
+            }
+        }
+    });
+    (port, receiver)
+}
+
+#[tokio::test]
+async fn send_reminders_queued_before_start() {
+    let (port, received) = smtp_stand_in();
+    let mut app = TestApp::new().await;
+    app.backend.email.enabled = true;
+    app.backend.email.host = "127.0.0.1".to_string();
+    app.backend.email.port = port;
+    app.backend.email.security = "none".to_string();
+    app.backend.email.sender = "kiosk@example.org".to_string();
+    app.events = EventBus::new(&app.backend);
+    let (status, body) = app
+        .call(
+            "POST",
+            "/api/user",
+            Some(json!({ "name": "alice", "email": "alice@example.org" })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .book(&body["user"]["id"], json!({ "amount": -150 }))
+        .await;
+    assert_eq!(status, 200);
+
+    // started only now, like after a restart or having missed the event
+    let mailer = Arc::new(Mailer::new(
+        app.backend.email.clone(),
+        Arc::new(app.settings.clone()),
+    ));
+    tokio::spawn(email::run_reminders(
+        app.db.clone(),
+        app.events.subscribe(),
+        mailer,
+    ));
+
+    let message =
+        tokio::task::spawn_blocking(move || received.recv_timeout(Duration::from_secs(5)))
+            .await
+            .unwrap()
+            .expect("no email within 5 seconds");
+    assert!(message.contains("alice@example.org"), "{}", message);
+    let queued: i32 = sqlx::query_scalar("SELECT COUNT(*) FROM email_reminder")
+        .fetch_one(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(queued, 0);
+}
+
+#[tokio::test]
+async fn keep_reminders_the_mail_server_refused() {
+    // nothing listens on the port once the listener is dropped
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut app = TestApp::new().await;
+    app.backend.email.enabled = true;
+    app.backend.email.host = "127.0.0.1".to_string();
+    app.backend.email.port = port;
+    app.backend.email.security = "none".to_string();
+    app.backend.email.sender = "kiosk@example.org".to_string();
+    app.events = EventBus::new(&app.backend);
+    let (status, body) = app
+        .call(
+            "POST",
+            "/api/user",
+            Some(json!({ "name": "alice", "email": "alice@example.org" })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .book(&body["user"]["id"], json!({ "amount": -150 }))
+        .await;
+    assert_eq!(status, 200);
+
+    let mailer = Arc::new(Mailer::new(
+        app.backend.email.clone(),
+        Arc::new(app.settings.clone()),
+    ));
+    tokio::spawn(email::run_reminders(
+        app.db.clone(),
+        app.events.subscribe(),
+        mailer,
+    ));
+
+    let mut attempts = 0;
+    for _ in 0..50 {
+        attempts = sqlx::query_scalar("SELECT attempts FROM email_reminder")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+        if attempts > 0 {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(100)).await;
+    }
+    assert_eq!(attempts, 1);
+    let (failed, error): (bool, Option<String>) =
+        sqlx::query_as("SELECT failed, last_error FROM email_reminder")
+            .fetch_one(&app.db)
+            .await
+            .unwrap();
+    assert!(!failed);
+    assert!(error.is_some());
+}
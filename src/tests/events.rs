@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use super::TestApp;
+
+#[tokio::test]
+async fn event_stream_ends_on_close() {
+    let app = TestApp::new().await;
+    let routes = app.routes();
+    let stream = tokio::spawn(async move {
+        warp::test::request()
+            .path("/api/events")
+            .reply(&routes)
+            .await
+    });
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+
+    app.events.close();
+    let response = tokio::time::timeout(Duration::from_secs(5), stream)
+        .await
+        .expect("the event stream did not end")
+        .unwrap();
+    assert_eq!(response.status(), 200);
+}
@@ -0,0 +1,31 @@
+use super::TestApp;
+
+#[tokio::test]
+async fn return_request_id() {
+    let app = TestApp::new().await;
+    let routes = app.routes();
+
+    let response = warp::test::request()
+        .path("/api/settings")
+        .header("X-Request-Id", "kiosk-42")
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["x-request-id"], "kiosk-42");
+
+    // errors carry the id as well, new ones are generated if the client sends none
+    let first = warp::test::request()
+        .path("/api/user/42")
+        .reply(&routes)
+        .await;
+    let second = warp::test::request()
+        .path("/api/user/42")
+        .reply(&routes)
+        .await;
+    assert_eq!(first.status(), 404);
+    assert!(!first.headers()["x-request-id"].is_empty());
+    assert_ne!(
+        first.headers()["x-request-id"],
+        second.headers()["x-request-id"]
+    );
+}
@@ -0,0 +1,184 @@
+use serde_json::json;
+
+use super::TestApp;
+
+#[tokio::test]
+async fn system_metrics() {
+    let app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+    app.book(&alice["id"], json!({ "amount": 1000 })).await;
+    app.book(&bob["id"], json!({ "amount": -250 })).await;
+
+    let (status, body) = app.call("GET", "/api/metrics", None).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["balance"], 750);
+    assert_eq!(body["transactionCount"], 2);
+    assert_eq!(body["userCount"], 2);
+
+    let days = body["days"].as_array().unwrap();
+    assert_eq!(days.len(), 1);
+    assert_eq!(days[0]["transactions"], 2);
+    assert_eq!(days[0]["distinctUsers"], 2);
+    assert_eq!(days[0]["balance"], 750);
+    assert_eq!(days[0]["charged"]["amount"], 1000);
+    assert_eq!(days[0]["spent"]["amount"], -250);
+}
+
+#[tokio::test]
+async fn user_metrics() {
+    let app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+    let mate = app.add_article("Club Mate", 150).await;
+    let beer = app.add_article("Beer", 200).await;
+
+    app.book(&alice["id"], json!({ "amount": 2000 })).await;
+    app.book(&alice["id"], json!({ "articleId": mate["id"] }))
+        .await;
+    app.book(
+        &alice["id"],
+        json!({ "articleId": mate["id"], "quantity": 2 }),
+    )
+    .await;
+    app.book(&alice["id"], json!({ "articleId": beer["id"] }))
+        .await;
+    app.book(
+        &alice["id"],
+        json!({ "amount": -500, "recipientId": bob["id"] }),
+    )
+    .await;
+    app.book(
+        &bob["id"],
+        json!({ "amount": -100, "recipientId": alice["id"] }),
+    )
+    .await;
+
+    let (status, body) = app
+        .call("GET", &format!("/api/user/{}/metrics", alice["id"]), None)
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["balance"], 2000 - 450 - 200 - 500 + 100);
+
+    let articles = body["articles"].as_array().unwrap();
+    assert_eq!(articles.len(), 2);
+    assert_eq!(articles[0]["article"]["id"], mate["id"]);
+    assert_eq!(articles[0]["count"], 2);
+    assert_eq!(articles[0]["amount"], 450);
+    assert_eq!(articles[1]["article"]["id"], beer["id"]);
+    assert_eq!(articles[1]["amount"], 200);
+
+    let transactions = &body["transactions"];
+    assert_eq!(transactions["count"], 6);
+    assert_eq!(transactions["outgoing"]["count"], 1);
+    assert_eq!(transactions["outgoing"]["amount"], -500);
+    assert_eq!(transactions["incoming"]["count"], 1);
+    assert_eq!(transactions["incoming"]["amount"], 100);
+}
+
+#[tokio::test]
+async fn unknown_user_metrics() {
+    let app = TestApp::new().await;
+
+    let (status, _) = app.call("GET", "/api/user/42/metrics", None).await;
+
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn category_metrics() {
+    let app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+    let (_, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Club Mate", "amount": 150, "categories": ["Drinks"] })),
+        )
+        .await;
+    let mate = body["article"].clone();
+    let (_, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Chips", "amount": 100, "categories": ["Snacks"] })),
+        )
+        .await;
+    let chips = body["article"].clone();
+
+    app.book(
+        &alice["id"],
+        json!({ "articleId": mate["id"], "quantity": 2 }),
+    )
+    .await;
+    app.book(&alice["id"], json!({ "articleId": chips["id"] }))
+        .await;
+    app.book(&bob["id"], json!({ "articleId": mate["id"] }))
+        .await;
+
+    let (_, body) = app.call("GET", "/api/metrics", None).await;
+    assert_eq!(
+        body["categories"],
+        json!([
+            { "category": "Drinks", "count": 2, "amount": 450 },
+            { "category": "Snacks", "count": 1, "amount": 100 },
+        ])
+    );
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}/metrics", bob["id"]), None)
+        .await;
+    assert_eq!(
+        body["categories"],
+        json!([{ "category": "Drinks", "count": 1, "amount": 150 }])
+    );
+}
+
+#[tokio::test]
+async fn deposit_metrics() {
+    let app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let (_, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Beer", "amount": 200, "deposit": 8, "categories": ["Drinks"] })),
+        )
+        .await;
+    let beer = body["article"].clone();
+
+    app.book(
+        &alice["id"],
+        json!({ "articleId": beer["id"], "quantity": 5 }),
+    )
+    .await;
+    app.book(
+        &alice["id"],
+        json!({ "articleId": beer["id"], "quantity": 3, "returnDeposit": true }),
+    )
+    .await;
+
+    let (_, body) = app.call("GET", "/api/metrics", None).await;
+    assert_eq!(
+        body["deposits"],
+        json!({
+            "charged": 40,
+            "chargedQuantity": 5,
+            "returned": 24,
+            "returnedQuantity": 3,
+            "outstanding": 16,
+        })
+    );
+    // deposits are no purchases
+    assert_eq!(
+        body["categories"],
+        json!([{ "category": "Drinks", "count": 1, "amount": 1000 }])
+    );
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}/metrics", alice["id"]), None)
+        .await;
+    assert_eq!(body["articles"][0]["count"], 1);
+    assert_eq!(body["articles"][0]["amount"], 1000);
+}
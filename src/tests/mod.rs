@@ -0,0 +1,154 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde_json::Value;
+use sqlx::SqlitePool;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{db, events::EventBus, settings};
+
+mod api_token;
+mod article;
+mod backup;
+mod balance;
+mod cli;
+mod credential;
+mod email;
+mod events;
+mod logging;
+mod metrics;
+mod openapi;
+mod pending;
+mod ratelimit;
+mod schedule;
+mod settings;
+mod tls;
+mod transaction;
+mod user;
+mod webhook;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Admin token of the test servers
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Server state backed by a migrated database in a temporary file, removed on drop
+pub struct TestApp {
+    pub db: SqlitePool,
+    pub events: EventBus,
+    pub settings: settings::StrichlisteSetting,
+    pub backend: settings::BackendSetting,
+    dir: PathBuf,
+}
+
+impl TestApp {
+    pub async fn new() -> TestApp {
+        let dir = std::env::temp_dir().join(format!(
+            "strichliste-rs-test-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let db_file = dir.join("strichliste.sqlite");
+        let db = db::open_db(&db_file.to_string_lossy()).await.unwrap();
+        db::migrate_db(&db).await.unwrap();
+
+        let mut backend = settings::BackendSetting::default();
+        backend.backup.directory = dir.join("backup").to_string_lossy().to_string();
+        backend.backup.download = true;
+        backend.admin_token = ADMIN_TOKEN.to_string();
+        // the routes outside of /api/admin are called without a token
+        backend.api_tokens.anonymous = "admin".to_string();
+
+        TestApp {
+            db,
+            events: EventBus::default(),
+            settings: settings::StrichlisteSetting::default(),
+            backend,
+            dir,
+        }
+    }
+
+    pub fn routes(&self) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        crate::routes(
+            self.db.clone(),
+            self.events.clone(),
+            self.settings.clone(),
+            &self.backend,
+        )
+    }
+
+    /// Sends a request through the routes, with the admin token to `/api/admin`,
+    /// and returns the status and the JSON body, Null if there is none.
+    pub async fn call(&self, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+        let headers: &[(&str, &str)] = match path.starts_with("/api/admin") {
+            true => &[("x-admin-token", ADMIN_TOKEN)],
+            false => &[],
+        };
+        self.call_with_headers(method, path, body, headers).await
+    }
+
+    /// Like `call`, with the given request headers instead of the admin token
+    pub async fn call_with_headers(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<Value>,
+        headers: &[(&str, &str)],
+    ) -> (u16, Value) {
+        let mut request = warp::test::request().method(method).path(path);
+        for (key, value) in headers.iter() {
+            request = request.header(*key, *value);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.reply(&self.routes()).await;
+
+        let json = serde_json::from_slice(response.body()).unwrap_or(Value::Null);
+        (response.status().as_u16(), json)
+    }
+
+    pub async fn add_user(&self, name: &str) -> Value {
+        let (status, body) = self
+            .call(
+                "POST",
+                "/api/user",
+                Some(serde_json::json!({ "name": name })),
+            )
+            .await;
+        assert_eq!(status, 200, "{}", body);
+        body["user"].clone()
+    }
+
+    pub async fn add_article(&self, name: &str, amount: i32) -> Value {
+        let (status, body) = self
+            .call(
+                "POST",
+                "/api/article",
+                Some(serde_json::json!({ "name": name, "amount": amount })),
+            )
+            .await;
+        assert_eq!(status, 200, "{}", body);
+        body["article"].clone()
+    }
+
+    pub async fn book(&self, user_id: &Value, req: Value) -> (u16, Value) {
+        self.call(
+            "POST",
+            &format!("/api/user/{}/transaction", user_id),
+            Some(req),
+        )
+        .await
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
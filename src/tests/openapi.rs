@@ -0,0 +1,173 @@
+use std::collections::{BTreeSet, HashMap};
+
+use regex::Regex;
+use warp::reject::MethodNotAllowed;
+
+use super::{TestApp, ADMIN_TOKEN};
+use crate::openapi;
+
+const METHODS: &[&str] = &["get", "post", "put", "patch", "delete"];
+
+/// Every documented operation has to be routed and every other method on a documented path rejected.
+#[tokio::test]
+async fn spec_matches_routes() {
+    let app = TestApp::new().await;
+    let routes = app.routes();
+    let spec = openapi::spec();
+
+    let paths = spec["paths"].as_object().unwrap();
+    assert!(!paths.is_empty());
+    for (path, item) in paths.iter() {
+        let request_path = with_ids(path);
+        for method in METHODS.iter() {
+            let documented = item.get(*method).is_some();
+            let routed = match warp::test::request()
+                .method(method)
+                .path(&request_path)
+                .header("x-admin-token", ADMIN_TOKEN)
+                .filter(&routes)
+                .await
+            {
+                Ok(_) => true,
+                Err(r) => !r.is_not_found() && r.find::<MethodNotAllowed>().is_none(),
+            };
+            assert_eq!(
+                documented, routed,
+                "{} {}: documented {}, routed {}",
+                method, path, documented, routed
+            );
+        }
+    }
+}
+
+/// Replaces the path parameters, `1` is a valid id as well as a card uid
+fn with_ids(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.starts_with('{') {
+            true => "1",
+            false => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Every route declared in main.rs has to be documented, the probing above only covers documented paths.
+#[test]
+fn routes_are_documented() {
+    let spec = openapi::spec();
+    let parameter = Regex::new(r"\{[^}]*\}").unwrap();
+    let documented: BTreeSet<(String, String)> = spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, item)| {
+            let path = parameter.replace_all(path, "{}").to_string();
+            item.as_object()
+                .unwrap()
+                .keys()
+                .filter(|method| METHODS.contains(&method.as_str()))
+                .map(move |method| (method.clone(), path.clone()))
+        })
+        .collect();
+    let declared = declared_routes();
+
+    let undocumented: Vec<_> = declared.difference(&documented).collect();
+    assert!(
+        undocumented.is_empty(),
+        "undocumented routes: {:?}",
+        undocumented
+    );
+    // guards the parsing below as well
+    let undeclared: Vec<_> = documented.difference(&declared).collect();
+    assert!(
+        undeclared.is_empty(),
+        "documented routes not found in main.rs: {:?}",
+        undeclared
+    );
+}
+
+/// Method and path of the routes declared in `routes()` of main.rs, with `{}` for the path parameters.
+/// Relies on the layout used there: one `let` per route, prefixes mounted via `<name>_path.and(...)`.
+fn declared_routes() -> BTreeSet<(String, String)> {
+    let source = include_str!("../main.rs");
+    let start = source.find("fn routes(").unwrap();
+    let end = start
+        + source[start..]
+            .find("let api = warp::path(\"api\")")
+            .unwrap();
+
+    let route = Regex::new(r"let (\w+) = warp::(get|post|put|patch|delete)\(\)").unwrap();
+    let path = Regex::new(r"warp::path!\(([^)]*)\)").unwrap();
+    let prefix = Regex::new(r#"let (\w+) = warp::path\("([^"]*)"\)"#).unwrap();
+    let mount = Regex::new(r"let \w+ = (\w+)\.and\((.*)\)$").unwrap();
+    let ident = Regex::new(r"\w+").unwrap();
+
+    let mut routes: Vec<(String, String, Vec<String>)> = vec![];
+    let mut prefixes: HashMap<String, String> = HashMap::new();
+    let mut mounted: HashMap<String, String> = HashMap::new();
+    for statement in source[start..end].split(';') {
+        let statement = statement.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(c) = route.captures(&statement) {
+            // routes without path! match the end of their prefix
+            let segments = path.captures(&statement).map_or(vec![], |p| {
+                p[1].split('/')
+                    .map(|s| match s.trim() {
+                        s if s.starts_with('"') => s.trim_matches('"').to_string(),
+                        _ => "{}".to_string(),
+                    })
+                    .collect()
+            });
+            routes.push((c[1].to_string(), c[2].to_string(), segments));
+        } else if let Some(c) = prefix.captures(&statement) {
+            prefixes.insert(c[1].to_string(), c[2].to_string());
+        } else if let Some(c) = mount.captures(&statement) {
+            if let Some(p) = prefixes.get(&c[1]) {
+                for name in ident.find_iter(&c[2]) {
+                    mounted.insert(name.as_str().to_string(), p.clone());
+                }
+            }
+        }
+    }
+
+    routes
+        .into_iter()
+        .map(|(name, method, segments)| {
+            let segments: Vec<String> = mounted
+                .get(&name)
+                .cloned()
+                .into_iter()
+                .chain(segments)
+                .collect();
+            (method, format!("/{}", segments.join("/")))
+        })
+        .collect()
+}
+
+#[test]
+fn spec_references_resolve() {
+    let spec = openapi::spec();
+    let schemas = spec["components"]["schemas"].as_object().unwrap();
+
+    let text = spec.to_string();
+    for reference in text.split("\"$ref\":\"").skip(1) {
+        let name = reference
+            .split('"')
+            .next()
+            .unwrap()
+            .trim_start_matches("#/components/schemas/");
+        assert!(schemas.contains_key(name), "unresolved schema {}", name);
+    }
+}
+
+#[tokio::test]
+async fn spec_is_served() {
+    let app = TestApp::new().await;
+    let response = warp::test::request()
+        .path("/api/openapi.json")
+        .reply(&app.routes())
+        .await;
+
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body, openapi::spec());
+}
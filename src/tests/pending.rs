@@ -0,0 +1,333 @@
+use serde_json::json;
+
+use super::TestApp;
+
+#[tokio::test]
+async fn hold_and_approve() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    app.backend.approval.enabled = true;
+    let user = app.add_user("guest").await;
+    let article = app.add_article("Club Mate", 600).await;
+    app.book(&user["id"], json!({ "amount": -800 })).await;
+
+    let (status, body) = app
+        .book(
+            &user["id"],
+            json!({ "articleId": article["id"], "comment": "party" }),
+        )
+        .await;
+    assert_eq!(status, 202, "{}", body);
+    let pending = &body["pendingTransaction"];
+    assert_eq!(pending["state"], "pending");
+    assert_eq!(pending["amount"], -600);
+    assert_eq!(pending["articleId"], article["id"]);
+
+    // not applied until approved
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -800);
+    let (_, body) = app.call("GET", "/api/admin/pending", None).await;
+    assert_eq!(body["count"], 1);
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &format!("/api/admin/pending/{}/approve", pending["id"]),
+            None,
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["pendingTransaction"]["state"], "approved");
+    assert!(body["pendingTransaction"]["transactionId"].is_number());
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -1400);
+    let (_, body) = app.call("GET", "/api/admin/pending", None).await;
+    assert_eq!(body["count"], 0);
+
+    // a decision is final
+    let (status, _) = app
+        .call(
+            "POST",
+            &format!("/api/admin/pending/{}/reject", pending["id"]),
+            None,
+        )
+        .await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn hold_and_reject() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    app.backend.approval.enabled = true;
+    let user = app.add_user("guest").await;
+
+    let (status, body) = app.book(&user["id"], json!({ "amount": -1200 })).await;
+    assert_eq!(status, 202, "{}", body);
+    let id = &body["pendingTransaction"]["id"];
+
+    let (status, body) = app
+        .call("POST", &format!("/api/admin/pending/{}/reject", id), None)
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["pendingTransaction"]["state"], "rejected");
+    assert_eq!(body["pendingTransaction"]["transactionId"], json!(null));
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+    let (_, body) = app.call("GET", "/api/admin/pending?state=all", None).await;
+    assert_eq!(body["count"], 1);
+}
+
+#[tokio::test]
+async fn stale_holds_are_not_approved() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    app.backend.approval.enabled = true;
+    let user = app.add_user("guest").await;
+    let article = app.add_article("Club Mate", 600).await;
+    app.book(&user["id"], json!({ "amount": -800 })).await;
+
+    let (status, body) = app
+        .book(&user["id"], json!({ "articleId": article["id"] }))
+        .await;
+    assert_eq!(status, 202, "{}", body);
+    let purchase = body["pendingTransaction"]["id"].clone();
+    let (status, body) = app.book(&user["id"], json!({ "amount": -300 })).await;
+    assert_eq!(status, 202, "{}", body);
+    let payment = body["pendingTransaction"]["id"].clone();
+
+    // repriced after the booking was held
+    let (status, _) = app
+        .call(
+            "POST",
+            &format!("/api/article/{}", article["id"]),
+            Some(json!({ "name": "Club Mate", "amount": 700 })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .call(
+            "POST",
+            &format!("/api/admin/pending/{}/approve", purchase),
+            None,
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    let (status, _) = app
+        .call(
+            "POST",
+            &format!("/api/user/{}", user["id"]),
+            Some(json!({ "name": "guest", "isDisabled": true })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = app
+        .call(
+            "POST",
+            &format!("/api/admin/pending/{}/approve", payment),
+            None,
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    // both can still be rejected, nothing was booked
+    for id in [purchase, payment].iter() {
+        let (status, _) = app
+            .call("POST", &format!("/api/admin/pending/{}/reject", id), None)
+            .await;
+        assert_eq!(status, 200);
+    }
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -800);
+}
+
+#[tokio::test]
+async fn payment_boundary_is_not_held() {
+    let mut app = TestApp::new().await;
+    app.backend.approval.enabled = true;
+    let user = app.add_user("guest").await;
+    let lower = app.settings.payment.boundary.lower;
+
+    let (status, _) = app.book(&user["id"], json!({ "amount": lower - 1 })).await;
+
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn approval_needs_the_admin_token() {
+    let app = TestApp::new().await;
+    let routes = app.routes();
+
+    for token in [None, Some("wrong")].iter() {
+        for (method, path) in [
+            ("GET", "/api/admin/pending"),
+            ("POST", "/api/admin/pending/1/approve"),
+            ("POST", "/api/admin/pending/1/reject"),
+        ]
+        .iter()
+        {
+            let mut request = warp::test::request().method(method).path(path);
+            if let Some(token) = token {
+                request = request.header("x-admin-token", *token);
+            }
+            let response = request.reply(&routes).await;
+            assert_eq!(response.status(), 401, "{} {}", method, path);
+        }
+    }
+}
+
+#[tokio::test]
+async fn hold_transfer_deposit_return_and_cart() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    app.settings.account.boundary.upper = 1000;
+    app.backend.approval.enabled = true;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+    let (status, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Club Mate", "amount": 400, "deposit": 100 })),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let article = body["article"].clone();
+    app.book(&bob["id"], json!({ "amount": 900 })).await;
+
+    // the recipient would leave the boundary
+    let (status, body) = app
+        .book(
+            &alice["id"],
+            json!({ "amount": -200, "recipientId": bob["id"] }),
+        )
+        .await;
+    assert_eq!(status, 202, "{}", body);
+    let transfer = body["pendingTransaction"].clone();
+    assert_eq!(transfer["recipientId"], bob["id"]);
+
+    let (status, body) = app
+        .book(
+            &bob["id"],
+            json!({ "articleId": article["id"], "quantity": 2, "returnDeposit": true }),
+        )
+        .await;
+    assert_eq!(status, 202, "{}", body);
+    let deposit_return = body["pendingTransaction"].clone();
+    assert_eq!(deposit_return["returnDeposit"], true);
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &format!("/api/user/{}/cart", alice["id"]),
+            Some(json!({ "lines": [
+                { "articleId": article["id"], "quantity": 2 },
+                { "articleId": article["id"], "quantity": 1 },
+            ], "comment": "party" })),
+        )
+        .await;
+    assert_eq!(status, 202, "{}", body);
+    assert_eq!(body["count"], 1);
+    let cart = body["pendingTransactions"][0].clone();
+    assert_eq!(cart["cartPendingId"], cart["id"]);
+    assert_eq!(cart["amount"], -1200);
+
+    // nothing is applied until approved
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", alice["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", bob["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 900);
+
+    for pending in [&transfer, &deposit_return, &cart].iter() {
+        let (status, body) = app
+            .call(
+                "POST",
+                &format!("/api/admin/pending/{}/approve", pending["id"]),
+                None,
+            )
+            .await;
+        assert_eq!(status, 200, "{}", body);
+        assert!(body["pendingTransaction"]["transactionId"].is_number());
+    }
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", alice["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -200 - 1200 - 300);
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", bob["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 900 + 200 + 200);
+}
+
+#[tokio::test]
+async fn retry_held_booking_with_idempotency_key() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    app.backend.approval.enabled = true;
+    let user = app.add_user("guest").await;
+    let article = app.add_article("Club Mate", 600).await;
+    let req = json!({ "amount": -1200, "idempotencyKey": "held-1" });
+
+    let (status, first) = app.book(&user["id"], req.clone()).await;
+    assert_eq!(status, 202, "{}", first);
+    let (status, retry) = app.book(&user["id"], req.clone()).await;
+    assert_eq!(status, 202, "{}", retry);
+    assert_eq!(
+        retry["pendingTransaction"]["id"],
+        first["pendingTransaction"]["id"]
+    );
+
+    let cart_path = format!("/api/user/{}/cart", user["id"]);
+    let cart = json!({
+        "lines": [{ "articleId": article["id"], "quantity": 2 }],
+        "idempotencyKey": "held-cart",
+    });
+    let (status, first_cart) = app.call("POST", &cart_path, Some(cart.clone())).await;
+    assert_eq!(status, 202, "{}", first_cart);
+    let (status, retry_cart) = app.call("POST", &cart_path, Some(cart.clone())).await;
+    assert_eq!(status, 202, "{}", retry_cart);
+    assert_eq!(
+        retry_cart["pendingTransactions"],
+        first_cart["pendingTransactions"]
+    );
+
+    let (_, body) = app.call("GET", "/api/admin/pending", None).await;
+    assert_eq!(body["count"], 2);
+
+    // once approved, a retry returns the booking
+    let (_, approved) = app
+        .call(
+            "POST",
+            &format!(
+                "/api/admin/pending/{}/approve",
+                first["pendingTransaction"]["id"]
+            ),
+            None,
+        )
+        .await;
+    let (status, retry) = app.book(&user["id"], req).await;
+    assert_eq!(status, 200, "{}", retry);
+    assert_eq!(
+        retry["transaction"]["id"],
+        approved["pendingTransaction"]["transactionId"]
+    );
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -1200);
+}
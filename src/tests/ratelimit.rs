@@ -0,0 +1,64 @@
+use serde_json::json;
+
+use super::TestApp;
+
+#[tokio::test]
+async fn limit_writes_per_user() {
+    let mut app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    app.backend.rate_limit.enabled = true;
+    app.backend.rate_limit.per_user.write.rate = 0.01;
+    app.backend.rate_limit.per_user.write.burst = 2;
+    let routes = app.routes();
+    let path = format!("/api/user/{}/transaction", user["id"]);
+
+    let book = || {
+        warp::test::request()
+            .method("POST")
+            .path(&path)
+            .json(&json!({ "amount": 100 }))
+            .reply(&routes)
+    };
+    assert_eq!(book().await.status(), 200);
+    assert_eq!(book().await.status(), 200);
+    let response = book().await;
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()["retry-after"], "100");
+
+    // reads have a budget of their own
+    let response = warp::test::request().path(&path).reply(&routes).await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["count"], 2);
+
+    // as do other users
+    let bob = app.add_user("bob").await;
+    let response = warp::test::request()
+        .method("POST")
+        .path(&format!("/api/user/{}/transaction", bob["id"]))
+        .json(&json!({ "amount": 100 }))
+        .reply(&routes)
+        .await;
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn limit_per_ip() {
+    let mut app = TestApp::new().await;
+    app.backend.rate_limit.enabled = true;
+    app.backend.rate_limit.per_ip.read.rate = 0.5;
+    app.backend.rate_limit.per_ip.read.burst = 1;
+    let routes = app.routes();
+
+    let get = |addr: &str| {
+        warp::test::request()
+            .path("/api/settings")
+            .remote_addr(addr.parse().unwrap())
+            .reply(&routes)
+    };
+    assert_eq!(get("192.0.2.1:4000").await.status(), 200);
+    let response = get("192.0.2.1:4001").await;
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()["retry-after"], "2");
+    assert_eq!(get("192.0.2.2:4000").await.status(), 200);
+}
@@ -0,0 +1,222 @@
+use serde_json::json;
+
+use super::TestApp;
+use crate::schedule;
+
+#[tokio::test]
+async fn book_scheduled_transaction() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let path = format!("/api/user/{}/scheduled", user["id"]);
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": -1500, "comment": "membership", "interval": "monthly" })),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let scheduled = body["scheduledTransaction"].clone();
+    assert_eq!(scheduled["isActive"], true);
+
+    schedule::book_due_transactions(&app.db, &app.settings, &app.events)
+        .await
+        .unwrap();
+
+    let (_, body) = app
+        .call(
+            "GET",
+            &format!("/api/user/{}/transaction", user["id"]),
+            None,
+        )
+        .await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["transactions"][0]["amount"], -1500);
+    assert_eq!(body["transactions"][0]["comment"], "membership");
+
+    // the next run is a month later, so nothing is due anymore
+    let (_, body) = app.call("GET", &path, None).await;
+    assert_eq!(body["count"], 1);
+    assert!(body["scheduledTransactions"][0]["nextRun"].as_str() > scheduled["nextRun"].as_str());
+    schedule::book_due_transactions(&app.db, &app.settings, &app.events)
+        .await
+        .unwrap();
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -1500);
+}
+
+#[tokio::test]
+async fn catch_up_and_skip_out_of_boundary() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+    app.call(
+        "POST",
+        &format!("/api/user/{}/scheduled", alice["id"]),
+        Some(json!({ "amount": 100, "interval": "daily" })),
+    )
+    .await;
+    app.call(
+        "POST",
+        &format!("/api/user/{}/scheduled", bob["id"]),
+        Some(json!({ "amount": -600, "interval": "daily" })),
+    )
+    .await;
+    // as if the server had been down for three days
+    sqlx::query("UPDATE scheduled_transaction SET next_run = datetime(next_run, '-3 days')")
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    schedule::book_due_transactions(&app.db, &app.settings, &app.events)
+        .await
+        .unwrap();
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", alice["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 400);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", bob["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -600);
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}/scheduled", bob["id"]), None)
+        .await;
+    let scheduled = &body["scheduledTransactions"][0];
+    assert_eq!(scheduled["skipped"], 3);
+    assert!(scheduled["lastError"]
+        .as_str()
+        .unwrap()
+        .contains("boundary"));
+}
+
+#[tokio::test]
+async fn cancel_scheduled_transaction() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let path = format!("/api/user/{}/scheduled", user["id"]);
+
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": -100, "interval": "hourly" })),
+        )
+        .await;
+    assert_eq!(status, 400);
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": -100, "interval": "weekly", "start": "2000-01-01 00:00:00" })),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    let (_, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "amount": -100, "interval": "weekly" })),
+        )
+        .await;
+    let id = &body["scheduledTransaction"]["id"];
+    let (status, body) = app.call("DELETE", &format!("{}/{}", path, id), None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["scheduledTransaction"]["isActive"], false);
+    let (status, _) = app.call("DELETE", &format!("{}/{}", path, id), None).await;
+    assert_eq!(status, 404);
+
+    schedule::book_due_transactions(&app.db, &app.settings, &app.events)
+        .await
+        .unwrap();
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+}
+
+#[test]
+fn monthly_runs_keep_the_day_of_the_first_run() {
+    let first = "2024-01-31 08:00:00";
+    let mut next = first.to_string();
+    let mut runs = Vec::new();
+    for _ in 0..4 {
+        next = schedule::following_run("monthly", first, &next).unwrap();
+        runs.push(next.clone());
+    }
+    assert_eq!(
+        runs,
+        vec![
+            "2024-02-29 08:00:00",
+            "2024-03-31 08:00:00",
+            "2024-04-30 08:00:00",
+            "2024-05-31 08:00:00",
+        ]
+    );
+    assert_eq!(
+        schedule::following_run("monthly", "2024-12-15 08:00:00", "2024-12-15 08:00:00").unwrap(),
+        "2025-01-15 08:00:00"
+    );
+    assert_eq!(
+        schedule::following_run("weekly", first, first).unwrap(),
+        "2024-02-07 08:00:00"
+    );
+    assert_eq!(schedule::following_run("hourly", first, first), None);
+}
+
+#[tokio::test]
+async fn skip_out_of_payment_boundary_and_continue_after_failure() {
+    let mut app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+    let carol = app.add_user("carol").await;
+    for (user, amount) in [(&alice, -100), (&bob, -1500), (&carol, -200)].iter() {
+        let (status, body) = app
+            .call(
+                "POST",
+                &format!("/api/user/{}/scheduled", user["id"]),
+                Some(json!({ "amount": amount, "interval": "daily" })),
+            )
+            .await;
+        assert_eq!(status, 200, "{}", body);
+    }
+    app.settings.payment.boundary.lower = -1000;
+    // broken entry, booking it fails
+    sqlx::query("UPDATE scheduled_transaction SET interval = 'hourly' WHERE user_id = ?")
+        .bind(alice["id"].as_i64().unwrap() as i32)
+        .execute(&app.db)
+        .await
+        .unwrap();
+
+    schedule::book_due_transactions(&app.db, &app.settings, &app.events)
+        .await
+        .unwrap();
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}/scheduled", alice["id"]), None)
+        .await;
+    assert_eq!(body["scheduledTransactions"][0]["skipped"], 0);
+    assert!(body["scheduledTransactions"][0]["lastError"]
+        .as_str()
+        .unwrap()
+        .contains("hourly"));
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}/scheduled", bob["id"]), None)
+        .await;
+    assert_eq!(body["scheduledTransactions"][0]["skipped"], 1);
+    assert!(body["scheduledTransactions"][0]["lastError"]
+        .as_str()
+        .unwrap()
+        .contains("payment boundary"));
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", carol["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -200);
+}
@@ -0,0 +1,57 @@
+use crate::settings;
+
+#[test]
+fn env_overrides_keep_string_settings() {
+    let mut tree: serde_yaml::Value = serde_yaml::from_str(
+        "parameters:
+  strichliste:
+    paypal:
+      enabled: false
+      recipient: ''
+    account:
+      boundary:
+        lower: -20000
+",
+    )
+    .unwrap();
+    let vars = vec![
+        ("STRICHLISTE__PAYPAL__RECIPIENT", "12345"),
+        ("STRICHLISTE__PAYPAL__ENABLED", "true"),
+        ("STRICHLISTE__ACCOUNT__BOUNDARY__LOWER", "-5000"),
+    ];
+
+    let problems = settings::apply_env_overrides(
+        &mut tree,
+        vars.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+    );
+
+    assert!(problems.is_empty(), "{:?}", problems);
+    let strichliste = &tree["parameters"]["strichliste"];
+    assert_eq!(strichliste["paypal"]["recipient"].as_str(), Some("12345"));
+    assert_eq!(strichliste["paypal"]["enabled"].as_bool(), Some(true));
+    assert_eq!(
+        strichliste["account"]["boundary"]["lower"].as_i64(),
+        Some(-5000)
+    );
+}
+
+#[test]
+fn hooks_sharing_a_url_need_distinct_ids() {
+    let hook = settings::HookSetting {
+        url: "https://bot.example.org/strichliste".to_string(),
+        ..Default::default()
+    };
+    let mut settings = settings::Settings::default();
+    settings.backend.webhooks.hooks = vec![hook.clone(), hook.clone()];
+
+    let problems = settings::validate_settings(&settings).unwrap_err();
+    assert!(
+        problems.iter().any(|p| p.contains("hooks[1].id")),
+        "{:?}",
+        problems
+    );
+
+    settings.backend.webhooks.hooks[1].id = "second".to_string();
+    assert!(settings::validate_settings(&settings).is_ok());
+}
@@ -0,0 +1,42 @@
+use crate::tls;
+
+#[test]
+fn strip_port_keeps_ipv6_literals() {
+    assert_eq!(tls::strip_port("example.com:8080"), "example.com");
+    assert_eq!(tls::strip_port("example.com"), "example.com");
+    assert_eq!(tls::strip_port("127.0.0.1:80"), "127.0.0.1");
+    assert_eq!(tls::strip_port("[::1]:8080"), "[::1]");
+    assert_eq!(tls::strip_port("[::1]"), "[::1]");
+}
+
+#[tokio::test]
+async fn redirect_to_https_keeping_path_and_query() {
+    let response = warp::test::request()
+        .path("/api/user?active=true")
+        .header("host", "example.com:8080")
+        .reply(&tls::redirect(8443))
+        .await;
+    assert_eq!(response.status(), 301);
+    assert_eq!(
+        response.headers()["location"],
+        "https://example.com:8443/api/user?active=true"
+    );
+
+    // the default port is left out
+    let response = warp::test::request()
+        .path("/")
+        .header("host", "[::1]:80")
+        .reply(&tls::redirect(443))
+        .await;
+    assert_eq!(response.status(), 301);
+    assert_eq!(response.headers()["location"], "https://[::1]/");
+}
+
+#[tokio::test]
+async fn redirect_needs_a_host() {
+    let result = warp::test::request()
+        .path("/")
+        .filter(&tls::redirect(443))
+        .await;
+    assert!(result.is_err());
+}
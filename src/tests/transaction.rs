@@ -0,0 +1,614 @@
+use serde_json::json;
+
+use super::TestApp;
+use crate::{error::DbError, transaction_db, user_db};
+
+#[tokio::test]
+async fn book_value() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+
+    let (status, body) = app
+        .book(&user["id"], json!({ "amount": 1000, "comment": "cash" }))
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let transaction = &body["transaction"];
+    assert_eq!(transaction["amount"], 1000);
+    assert_eq!(transaction["comment"], "cash");
+    assert_eq!(transaction["user"]["balance"], 1000);
+    assert_eq!(transaction["article"], json!(null));
+    assert_eq!(transaction["recipient"], json!(null));
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 1000);
+}
+
+#[tokio::test]
+async fn book_article() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let article = app.add_article("Club Mate", 150).await;
+
+    let (status, body) = app
+        .book(
+            &user["id"],
+            json!({ "articleId": article["id"], "quantity": 2 }),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let transaction = &body["transaction"];
+    assert_eq!(transaction["amount"], -300);
+    assert_eq!(transaction["quantity"], 2);
+    assert_eq!(transaction["article"]["id"], article["id"]);
+    assert_eq!(transaction["user"]["balance"], -300);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/article/{}", article["id"]), None)
+        .await;
+    assert_eq!(body["article"]["usageCount"], 1);
+}
+
+#[tokio::test]
+async fn reject_quantities_out_of_range() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let article = app.add_article("Club Mate", 150).await;
+
+    let (status, _) = app
+        .book(
+            &user["id"],
+            json!({ "articleId": article["id"], "quantity": 20000000 }),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    // merged lines of the same article
+    let (status, _) = app
+        .call(
+            "POST",
+            &format!("/api/user/{}/cart", user["id"]),
+            Some(json!({ "lines": [
+                { "articleId": article["id"], "quantity": 2147483647 },
+                { "articleId": article["id"] }
+            ] })),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+}
+
+#[tokio::test]
+async fn book_unknown_article() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+
+    let (status, _) = app.book(&user["id"], json!({ "articleId": 42 })).await;
+
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn transfer_to_recipient() {
+    let app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+
+    let (status, body) = app
+        .book(
+            &alice["id"],
+            json!({ "amount": -500, "recipientId": bob["id"] }),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let transaction = &body["transaction"];
+    assert_eq!(transaction["amount"], -500);
+    assert_eq!(transaction["user"]["balance"], -500);
+    assert_eq!(transaction["recipient"]["id"], bob["id"]);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", bob["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 500);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}/transaction", bob["id"]), None)
+        .await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["transactions"][0]["amount"], 500);
+    assert_eq!(body["transactions"][0]["sender"]["id"], alice["id"]);
+}
+
+#[tokio::test]
+async fn reject_mismatching_parameters() {
+    let app = TestApp::new().await;
+    let alice = app.add_user("alice").await;
+    let bob = app.add_user("bob").await;
+
+    // a transfer has to take money from the sender
+    let (status, _) = app
+        .book(
+            &alice["id"],
+            json!({ "amount": 500, "recipientId": bob["id"] }),
+        )
+        .await;
+    assert_eq!(status, 400);
+    let (status, _) = app
+        .book(
+            &alice["id"],
+            json!({ "amount": -500, "recipientId": alice["id"] }),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    let (status, _) = app.book(&alice["id"], json!({})).await;
+    assert_eq!(status, 400);
+
+    let (status, _) = app
+        .book(&alice["id"], json!({ "amount": -100, "articleId": 1 }))
+        .await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn reject_payment_out_of_boundary() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let boundary = app.settings.payment.boundary.clone();
+
+    let (status, _) = app
+        .book(&user["id"], json!({ "amount": boundary.upper + 1 }))
+        .await;
+    assert_eq!(status, 400);
+    let (status, _) = app
+        .book(&user["id"], json!({ "amount": boundary.lower - 1 }))
+        .await;
+    assert_eq!(status, 400);
+
+    let (status, _) = app
+        .book(&user["id"], json!({ "amount": boundary.upper }))
+        .await;
+    assert_eq!(status, 200);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], boundary.upper);
+}
+
+#[tokio::test]
+async fn reject_balance_out_of_boundary() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    let user = app.add_user("alice").await;
+    let article = app.add_article("Club Mate", 600).await;
+
+    let (status, _) = app.book(&user["id"], json!({ "amount": -800 })).await;
+    assert_eq!(status, 200);
+
+    // each branch checks the resulting balance
+    let (status, _) = app.book(&user["id"], json!({ "amount": -201 })).await;
+    assert_eq!(status, 400);
+    let (status, _) = app
+        .book(&user["id"], json!({ "articleId": article["id"] }))
+        .await;
+    assert_eq!(status, 400);
+    let bob = app.add_user("bob").await;
+    let (status, _) = app
+        .book(
+            &user["id"],
+            json!({ "amount": -201, "recipientId": bob["id"] }),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -800);
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", bob["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+}
+
+#[tokio::test]
+async fn list_transactions() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    for amount in 1..=7 {
+        let (status, _) = app.book(&user["id"], json!({ "amount": amount })).await;
+        assert_eq!(status, 200);
+    }
+
+    let path = format!("/api/user/{}/transaction", user["id"]);
+    let (status, body) = app.call("GET", &path, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["count"], 7);
+    // five by default
+    let first_page = body["transactions"].as_array().unwrap().clone();
+    assert_eq!(first_page.len(), 5);
+
+    let (_, body) = app
+        .call("GET", &format!("{}?limit=3&offset=5", path), None)
+        .await;
+    let second_page = body["transactions"].as_array().unwrap();
+    assert_eq!(second_page.len(), 2);
+
+    let mut amounts: Vec<i64> = first_page
+        .iter()
+        .chain(second_page.iter())
+        .map(|t| t["amount"].as_i64().unwrap())
+        .collect();
+    amounts.sort();
+    assert_eq!(amounts, (1..=7).collect::<Vec<i64>>());
+}
+
+#[tokio::test]
+async fn retry_with_idempotency_key() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let path = format!("/api/user/{}/transaction", user["id"]);
+
+    let mut ids = vec![];
+    for _ in 0..2 {
+        let (status, body) = app
+            .call_with_headers(
+                "POST",
+                &path,
+                Some(json!({ "amount": -200 })),
+                &[("Idempotency-Key", "kiosk-1")],
+            )
+            .await;
+        assert_eq!(status, 200);
+        ids.push(body["transaction"]["id"].clone());
+    }
+    assert_eq!(ids[0], ids[1]);
+
+    // the key may be sent in the body as well
+    let (_, body) = app
+        .book(
+            &user["id"],
+            json!({ "amount": -200, "idempotencyKey": "kiosk-1" }),
+        )
+        .await;
+    assert_eq!(body["transaction"]["id"], ids[0]);
+
+    let (_, body) = app
+        .book(
+            &user["id"],
+            json!({ "amount": -200, "idempotencyKey": "kiosk-2" }),
+        )
+        .await;
+    assert_ne!(body["transaction"]["id"], ids[0]);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -400);
+}
+
+#[tokio::test]
+async fn idempotency_key_is_unique_within_the_window() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let (_, body) = app
+        .book(
+            &user["id"],
+            json!({ "amount": -200, "idempotencyKey": "kiosk-1" }),
+        )
+        .await;
+    let first = body["transaction"]["id"].as_i64().unwrap() as i32;
+    let user_id = user["id"].as_i64().unwrap() as i32;
+
+    // what a concurrent retry runs into
+    let mut tx = app.db.begin().await.unwrap();
+    let entity = user_db::get_user_tx(&mut tx, &app.settings, &user_id)
+        .await
+        .unwrap()
+        .unwrap();
+    let second = transaction_db::add_transaction_with_value_tx(&mut tx, entity, &-200, None)
+        .await
+        .unwrap();
+    let result = transaction_db::set_idempotency_key_tx(
+        &mut tx,
+        &user_id,
+        &second.entity.id,
+        "kiosk-1",
+        3600,
+    )
+    .await;
+    assert!(matches!(result, Err(DbError::DuplicateKey(_))));
+    drop(tx);
+
+    // outside of the window the key books again
+    sqlx::query(
+        "UPDATE transactions SET created = datetime('now', 'localtime', '-2 days') WHERE id = ?",
+    )
+    .bind(first)
+    .execute(&app.db)
+    .await
+    .unwrap();
+    let (status, body) = app
+        .book(
+            &user["id"],
+            json!({ "amount": -200, "idempotencyKey": "kiosk-1" }),
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert_ne!(body["transaction"]["id"], first);
+}
+
+#[tokio::test]
+async fn book_article_with_deposit() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let (_, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Club Mate", "amount": 150, "deposit": 15 })),
+        )
+        .await;
+    let article = body["article"].clone();
+    assert_eq!(article["deposit"], 15);
+
+    let (status, body) = app
+        .book(
+            &user["id"],
+            json!({ "articleId": article["id"], "quantity": 2 }),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let transaction = &body["transaction"];
+    assert_eq!(transaction["amount"], -300);
+    assert_eq!(transaction["isDeposit"], false);
+    assert_eq!(transaction["depositTransaction"]["amount"], -30);
+    assert_eq!(transaction["depositTransaction"]["quantity"], 2);
+    assert_eq!(transaction["depositTransaction"]["isDeposit"], true);
+    assert_eq!(transaction["user"]["balance"], -330);
+
+    // both are reverted together
+    let id = transaction["id"].as_i64().unwrap() as i32;
+    let reverted = transaction_db::revert_transaction(&app.db, &app.settings, &id)
+        .await
+        .unwrap();
+    assert!(reverted.entity.deleted);
+    assert!(reverted.deposit_transaction.unwrap().deleted);
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+}
+
+#[tokio::test]
+async fn revert_deposit_with_its_purchase() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let (_, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Club Mate", "amount": 150, "deposit": 15 })),
+        )
+        .await;
+    let (_, body) = app
+        .book(&user["id"], json!({ "articleId": body["article"]["id"] }))
+        .await;
+    let purchase = body["transaction"]["id"].as_i64().unwrap() as i32;
+    let deposit = body["transaction"]["depositTransaction"]["id"]
+        .as_i64()
+        .unwrap() as i32;
+
+    let reverted = transaction_db::revert_transaction(&app.db, &app.settings, &deposit)
+        .await
+        .unwrap();
+    assert_eq!(reverted.entity.id, purchase);
+    assert!(reverted.deposit_transaction.unwrap().deleted);
+    // the purchase went along, so it is not subtracted twice
+    assert!(
+        transaction_db::revert_transaction(&app.db, &app.settings, &purchase)
+            .await
+            .is_err()
+    );
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+}
+
+#[tokio::test]
+async fn return_deposit() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let (_, body) = app
+        .call(
+            "POST",
+            "/api/article",
+            Some(json!({ "name": "Club Mate", "amount": 150, "deposit": 15 })),
+        )
+        .await;
+    let article = body["article"].clone();
+
+    let (status, body) = app
+        .book(
+            &user["id"],
+            json!({ "articleId": article["id"], "quantity": 6, "returnDeposit": true }),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let transaction = &body["transaction"];
+    assert_eq!(transaction["amount"], 90);
+    assert_eq!(transaction["isDeposit"], true);
+    assert_eq!(transaction["user"]["balance"], 90);
+
+    // a return does not count as a purchase
+    let (_, body) = app
+        .call("GET", &format!("/api/article/{}", article["id"]), None)
+        .await;
+    assert_eq!(body["article"]["usageCount"], 0);
+
+    let plain = app.add_article("Chips", 100).await;
+    let (status, _) = app
+        .book(
+            &user["id"],
+            json!({ "articleId": plain["id"], "returnDeposit": true }),
+        )
+        .await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn checkout_cart() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let mate = app.add_article("Club Mate", 150).await;
+    let chips = app.add_article("Chips", 100).await;
+    let path = format!("/api/user/{}/cart", user["id"]);
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "lines": [
+                { "articleId": mate["id"], "quantity": 2 },
+                { "articleId": chips["id"] },
+                { "articleId": mate["id"] },
+            ] })),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let receipt = &body["receipt"];
+    assert_eq!(receipt["amount"], -550);
+    assert_eq!(receipt["deposit"], 0);
+    assert_eq!(receipt["total"], -550);
+    assert_eq!(receipt["user"]["balance"], -550);
+    // lines of the same article are booked together
+    let transactions = receipt["transactions"].as_array().unwrap();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0]["article"]["id"], mate["id"]);
+    assert_eq!(transactions[0]["quantity"], 3);
+    assert_eq!(transactions[0]["amount"], -450);
+    assert_eq!(transactions[1]["article"]["id"], chips["id"]);
+    assert_eq!(transactions[1]["amount"], -100);
+
+    let (status, _) = app.call("POST", &path, Some(json!({ "lines": [] }))).await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn cart_is_booked_atomically() {
+    let mut app = TestApp::new().await;
+    app.settings.account.boundary.lower = -1000;
+    let user = app.add_user("alice").await;
+    let mate = app.add_article("Club Mate", 600).await;
+    let chips = app.add_article("Chips", 500).await;
+    let path = format!("/api/user/{}/cart", user["id"]);
+
+    // each line is within the boundary, but the total is not
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "lines": [{ "articleId": mate["id"] }, { "articleId": chips["id"] }] })),
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    // an unknown article books none of the lines
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "lines": [{ "articleId": chips["id"] }, { "articleId": 42 }] })),
+        )
+        .await;
+    assert_eq!(status, 404);
+
+    // a line failing after the first one was inserted rolls back the whole cart
+    sqlx::query(&format!(
+        "CREATE TRIGGER fail_chips BEFORE INSERT ON transactions WHEN NEW.article_id = {}
+        BEGIN SELECT RAISE(ABORT, 'injected failure'); END;",
+        chips["id"]
+    ))
+    .execute(&app.db)
+    .await
+    .unwrap();
+    app.settings.account.boundary.lower = -5000;
+    let (status, _) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "lines": [{ "articleId": mate["id"] }, { "articleId": chips["id"] }] })),
+        )
+        .await;
+    assert_eq!(status, 500);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], 0);
+    let (_, body) = app
+        .call(
+            "GET",
+            &format!("/api/user/{}/transaction", user["id"]),
+            None,
+        )
+        .await;
+    assert_eq!(body["count"], 0);
+}
+
+#[tokio::test]
+async fn retry_cart_with_idempotency_key() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let mate = app.add_article("Club Mate", 150).await;
+    let chips = app.add_article("Chips", 200).await;
+    let path = format!("/api/user/{}/cart", user["id"]);
+
+    let mut receipts = vec![];
+    for _ in 0..2 {
+        let (status, body) = app
+            .call_with_headers(
+                "POST",
+                &path,
+                Some(
+                    json!({ "lines": [{ "articleId": mate["id"] }, { "articleId": chips["id"] }] }),
+                ),
+                &[("Idempotency-Key", "kiosk-1")],
+            )
+            .await;
+        assert_eq!(status, 200);
+        receipts.push(body["receipt"].clone());
+    }
+    let ids = |receipt: &serde_json::Value| -> Vec<serde_json::Value> {
+        receipt["transactions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["id"].clone())
+            .collect()
+    };
+    assert_eq!(ids(&receipts[0]).len(), 2);
+    assert_eq!(ids(&receipts[0]), ids(&receipts[1]));
+    assert_eq!(receipts[1]["total"], -350);
+
+    // the key is taken by the cart, a single booking doesn't get just its first line
+    let (status, _) = app
+        .book(
+            &user["id"],
+            json!({ "amount": -200, "idempotencyKey": "kiosk-1" }),
+        )
+        .await;
+    assert_eq!(status, 409);
+
+    let (_, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(body["user"]["balance"], -350);
+}
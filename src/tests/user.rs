@@ -0,0 +1,214 @@
+use serde_json::json;
+
+use super::TestApp;
+
+#[tokio::test]
+async fn create_and_get_user() {
+    let app = TestApp::new().await;
+    let (status, body) = app
+        .call(
+            "POST",
+            "/api/user",
+            Some(json!({ "name": " alice ", "email": "alice@example.org" })),
+        )
+        .await;
+
+    assert_eq!(status, 200);
+    let user = &body["user"];
+    assert_eq!(user["name"], "alice");
+    assert_eq!(user["email"], "alice@example.org");
+    assert_eq!(user["balance"], 0);
+    assert_eq!(user["isDisabled"], false);
+
+    let (status, body) = app
+        .call("GET", &format!("/api/user/{}", user["id"]), None)
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["user"]["name"], "alice");
+}
+
+#[tokio::test]
+async fn reject_invalid_email() {
+    let app = TestApp::new().await;
+    let (status, _) = app
+        .call(
+            "POST",
+            "/api/user",
+            Some(json!({ "name": "alice", "email": "no address" })),
+        )
+        .await;
+
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn unknown_user_is_not_found() {
+    let app = TestApp::new().await;
+    let (status, _) = app.call("GET", "/api/user/42", None).await;
+
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn update_and_disable_user() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    app.add_user("bob").await;
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &format!("/api/user/{}", user["id"]),
+            Some(json!({ "name": "alicia", "email": null, "isDisabled": true })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["user"]["name"], "alicia");
+    assert_eq!(body["user"]["isDisabled"], true);
+
+    let (_, body) = app.call("GET", "/api/user", None).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["users"][0]["name"], "bob");
+
+    let (_, body) = app.call("GET", "/api/user?deleted=true", None).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["users"][0]["name"], "alicia");
+}
+
+#[tokio::test]
+async fn search_user() {
+    let app = TestApp::new().await;
+    app.add_user("alice").await;
+    app.add_user("bob").await;
+
+    let (status, body) = app.call("GET", "/api/user/search?query=li", None).await;
+
+    assert_eq!(status, 200);
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["users"][0]["name"], "alice");
+}
+
+#[tokio::test]
+async fn update_profile_fields() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let path = format!("/api/user/{}", user["id"]);
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({
+                "name": "alice",
+                "displayName": " Alice Liddell ",
+                "isDisabled": false,
+            })),
+        )
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["user"]["displayName"], "Alice Liddell");
+
+    // missing fields are kept, empty ones removed
+    let (_, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "name": "alice", "isDisabled": false })),
+        )
+        .await;
+    assert_eq!(body["user"]["displayName"], "Alice Liddell");
+    let (_, body) = app
+        .call("GET", "/api/user/search?query=Liddell", None)
+        .await;
+    assert_eq!(body["count"], 1);
+
+    let (_, body) = app
+        .call(
+            "POST",
+            &path,
+            Some(json!({ "name": "alice", "displayName": "", "isDisabled": false })),
+        )
+        .await;
+    assert_eq!(body["user"]["displayName"], json!(null));
+}
+
+#[tokio::test]
+async fn notes_are_admin_only() {
+    let app = TestApp::new().await;
+    let user = app.add_user("alice").await;
+    let notes_path = format!("/api/admin/user/{}/notes", user["id"]);
+
+    let (status, body) = app
+        .call(
+            "POST",
+            &notes_path,
+            Some(json!({ "notes": " pays in cash " })),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["userNotes"]["notes"], "pays in cash");
+    let (_, body) = app.call("GET", &notes_path, None).await;
+    assert_eq!(body["userNotes"]["userId"], user["id"]);
+    assert_eq!(body["userNotes"]["notes"], "pays in cash");
+
+    // not part of the user anywhere else
+    for path in [
+        format!("/api/user/{}", user["id"]),
+        "/api/user".to_string(),
+        "/api/user/search?query=alice".to_string(),
+    ]
+    .iter()
+    {
+        let (status, body) = app.call("GET", path, None).await;
+        assert_eq!(status, 200);
+        assert!(!body.to_string().contains("pays in cash"), "{}", body);
+    }
+
+    let (_, body) = app
+        .call("POST", &notes_path, Some(json!({ "notes": "" })))
+        .await;
+    assert_eq!(body["userNotes"]["notes"], json!(null));
+    let (status, _) = app.call("GET", "/api/admin/user/999/notes", None).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn upload_avatar() {
+    let mut app = TestApp::new().await;
+    app.backend.avatar.max_size = 64;
+    let user = app.add_user("alice").await;
+    let path = format!("/api/user/{}/avatar", user["id"]);
+    let png = b"\x89PNG\r\n\x1a\n0000IHDR".to_vec();
+
+    let (status, _) = app.call("GET", &path, None).await;
+    assert_eq!(status, 404);
+
+    let response = warp::test::request()
+        .method("POST")
+        .path(&path)
+        .body(png.clone())
+        .reply(&app.routes())
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["user"]["hasAvatar"], true);
+
+    let response = warp::test::request().path(&path).reply(&app.routes()).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert_eq!(response.body().to_vec(), png);
+
+    for (data, status) in [(b"not an image".to_vec(), 400), (vec![0xff; 65], 413)].iter() {
+        let response = warp::test::request()
+            .method("POST")
+            .path(&path)
+            .body(data.clone())
+            .reply(&app.routes())
+            .await;
+        assert_eq!(response.status(), *status);
+    }
+
+    let (status, body) = app.call("DELETE", &path, None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["user"]["hasAvatar"], false);
+}
@@ -0,0 +1,153 @@
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use hmac::{Hmac, Mac, NewMac};
+use hyper::body::Bytes;
+use serde_json::json;
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use warp::{http::HeaderMap, Filter};
+
+use super::TestApp;
+use crate::{events::EventBus, settings::HookSetting, webhook};
+
+/// Local hook receiving the deliveries, passes on their headers and bodies
+fn stand_in() -> (SocketAddr, mpsc::UnboundedReceiver<(HeaderMap, String)>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let route = warp::post()
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .map(move |headers: HeaderMap, body: Bytes| {
+            let _ = sender.send((headers, String::from_utf8_lossy(&body).to_string()));
+            warp::reply()
+        });
+    let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (addr, receiver)
+}
+
+fn sign(secret: &str, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+    mac.update(payload.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[tokio::test]
+async fn deliver_to_hooks_sharing_a_url() {
+    let mut app = TestApp::new().await;
+    let (addr, mut received) = stand_in();
+    let hook = |id: &str, secret: &str| HookSetting {
+        id: id.to_string(),
+        url: format!("http://{}/hook", addr),
+        secret: secret.to_string(),
+        events: vec!["transaction.created".to_string()],
+    };
+    app.backend.webhooks.hooks = vec![hook("first", "one"), hook("second", "two")];
+    app.events = EventBus::new(&app.backend);
+    tokio::spawn(webhook::run_webhooks(
+        app.db.clone(),
+        app.events.subscribe(),
+        app.backend.webhooks.clone(),
+    ));
+
+    let user = app.add_user("alice").await;
+    let (status, _) = app.book(&user["id"], json!({ "amount": -150 })).await;
+    assert_eq!(status, 200);
+
+    let mut signatures = vec![];
+    for _ in 0..2 {
+        let (headers, body) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("no delivery within 5 seconds")
+            .unwrap();
+        assert_eq!(headers["x-strichliste-event"], "transaction.created");
+        let payload: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(payload["data"]["transaction"]["amount"], -150);
+        signatures.push((
+            headers["x-strichliste-signature"]
+                .to_str()
+                .unwrap()
+                .to_string(),
+            body,
+        ));
+    }
+    // each hook signs with its own secret
+    signatures.sort();
+    let mut expected: Vec<String> =
+        vec![sign("one", &signatures[0].1), sign("two", &signatures[1].1)];
+    expected.sort();
+    let mut actual: Vec<String> = signatures.into_iter().map(|(s, _)| s).collect();
+    actual.sort();
+    assert_eq!(actual, expected);
+}
+
+#[tokio::test]
+async fn queue_with_the_booking() {
+    let mut app = TestApp::new().await;
+    app.backend.webhooks.hooks = vec![HookSetting {
+        url: "http://127.0.0.1:9/hook".to_string(),
+        events: vec!["transaction.created".to_string()],
+        ..Default::default()
+    }];
+    // no delivery running, the outbox keeps what was queued
+    app.events = EventBus::new(&app.backend);
+    let user = app.add_user("alice").await;
+
+    let (status, _) = app.book(&user["id"], json!({ "amount": -150 })).await;
+    assert_eq!(status, 200);
+    // rejected bookings queue nothing
+    let (status, _) = app.book(&user["id"], json!({ "amount": -1000000 })).await;
+    assert_eq!(status, 400);
+
+    let queued: Vec<(String, String)> = sqlx::query_as("SELECT hook, event FROM webhook_outbox")
+        .fetch_all(&app.db)
+        .await
+        .unwrap();
+    assert_eq!(
+        queued,
+        vec![(
+            "http://127.0.0.1:9/hook".to_string(),
+            "transaction.created".to_string()
+        )]
+    );
+}
+
+#[tokio::test]
+async fn slow_hooks_dont_hold_up_others() {
+    let mut app = TestApp::new().await;
+    let slow = warp::post().and_then(|| async {
+        tokio::time::delay_for(Duration::from_secs(30)).await;
+        Ok::<_, Infallible>(warp::reply())
+    });
+    let (slow_addr, server) = warp::serve(slow).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let (addr, mut received) = stand_in();
+    // the slow hook is queued first
+    app.backend.webhooks.hooks = vec![
+        HookSetting {
+            url: format!("http://{}/hook", slow_addr),
+            ..Default::default()
+        },
+        HookSetting {
+            url: format!("http://{}/hook", addr),
+            ..Default::default()
+        },
+    ];
+    app.events = EventBus::new(&app.backend);
+    tokio::spawn(webhook::run_webhooks(
+        app.db.clone(),
+        app.events.subscribe(),
+        app.backend.webhooks.clone(),
+    ));
+
+    let user = app.add_user("alice").await;
+    app.book(&user["id"], json!({ "amount": -150 })).await;
+
+    // well before the request to the slow hook times out
+    for event in ["user.created", "transaction.created"].iter() {
+        let (headers, _) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("no delivery within 5 seconds")
+            .unwrap();
+        assert_eq!(headers["x-strichliste-event"], *event);
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use log::{error, info};
+use rustls::{internal::pemfile, NoClientAuth, ServerConfig};
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+use warp::{http::Uri, path::FullPath, Filter, Rejection, Reply};
+
+use crate::settings::{self, TlsSetting};
+
+/// How often binding the restarted listener is retried while the old one releases the address
+const BIND_ATTEMPTS: u32 = 10;
+
+/// Certificate chain and private key as read from disk
+struct Identity {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+    modified: Option<(SystemTime, SystemTime)>,
+}
+
+/// Serves `routes` via HTTPS until `shutdown` resolves.
+/// The listener is restarted gracefully whenever the certificate or key file changes,
+/// so renewed certificates are picked up without a restart of the process.
+/// Invalid replacements are logged and the previous certificate stays in use.
+pub async fn serve<F, R>(
+    routes: F,
+    addr: SocketAddr,
+    settings: TlsSetting,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> Result<(), io::Error>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let mut identity = read_identity(&settings).await?;
+    check_identity(&identity)?;
+
+    // one shutdown signal for all generations of the listener
+    let (stop_tx, stop_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown.await;
+        let _ = stop_tx.broadcast(true);
+    });
+
+    if !settings.redirect_address.is_empty() {
+        serve_redirect(&settings.redirect_address, addr.port(), stop_rx.clone())?;
+    }
+
+    let mut stop = stop_rx.clone();
+    let (mut drain, mut server) = bind(routes.clone(), addr, &identity).await?;
+    loop {
+        let (changed_tx, changed_rx) = oneshot::channel();
+        tokio::spawn(watch_identity(
+            settings.clone(),
+            identity.modified,
+            changed_tx,
+        ));
+        tokio::select! {
+            _ = wait_for_stop(&mut stop) => {
+                let _ = drain.send(());
+                let _ = server.await;
+                return Ok(());
+            }
+            _ = changed_rx => (),
+        }
+
+        let next = match read_identity(&settings)
+            .await
+            .and_then(|v| check_identity(&v).map(|_| v))
+        {
+            Ok(v) => v,
+            Err(e) => {
+                // keep serving the previous certificate rather than going offline
+                error!(
+                    "Reloading the certificate failed, keeping the old one: {}",
+                    e
+                );
+                identity.modified = modified(&settings).await.ok();
+                continue;
+            }
+        };
+        info!("Certificate changed, restarting TLS listener ...");
+        // the old server releases the address as soon as it starts draining,
+        // its open connections (e.g. event streams) finish in the background
+        let _ = drain.send(());
+        let (next_drain, next_server) = bind(routes.clone(), addr, &next).await?;
+        drain = next_drain;
+        server = next_server;
+        identity = next;
+    }
+}
+
+/// Binds a TLS server for `identity` and spawns it.
+/// The server shuts down gracefully when the returned sender fires or is dropped.
+async fn bind<F, R>(
+    routes: F,
+    addr: SocketAddr,
+    identity: &Identity,
+) -> Result<(oneshot::Sender<()>, JoinHandle<()>), io::Error>
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let mut attempts = 0;
+    loop {
+        let (drain_tx, drain_rx) = oneshot::channel::<()>();
+        let signal = async move {
+            let _ = drain_rx.await;
+        };
+        match warp::serve(routes.clone())
+            .tls()
+            .cert(&identity.cert)
+            .key(&identity.key)
+            .try_bind_with_graceful_shutdown(addr, signal)
+        {
+            Ok((bound, server)) => {
+                info!("Listening on https://{}", bound);
+                return Ok((drain_tx, tokio::spawn(server)));
+            }
+            // the previous generation may not have closed its listener yet
+            Err(_) if attempts < BIND_ATTEMPTS => {
+                attempts += 1;
+                tokio::time::delay_for(Duration::from_millis(100)).await;
+            }
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("Failed to bind {}: {}", addr, e),
+                ))
+            }
+        }
+    }
+}
+
+/// Checks that certificate and key form a usable identity, as warp panics on invalid key material.
+fn check_identity(identity: &Identity) -> Result<(), io::Error> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let certs = pemfile::certs(&mut identity.cert.as_slice())
+        .map_err(|_| invalid("Failed to parse the certificate chain".to_string()))?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut identity.key.as_slice())
+        .map_err(|_| invalid("Failed to parse the private key".to_string()))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut identity.key.as_slice())
+            .map_err(|_| invalid("Failed to parse the private key".to_string()))?;
+    }
+    let key = match keys.into_iter().next() {
+        Some(v) => v,
+        None => return Err(invalid("No private key found".to_string())),
+    };
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certs, key)
+        .map_err(|e| invalid(format!("Invalid certificate or key: {}", e)))?;
+    return Ok(());
+}
+
+async fn read_identity(settings: &TlsSetting) -> Result<Identity, io::Error> {
+    let modified = modified(settings).await.ok();
+    let cert = read_pem(&settings.cert).await?;
+    let key = read_pem(&settings.key).await?;
+    return Ok(Identity {
+        cert,
+        key,
+        modified,
+    });
+}
+
+async fn read_pem(file: &str) -> Result<Vec<u8>, io::Error> {
+    let content = tokio::fs::read(file)
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to read {}: {}", file, e)))?;
+    if !String::from_utf8_lossy(&content).contains("-----BEGIN ") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a PEM file", file),
+        ));
+    }
+    return Ok(content);
+}
+
+async fn modified(settings: &TlsSetting) -> Result<(SystemTime, SystemTime), io::Error> {
+    let cert = tokio::fs::metadata(&settings.cert).await?.modified()?;
+    let key = tokio::fs::metadata(&settings.key).await?.modified()?;
+    return Ok((cert, key));
+}
+
+/// Polls the modification times of the certificate and key and reports the first change.
+async fn watch_identity(
+    settings: TlsSetting,
+    last: Option<(SystemTime, SystemTime)>,
+    mut changed: oneshot::Sender<()>,
+) {
+    let mut interval = tokio::time::interval(settings::to_duration(&settings.reload_interval));
+    // the first tick resolves immediately
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            // the listener stopped for good
+            _ = changed.closed() => return,
+        }
+        let current = modified(&settings).await.ok();
+        // renewals may replace the files one after another, wait until both are readable again
+        if current.is_some() && current != last && read_identity(&settings).await.is_ok() {
+            let _ = changed.send(());
+            return;
+        }
+    }
+}
+
+async fn wait_for_stop(stop: &mut watch::Receiver<bool>) {
+    while !*stop.borrow() {
+        if stop.recv().await.is_none() {
+            return;
+        }
+    }
+}
+
+/// Starts a plain HTTP listener on `address` answering all requests with a redirect to HTTPS.
+fn serve_redirect(
+    address: &str,
+    https_port: u16,
+    mut stop: watch::Receiver<bool>,
+) -> Result<(), io::Error> {
+    let addr = match std::net::ToSocketAddrs::to_socket_addrs(address)?.next() {
+        Some(v) => v,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Redirect address {} did not resolve to any address.",
+                    address
+                ),
+            ))
+        }
+    };
+
+    let signal = async move { wait_for_stop(&mut stop).await };
+    let (bound, server) = warp::serve(redirect(https_port))
+        .try_bind_with_graceful_shutdown(addr, signal)
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("Failed to bind {}: {}", addr, e),
+            )
+        })?;
+    info!("Redirecting http://{} to HTTPS", bound);
+    tokio::spawn(server);
+
+    return Ok(());
+}
+
+pub(crate) fn redirect(
+    https_port: u16,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let query = warp::query::raw()
+        .map(|q: String| format!("?{}", q))
+        .or(warp::any().map(String::new))
+        .unify();
+
+    warp::path::full()
+        .and(query)
+        .and(warp::header::<String>("host"))
+        .and_then(
+            move |path: FullPath, query: String, host: String| async move {
+                let host = strip_port(&host);
+                let location = match https_port {
+                    443 => format!("https://{}{}{}", host, path.as_str(), query),
+                    port => format!("https://{}:{}{}{}", host, port, path.as_str(), query),
+                };
+                match location.parse::<Uri>() {
+                    Ok(uri) => Ok(warp::redirect(uri)),
+                    Err(_) => Err(warp::reject::not_found()),
+                }
+            },
+        )
+}
+
+/// Removes the port from a host header, keeping IPv6 literals like `[::1]` intact.
+pub(crate) fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(idx) if !host[idx..].contains(']') => &host[..idx],
+        _ => host,
+    }
+}
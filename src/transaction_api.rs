@@ -1,18 +1,25 @@
 use std::{collections::HashMap, sync::Arc};
 
-use sqlx::SqlitePool;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use warp::{http::StatusCode, Reply};
 
 use crate::{
-    article_db,
+    article_db, credential_api,
     error::{ClientError, DbError},
+    events::{Event, EventBus},
     model,
     model::TransactionResp,
     model::TransactionsResp,
     model::{json_reply, JsonReply},
-    settings::StrichlisteSetting,
+    model::{CartReceipt, CartResp, PendingTransactionResp, PendingTransactionsResp},
+    pending_db,
+    settings::{to_duration, ApprovalSetting, IdempotencySetting, StrichlisteSetting},
     transaction_db, user_db,
 };
 
+/// Longest accepted `Idempotency-Key`
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
 pub async fn get_transactions(
     db: SqlitePool,
     settings: Arc<StrichlisteSetting>,
@@ -37,10 +44,30 @@ pub async fn get_transactions(
 pub async fn add_transaction(
     db: SqlitePool,
     settings: Arc<StrichlisteSetting>,
+    events: EventBus,
+    idempotency: Arc<IdempotencySetting>,
+    approval: Arc<ApprovalSetting>,
     user_id: i32,
+    idempotency_key: Option<String>,
+    pin: Option<String>,
     req: model::TransactionAddReq,
-) -> Result<JsonReply<TransactionResp>, warp::Rejection> {
+) -> Result<Box<dyn Reply>, warp::Rejection> {
+    // the header takes precedence over the field
+    let idempotency_key = idempotency_key.or_else(|| req.idempotency_key.clone());
+    check_idempotency_key(&idempotency_key)?;
+
+    credential_api::check_pin(&db, user_id, pin.as_deref()).await?;
     let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+
+    // a retry returns the transaction booked by the first request
+    let window = to_duration(&idempotency.window).as_secs();
+    if let Some(key) = &idempotency_key {
+        if let Some(reply) = retried(&mut tx, &settings, &user_id, key, window).await? {
+            tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+            return Ok(reply);
+        }
+    }
+
     let user = match user_db::get_user_tx(&mut tx, &*settings, &user_id).await {
         Ok(Some(u)) => u,
         Ok(None) => {
@@ -49,51 +76,121 @@ pub async fn add_transaction(
         Err(e) => return Err(e.into()),
     };
 
-    let transaction = match (
+    let return_deposit = req.return_deposit.unwrap_or(false);
+    let booking = match (
         req.amount.map(|f| f.trunc() as i32),
         req.article_id,
         req.recipient_id,
     ) {
         // transaction with pure value
         (Some(amount), None, None) => {
-            check_limit(&*settings, &(user.balance + amount), &amount)?;
-            let result = transaction_db::add_transaction_with_value_tx(
-                &mut tx,
-                user,
-                &amount,
-                req.comment.as_deref(),
-            )
-            .await?;
-            // TODO: find out how to use the From trait for this more elegantly
-            tx.commit().await.map_err(|e| -> DbError { e.into() })?;
-            result
+            if hold_for_approval(&*settings, &*approval, &user.balance, &amount)? {
+                let pending = pending_db::add_pending_transaction_tx(
+                    &mut tx,
+                    &user_id,
+                    None,
+                    None,
+                    &amount,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Held(pending)
+            } else {
+                let result = transaction_db::add_transaction_with_value_tx(
+                    &mut tx,
+                    user,
+                    &amount,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Booked(result)
+            }
         }
-        // transaction with article
-        (None, Some(article_id), _) => {
+        // returned bottles of an article
+        (None, Some(article_id), None) if return_deposit => {
             let quantity = req.quantity.unwrap_or(1);
 
             let article = match article_db::get_article_or_error_tx(&mut tx, article_id).await {
                 Ok(v) => v,
                 Err(e) => return Err(e.into()),
             };
+            if article.entity.deposit == 0 || quantity < 1 {
+                return Err(ClientError::ParameterInvalid(
+                    "Only a positive quantity of articles with a deposit can be returned."
+                        .to_string(),
+                )
+                .into());
+            }
 
-            let amount = article.entity.amount * quantity * -1;
-            check_limit(&*settings, &(user.balance + amount), &amount)?;
+            let amount = -debit(article.entity.deposit, quantity)?;
+            if hold_for_approval(&*settings, &*approval, &user.balance, &amount)? {
+                let pending = pending_db::add_pending_deposit_return_tx(
+                    &mut tx,
+                    &user_id,
+                    article_id,
+                    quantity,
+                    &amount,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Held(pending)
+            } else {
+                let result = transaction_db::add_deposit_return_tx(
+                    &mut tx,
+                    user,
+                    &quantity,
+                    &amount,
+                    article,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Booked(result)
+            }
+        }
+        // transaction with article
+        (None, Some(article_id), _) if !return_deposit => {
+            let quantity = req.quantity.unwrap_or(1);
 
-            let result = transaction_db::add_transaction_with_article_tx(
-                &mut tx,
-                user,
-                &quantity,
-                &amount,
-                article,
-                req.comment.as_deref(),
-            )
-            .await?;
-            tx.commit().await.map_err(|e| -> DbError { e.into() })?;
-            result
+            let article = match article_db::get_article_or_error_tx(&mut tx, article_id).await {
+                Ok(v) => v,
+                Err(e) => return Err(e.into()),
+            };
+
+            let amount = debit(article.entity.amount, quantity)?;
+            // the deposit counts towards the boundaries as well
+            let total = sum_amounts(&[amount, debit(article.entity.deposit, quantity)?])?;
+            if hold_for_approval(&*settings, &*approval, &user.balance, &total)? {
+                let pending = pending_db::add_pending_transaction_tx(
+                    &mut tx,
+                    &user_id,
+                    Some(article_id),
+                    Some(quantity),
+                    &amount,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Held(pending)
+            } else {
+                let result = transaction_db::add_transaction_with_article_tx(
+                    &mut tx,
+                    user,
+                    &quantity,
+                    &amount,
+                    article,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Booked(result)
+            }
         }
         // transaction with recipient
         (Some(amount), None, Some(recipient_id)) if amount < 0 => {
+            if recipient_id == user_id {
+                return Err(ClientError::ParameterInvalid(
+                    "Sender and recipient must be different users.".to_string(),
+                )
+                .into());
+            }
             let recipient = match user_db::get_user_tx(&mut tx, &*settings, &recipient_id).await {
                 Ok(Some(v)) => v,
                 Ok(None) => {
@@ -104,19 +201,43 @@ pub async fn add_transaction(
                 Err(e) => return Err(e.into()),
             };
 
-            // checking the payee is sufficient
-            check_limit(&*settings, &(user.balance + amount), &amount)?;
-
-            let result = transaction_db::add_transaction_with_recipient_tx(
-                &mut tx,
-                user,
-                &amount,
-                recipient,
-                req.comment.as_deref(),
-            )
-            .await?;
-            tx.commit().await.map_err(|e| -> DbError { e.into() })?;
-            result
+            let hold = hold_for_approval(&*settings, &*approval, &user.balance, &amount)?;
+            let recipient_balance = recipient
+                .balance
+                .checked_sub(amount)
+                .ok_or_else(out_of_range)?;
+            let hold_recipient = match check_balance_limit(&*settings, &recipient_balance) {
+                Ok(()) => false,
+                Err(_) if approval.enabled => true,
+                Err(_) => {
+                    return Err(ClientError::ParameterInvalid(
+                        "Balance of the recipient would be out of the allowed boundary."
+                            .to_string(),
+                    )
+                    .into())
+                }
+            };
+            if hold || hold_recipient {
+                let pending = pending_db::add_pending_transfer_tx(
+                    &mut tx,
+                    &user_id,
+                    &amount,
+                    recipient_id,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Held(pending)
+            } else {
+                let result = transaction_db::add_transaction_with_recipient_tx(
+                    &mut tx,
+                    user,
+                    &amount,
+                    recipient,
+                    req.comment.as_deref(),
+                )
+                .await?;
+                Booking::Booked(result)
+            }
         }
         _ => {
             return Err(ClientError::ParameterInvalid(
@@ -126,13 +247,376 @@ pub async fn add_transaction(
         }
     };
 
-    Ok(json_reply(TransactionResp { transaction }))
+    let transaction = match booking {
+        Booking::Booked(v) => v,
+        Booking::Held(pending) => {
+            if let Some(key) = &idempotency_key {
+                let stored =
+                    pending_db::set_idempotency_key_tx(&mut tx, &user_id, &pending.id, key, window)
+                        .await;
+                match stored {
+                    Ok(()) => (),
+                    Err(DbError::DuplicateKey(_)) => {
+                        drop(tx);
+                        return replay(&db, &settings, &user_id, key, window).await;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+            return Ok(held(pending));
+        }
+    };
+    if let Some(key) = &idempotency_key {
+        let stored = transaction_db::set_idempotency_key_tx(
+            &mut tx,
+            &user_id,
+            &transaction.entity.id,
+            key,
+            window,
+        )
+        .await;
+        match stored {
+            Ok(()) => (),
+            // a concurrent retry was faster, this booking is rolled back in favour of it
+            Err(DbError::DuplicateKey(_)) => {
+                drop(tx);
+                return replay(&db, &settings, &user_id, key, window).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let event = Event::TransactionCreated(transaction.clone());
+    events.queue_tx(&mut tx, &event).await?;
+    // TODO: find out how to use the From trait for this more elegantly
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    events.publish(event);
+
+    Ok(Box::new(json_reply(TransactionResp { transaction })))
+}
+
+fn check_idempotency_key(key: &Option<String>) -> Result<(), ClientError> {
+    match key {
+        Some(key) if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN => {
+            Err(ClientError::ParameterInvalid(format!(
+                "Idempotency key must have 1 to {} characters.",
+                MAX_IDEMPOTENCY_KEY_LEN
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Booking requested by `add_transaction`, either booked right away or held for approval
+enum Booking {
+    Booked(model::TransactionObject),
+    Held(model::PendingTransactionEntity),
+}
+
+/// The reply to a retry of the booking with `key`, None if there was no booking with it within the window.
+/// A held booking is returned in its current state, or as its transaction once approved.
+async fn retried(
+    tx: &mut Transaction<'static, Sqlite>,
+    settings: &StrichlisteSetting,
+    user_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> Result<Option<Box<dyn Reply>>, DbError> {
+    let id =
+        match transaction_db::get_idempotent_transaction_tx(tx, user_id, key, window_secs).await? {
+            Some(v) => v,
+            None => {
+                match pending_db::get_idempotent_pending_tx(tx, user_id, key, window_secs).await? {
+                    None => return Ok(None),
+                    // the key belongs to a cart
+                    Some(p) if p.cart_pending_id.is_some() => {
+                        return Err(DbError::DuplicateKey("Idempotency key".to_string()))
+                    }
+                    Some(p) => match p.transaction_id {
+                        Some(v) => v,
+                        None => return Ok(Some(held(p))),
+                    },
+                }
+            }
+        };
+    // the key belongs to a cart
+    if !transaction_db::get_cart_transaction_ids_tx(tx, &id)
+        .await?
+        .is_empty()
+    {
+        return Err(DbError::DuplicateKey("Idempotency key".to_string()));
+    }
+    let transaction = transaction_db::get_transaction_tx(tx, settings, &id)
+        .await?
+        .ok_or_else(|| DbError::EntityNotFound("Transaction".to_string()))?;
+    return Ok(Some(Box::new(json_reply(TransactionResp { transaction }))));
+}
+
+/// Reply to a request that lost the race for its key against a concurrent retry, which it was rolled back for.
+async fn replay(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    user_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> Result<Box<dyn Reply>, warp::Rejection> {
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    let reply = retried(&mut tx, settings, user_id, key, window_secs).await?;
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+    return match reply {
+        Some(v) => Ok(v),
+        None => Err(DbError::DuplicateKey("Idempotency key".to_string()).into()),
+    };
+}
+
+/// Like `replay`, for a cart
+async fn replay_cart(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    user_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> Result<Box<dyn Reply>, warp::Rejection> {
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+    let reply = retried_cart(&mut tx, settings, user_id, key, window_secs).await?;
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+    return match reply {
+        Some(v) => Ok(v),
+        None => Err(DbError::DuplicateKey("Idempotency key".to_string()).into()),
+    };
 }
 
-fn check_limit(
+/// Whether the booking has to wait for approval, because it would take the balance out of the boundary.
+/// Without approvals enabled such a booking is rejected, as are amounts out of the payment boundary.
+fn hold_for_approval(
+    settings: &StrichlisteSetting,
+    approval: &ApprovalSetting,
+    balance: &i32,
+    amount: &i32,
+) -> Result<bool, ClientError> {
+    match check_limit(settings, &new_balance(balance, amount)?, amount) {
+        Ok(()) => Ok(false),
+        Err(_) if approval.enabled && check_payment_limit(settings, amount).is_ok() => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+fn held(pending_transaction: model::PendingTransactionEntity) -> Box<dyn Reply> {
+    Box::new(warp::reply::with_status(
+        json_reply(PendingTransactionResp {
+            pending_transaction,
+        }),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+fn held_cart(pending_transactions: Vec<model::PendingTransactionEntity>) -> Box<dyn Reply> {
+    Box::new(warp::reply::with_status(
+        json_reply(PendingTransactionsResp {
+            count: pending_transactions.len(),
+            pending_transactions,
+        }),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+/// Books all lines of the cart at once, the boundaries are checked against the total.
+/// A cart beyond the balance boundary is held for approval as a whole if enabled.
+/// A retry with the same `Idempotency-Key` returns the receipt of the first request.
+pub async fn checkout_cart(
+    db: SqlitePool,
+    settings: Arc<StrichlisteSetting>,
+    events: EventBus,
+    idempotency: Arc<IdempotencySetting>,
+    approval: Arc<ApprovalSetting>,
+    user_id: i32,
+    idempotency_key: Option<String>,
+    pin: Option<String>,
+    req: model::CartReq,
+) -> Result<Box<dyn Reply>, warp::Rejection> {
+    let idempotency_key = idempotency_key.or_else(|| req.idempotency_key.clone());
+    check_idempotency_key(&idempotency_key)?;
+
+    // lines of the same article are merged, keeping the order of their first occurrence
+    let mut lines: Vec<(i32, i32)> = vec![];
+    for line in req.lines.iter() {
+        let quantity = line.quantity.unwrap_or(1);
+        if quantity < 1 {
+            return Err(ClientError::ParameterInvalid(
+                "Quantity of a cart line must be positive.".to_string(),
+            )
+            .into());
+        }
+        match lines.iter_mut().find(|(id, _)| *id == line.article_id) {
+            Some((_, q)) => {
+                *q = q.checked_add(quantity).ok_or_else(|| {
+                    ClientError::ParameterInvalid("Quantity is out of range.".to_string())
+                })?
+            }
+            None => lines.push((line.article_id, quantity)),
+        }
+    }
+    if lines.is_empty() {
+        return Err(ClientError::ParameterMissing("lines".to_string()).into());
+    }
+
+    credential_api::check_pin(&db, user_id, pin.as_deref()).await?;
+    let mut tx = db.begin().await.map_err(|e| -> DbError { e.into() })?;
+
+    let window = to_duration(&idempotency.window).as_secs();
+    if let Some(key) = &idempotency_key {
+        if let Some(reply) = retried_cart(&mut tx, &settings, &user_id, key, window).await? {
+            tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+            return Ok(reply);
+        }
+    }
+
+    let mut user = match user_db::get_user_tx(&mut tx, &*settings, &user_id).await {
+        Ok(Some(u)) => u,
+        Ok(None) => {
+            return Err(DbError::EntityNotFound("Sender does not exist.".to_string()).into())
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut articles = vec![];
+    for (article_id, quantity) in lines.into_iter() {
+        let article = article_db::get_article_or_error_tx(&mut tx, article_id).await?;
+        articles.push((article, quantity));
+    }
+    let mut amounts = vec![];
+    let mut deposits = vec![];
+    for (article, quantity) in articles.iter() {
+        amounts.push(debit(article.entity.amount, *quantity)?);
+        deposits.push(debit(article.entity.deposit, *quantity)?);
+    }
+    let amount = sum_amounts(&amounts)?;
+    let deposit = sum_amounts(&deposits)?;
+    let total = sum_amounts(&[amount, deposit])?;
+    if hold_for_approval(&*settings, &*approval, &user.balance, &total)? {
+        let mut pending = vec![];
+        for ((article, quantity), line_amount) in articles.iter().zip(amounts.iter()) {
+            let line = pending_db::add_pending_transaction_tx(
+                &mut tx,
+                &user_id,
+                Some(article.entity.id),
+                Some(*quantity),
+                line_amount,
+                req.comment.as_deref(),
+            )
+            .await?;
+            pending.push(line);
+        }
+        let ids: Vec<i32> = pending.iter().map(|p| p.id).collect();
+        pending_db::link_cart_tx(&mut tx, &ids).await?;
+        for line in pending.iter_mut() {
+            line.cart_pending_id = Some(ids[0]);
+        }
+        if let Some(key) = &idempotency_key {
+            match pending_db::set_idempotency_key_tx(&mut tx, &user_id, &ids[0], key, window).await
+            {
+                Ok(()) => (),
+                Err(DbError::DuplicateKey(_)) => {
+                    drop(tx);
+                    return replay_cart(&db, &settings, &user_id, key, window).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+        return Ok(held_cart(pending));
+    }
+
+    let mut transactions = vec![];
+    for ((article, quantity), line_amount) in articles.into_iter().zip(amounts.into_iter()) {
+        let transaction = transaction_db::add_transaction_with_article_tx(
+            &mut tx,
+            user,
+            &quantity,
+            &line_amount,
+            article,
+            req.comment.as_deref(),
+        )
+        .await?;
+        user = transaction.user.clone();
+        transactions.push(transaction);
+    }
+
+    let ids: Vec<i32> = transactions.iter().map(|t| t.entity.id).collect();
+    transaction_db::link_cart_tx(&mut tx, &ids).await?;
+    if let Some(key) = &idempotency_key {
+        match transaction_db::set_idempotency_key_tx(&mut tx, &user_id, &ids[0], key, window).await
+        {
+            Ok(()) => (),
+            // a concurrent retry was faster, this cart is rolled back in favour of it
+            Err(DbError::DuplicateKey(_)) => {
+                drop(tx);
+                return replay_cart(&db, &settings, &user_id, key, window).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let booked: Vec<Event> = transactions
+        .iter()
+        .map(|t| Event::TransactionCreated(t.clone()))
+        .collect();
+    for event in booked.iter() {
+        events.queue_tx(&mut tx, event).await?;
+    }
+    tx.commit().await.map_err(|e| -> DbError { e.into() })?;
+
+    for event in booked.into_iter() {
+        events.publish(event);
+    }
+
+    Ok(Box::new(json_reply(CartResp {
+        receipt: CartReceipt {
+            user,
+            transactions,
+            amount,
+            deposit,
+            total,
+        },
+    })))
+}
+
+/// `price * quantity` as the amount booked for them, rejected if it is out of the range of amounts
+fn debit(price: i32, quantity: i32) -> Result<i32, ClientError> {
+    price
+        .checked_mul(quantity)
+        .and_then(i32::checked_neg)
+        .ok_or_else(out_of_range)
+}
+
+fn sum_amounts(amounts: &[i32]) -> Result<i32, ClientError> {
+    amounts
+        .iter()
+        .try_fold(0i32, |sum, v| sum.checked_add(*v))
+        .ok_or_else(out_of_range)
+}
+
+fn new_balance(balance: &i32, amount: &i32) -> Result<i32, ClientError> {
+    balance.checked_add(*amount).ok_or_else(out_of_range)
+}
+
+fn out_of_range() -> ClientError {
+    ClientError::ParameterInvalid("Requested amount is out of range.".to_string())
+}
+
+pub(crate) fn check_limit(
     settings: &StrichlisteSetting,
     new_balance: &i32,
     amount: &i32,
+) -> Result<(), ClientError> {
+    check_balance_limit(settings, new_balance)?;
+    check_payment_limit(settings, amount)
+}
+
+fn check_balance_limit(
+    settings: &StrichlisteSetting,
+    new_balance: &i32,
 ) -> Result<(), ClientError> {
     if &settings.account.boundary.lower > new_balance
         || new_balance > &settings.account.boundary.upper
@@ -140,8 +624,13 @@ fn check_limit(
         return Err(ClientError::ParameterInvalid(
             "Requested balance is out of the allowed boundary.".to_string(),
         ));
-    } else if &settings.payment.boundary.lower > amount || amount > &settings.payment.boundary.upper
-    {
+    } else {
+        return Ok(());
+    }
+}
+
+fn check_payment_limit(settings: &StrichlisteSetting, amount: &i32) -> Result<(), ClientError> {
+    if &settings.payment.boundary.lower > amount || amount > &settings.payment.boundary.upper {
         return Err(ClientError::ParameterInvalid(
             "Requested amount is out of the allowed boundary.".to_string(),
         ));
@@ -149,3 +638,63 @@ fn check_limit(
         return Ok(());
     }
 }
+
+/// The reply to a retry of the cart with `key`, None if there was no cart with it within the window.
+/// A held cart is returned in its current state, or as its receipt once approved.
+async fn retried_cart(
+    tx: &mut Transaction<'static, Sqlite>,
+    settings: &StrichlisteSetting,
+    user_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> Result<Option<Box<dyn Reply>>, DbError> {
+    let first = match transaction_db::get_idempotent_transaction_tx(tx, user_id, key, window_secs)
+        .await?
+    {
+        Some(v) => v,
+        None => {
+            match pending_db::get_idempotent_pending_tx(tx, user_id, key, window_secs).await? {
+                None => return Ok(None),
+                Some(p) => match (p.cart_pending_id, p.transaction_id) {
+                    (Some(_), Some(v)) => v,
+                    (Some(first), None) => {
+                        return Ok(Some(held_cart(pending_db::get_cart_tx(tx, first).await?)))
+                    }
+                    // the key belongs to a single booking
+                    (None, _) => return Err(DbError::DuplicateKey("Idempotency key".to_string())),
+                },
+            }
+        }
+    };
+    let mut transactions = vec![];
+    for id in transaction_db::get_cart_transaction_ids_tx(tx, &first)
+        .await?
+        .iter()
+    {
+        let transaction = transaction_db::get_transaction_tx(tx, settings, id)
+            .await?
+            .ok_or_else(|| DbError::EntityNotFound("Transaction".to_string()))?;
+        transactions.push(transaction);
+    }
+    let user = match transactions.last() {
+        Some(t) => t.user.clone(),
+        // the key belongs to a single booking
+        None => return Err(DbError::DuplicateKey("Idempotency key".to_string())),
+    };
+    let amount: i32 = transactions.iter().map(|t| t.entity.amount).sum();
+    let deposit: i32 = transactions
+        .iter()
+        .filter_map(|t| t.deposit_transaction.as_ref())
+        .map(|d| d.amount)
+        .sum();
+
+    return Ok(Some(Box::new(json_reply(CartResp {
+        receipt: CartReceipt {
+            user,
+            transactions,
+            amount,
+            deposit,
+            total: amount + deposit,
+        },
+    }))));
+}
@@ -11,7 +11,7 @@ pub async fn get_transactions(
 ) -> std::result::Result<Vec<model::TransactionObject>, DbError> {
     let mut tx = db.begin().await?;
     let transaction_entities_result = sqlx::query_as::<_, model::TransactionEntity>(
-		"SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, quantity, comment, amount, deleted, created
+		"SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
 		FROM transactions
 		WHERE user_id = ?
 		ORDER BY created DESC LIMIT ? OFFSET ?"
@@ -23,24 +23,7 @@ pub async fn get_transactions(
 
     let mut result = Vec::new();
     for parent in transaction_entities_result {
-        let user = user_db::get_user_tx(&mut tx, settings, &parent.user_id)
-            .await?
-            .ok_or(sqlx::Error::RowNotFound)?;
-        let article = article_db::get_article_tx(&mut tx, parent.article_id).await?;
-        let recipient_tx =
-            get_child_transaction_tx(&mut tx, settings, parent.recipient_transaction_id.as_ref())
-                .await?;
-        let sender_tx =
-            get_child_transaction_tx(&mut tx, settings, parent.sender_transaction_id.as_ref())
-                .await?;
-        let o = model::TransactionObject {
-            entity: parent,
-            user,
-            article: article.map(|a| *a),
-            recipient: recipient_tx.map(|v| v.user),
-            sender: sender_tx.map(|v| v.user),
-        };
-        result.push(o);
+        result.push(build_transaction_object_tx(&mut tx, settings, parent).await?);
     }
 
     tx.commit().await?;
@@ -48,6 +31,100 @@ pub async fn get_transactions(
     return Ok(result);
 }
 
+/// Returns the active transactions of the user created in `[from, until)`, oldest first.
+pub async fn get_transactions_between_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    settings: &StrichlisteSetting,
+    user_id: &i32,
+    from: &str,
+    until: &str,
+) -> std::result::Result<Vec<model::TransactionObject>, DbError> {
+    let transaction_entities = sqlx::query_as::<_, model::TransactionEntity>(
+		"SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+		FROM transactions
+		WHERE user_id = ? AND deleted IS FALSE AND created >= ? AND created < ?
+		ORDER BY created, id"
+	)
+	.bind(user_id)
+	.bind(from)
+	.bind(until)
+	.fetch_all(&mut *tx).await?;
+
+    let mut result = Vec::new();
+    for entity in transaction_entities {
+        result.push(build_transaction_object_tx(tx, settings, entity).await?);
+    }
+
+    return Ok(result);
+}
+
+/// Balance of the user right before `until`
+pub async fn balance_before_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    until: &str,
+) -> std::result::Result<i32, DbError> {
+    let balance = sqlx::query_scalar::<_, i32>(
+        "SELECT COALESCE(SUM(amount), 0)
+		FROM transactions
+		WHERE user_id = ? AND deleted IS FALSE AND created < ?",
+    )
+    .bind(user_id)
+    .bind(until)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(balance);
+}
+
+pub async fn get_transaction_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    settings: &StrichlisteSetting,
+    transaction_id: &i32,
+) -> std::result::Result<Option<model::TransactionObject>, DbError> {
+    let transaction_entity = sqlx::query_as::<_, model::TransactionEntity>(
+		"SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+		FROM transactions
+		WHERE id = ?"
+	)
+	.bind(transaction_id)
+	.fetch_optional(&mut *tx).await?;
+
+    return match transaction_entity {
+        Some(entity) => Ok(Some(build_transaction_object_tx(tx, settings, entity).await?)),
+        None => Ok(None),
+    };
+}
+
+async fn build_transaction_object_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    settings: &StrichlisteSetting,
+    entity: model::TransactionEntity,
+) -> std::result::Result<model::TransactionObject, DbError> {
+    let user = user_db::get_user_tx(&mut *tx, settings, &entity.user_id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    let article = article_db::get_article_tx(&mut *tx, entity.article_id).await?;
+    let recipient_tx =
+        get_child_transaction_tx(&mut *tx, settings, entity.recipient_transaction_id.as_ref())
+            .await?;
+    let sender_tx =
+        get_child_transaction_tx(&mut *tx, settings, entity.sender_transaction_id.as_ref())
+            .await?;
+    let deposit_tx =
+        get_child_transaction_tx(&mut *tx, settings, entity.deposit_transaction_id.as_ref())
+            .await?;
+
+    return Ok(model::TransactionObject {
+        entity,
+        user,
+        article: article.map(|a| *a),
+        recipient: recipient_tx.map(|v| v.user),
+        sender: sender_tx.map(|v| v.user),
+        deposit_transaction: deposit_tx.map(|v| v.entity),
+    });
+}
+
 async fn get_child_transaction_tx(
     tx: &mut Transaction<'static, Sqlite>,
     settings: &StrichlisteSetting,
@@ -56,7 +133,7 @@ async fn get_child_transaction_tx(
     match transact_id {
         Some(tx_id) => {
             let transaction_entity = sqlx::query_as::<_, model::TransactionEntity>(
-		"SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, quantity, comment, amount, deleted, created
+		"SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
             FROM transactions
             WHERE id = ?"
 	        )
@@ -71,6 +148,7 @@ async fn get_child_transaction_tx(
                     article: None,
                     recipient: None,
                     sender: None,
+                    deposit_transaction: None,
                 }))),
                 None => Ok(None),
             }
@@ -111,7 +189,7 @@ pub async fn add_transaction_with_value_tx(
 		INSERT INTO transactions (user_id, comment, amount, deleted, created)
 		VALUES (?, ?, ?, FALSE, datetime('now', 'localtime'));
 		
-		SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, quantity, comment, amount, deleted, created
+		SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
         FROM transactions WHERE id = last_insert_rowid();"
 	)
 	.bind(amount)
@@ -131,9 +209,11 @@ pub async fn add_transaction_with_value_tx(
         article: None,
         sender: None,
         recipient: None,
+        deposit_transaction: None,
     });
 }
 
+/// Books the article, its deposit is booked as a linked transaction of its own.
 pub async fn add_transaction_with_article_tx(
     tx: &mut Transaction<'static, Sqlite>,
     mut user: model::UserEntity,
@@ -142,7 +222,7 @@ pub async fn add_transaction_with_article_tx(
     mut article: model::ArticleObject,
     comment: Option<&str>,
 ) -> std::result::Result<model::TransactionObject, DbError> {
-    let result = sqlx::query_as::<_, model::TransactionEntity>(
+    let mut result = sqlx::query_as::<_, model::TransactionEntity>(
         "UPDATE user SET balance = balance + ?, updated = datetime('now', 'localtime')
         WHERE id = ?;
         
@@ -152,7 +232,7 @@ pub async fn add_transaction_with_article_tx(
 		INSERT INTO transactions (user_id, article_id, quantity, comment, amount, deleted, created)
 		VALUES (?, ?, ?, ?, ?, FALSE, datetime('now', 'localtime'));
 		
-		SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, quantity, comment, amount, deleted, created
+		SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
         FROM transactions WHERE id = last_insert_rowid();"
 	)
 	.bind(amount)
@@ -163,10 +243,41 @@ pub async fn add_transaction_with_article_tx(
     .bind(quantity)
 	.bind(comment)
 	.bind(amount)
-    .fetch_one(tx).await?;
+    .fetch_one(&mut *tx).await?;
+
+    let deposit_amount = article.entity.deposit * quantity * -1;
+    let deposit_transaction = match deposit_amount {
+        0 => None,
+        _ => {
+            let deposit = sqlx::query_as::<_, model::TransactionEntity>(
+                "UPDATE user SET balance = balance + ?
+                WHERE id = ?;
+
+                INSERT INTO transactions (user_id, article_id, quantity, comment, amount, deposit, deleted, created)
+                VALUES (?, ?, ?, ?, ?, TRUE, FALSE, datetime('now', 'localtime'));
+
+                UPDATE transactions SET deposit_transaction_id = last_insert_rowid()
+                WHERE id = ?;
+
+                SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+                FROM transactions WHERE id = last_insert_rowid();"
+            )
+            .bind(deposit_amount)
+            .bind(user.id)
+            .bind(user.id)
+            .bind(article.entity.id)
+            .bind(quantity)
+            .bind(comment)
+            .bind(deposit_amount)
+            .bind(result.id)
+            .fetch_one(&mut *tx).await?;
+            result.deposit_transaction_id = Some(deposit.id);
+            Some(deposit)
+        }
+    };
 
     // correct entity objects
-    user.balance += amount;
+    user.balance += amount + deposit_amount;
     user.updated = Some(common::cur_datetime_str());
     article.entity.usage_count += 1;
 
@@ -176,6 +287,49 @@ pub async fn add_transaction_with_article_tx(
         article: Some(article),
         sender: None,
         recipient: None,
+        deposit_transaction,
+    });
+}
+
+/// Credits the deposit of `quantity` returned bottles of the article.
+pub async fn add_deposit_return_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    mut user: model::UserEntity,
+    quantity: &i32,
+    amount: &i32,
+    article: model::ArticleObject,
+    comment: Option<&str>,
+) -> std::result::Result<model::TransactionObject, DbError> {
+    let result = sqlx::query_as::<_, model::TransactionEntity>(
+        "UPDATE user SET balance = balance + ?, updated = datetime('now', 'localtime')
+        WHERE id = ?;
+
+		INSERT INTO transactions (user_id, article_id, quantity, comment, amount, deposit, deleted, created)
+		VALUES (?, ?, ?, ?, ?, TRUE, FALSE, datetime('now', 'localtime'));
+
+		SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+        FROM transactions WHERE id = last_insert_rowid();"
+	)
+	.bind(amount)
+    .bind(user.id)
+    .bind(user.id)
+    .bind(article.entity.id)
+    .bind(quantity)
+	.bind(comment)
+	.bind(amount)
+    .fetch_one(tx).await?;
+
+    // correct entity object
+    user.balance += amount;
+    user.updated = Some(common::cur_datetime_str());
+
+    return Ok(model::TransactionObject {
+        entity: result,
+        user: user,
+        article: Some(article),
+        sender: None,
+        recipient: None,
+        deposit_transaction: None,
     });
 }
 
@@ -202,7 +356,7 @@ pub async fn add_transaction_with_recipient_tx(
         UPDATE transactions SET recipient_transaction_id = last_insert_rowid()
         WHERE id IN (SELECT sender_transaction_id FROM transactions WHERE id = last_insert_rowid());
 		
-		SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, quantity, comment, amount, deleted, created
+		SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
         FROM transactions WHERE recipient_transaction_id = last_insert_rowid();"
     )
     // update user
@@ -231,5 +385,162 @@ pub async fn add_transaction_with_recipient_tx(
         article: None,
         sender: None,
         recipient: Some(recipient),
+        deposit_transaction: None,
     })
 }
+
+pub async fn revert_transaction(
+    db: &SqlitePool,
+    settings: &StrichlisteSetting,
+    transaction_id: &i32,
+) -> std::result::Result<model::TransactionObject, DbError> {
+    let mut tx = db.begin().await?;
+    let entity = sqlx::query_as::<_, model::TransactionEntity>(
+		"SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+		FROM transactions
+		WHERE id = ? AND deleted IS FALSE"
+	)
+	.bind(transaction_id)
+	.fetch_optional(&mut tx).await?
+    .ok_or(DbError::EntityNotFound("Active transaction".to_string()))?;
+
+    // the deposit charged with a purchase has no link back, so go through the purchase
+    let entity = match sqlx::query_as::<_, model::TransactionEntity>(
+        "SELECT id, user_id, article_id, recipient_transaction_id, sender_transaction_id, deposit_transaction_id, quantity, comment, amount, deposit, deleted, created
+        FROM transactions
+        WHERE deposit_transaction_id = ? AND deleted IS FALSE",
+    )
+    .bind(entity.id)
+    .fetch_optional(&mut tx)
+    .await?
+    {
+        Some(purchase) => purchase,
+        None => entity,
+    };
+
+    // a transfer consists of two transactions, both sides are reverted together,
+    // so is a purchase with its deposit
+    sqlx::query(
+        "UPDATE user SET balance = balance - (
+            SELECT SUM(t.amount) FROM transactions AS t
+            WHERE t.user_id = user.id AND t.id IN (?, ?, ?, ?) AND t.deleted IS FALSE)
+        WHERE id IN (SELECT user_id FROM transactions WHERE id IN (?, ?, ?, ?) AND deleted IS FALSE);
+
+        UPDATE article SET usage_count = usage_count - 1
+        WHERE id = ? AND ? IS FALSE;
+
+        UPDATE transactions SET deleted = TRUE
+        WHERE id IN (?, ?, ?, ?) AND deleted IS FALSE;",
+    )
+    .bind(entity.id)
+    .bind(entity.recipient_transaction_id)
+    .bind(entity.sender_transaction_id)
+    .bind(entity.deposit_transaction_id)
+    .bind(entity.id)
+    .bind(entity.recipient_transaction_id)
+    .bind(entity.sender_transaction_id)
+    .bind(entity.deposit_transaction_id)
+    .bind(entity.article_id)
+    .bind(entity.deposit)
+    .bind(entity.id)
+    .bind(entity.recipient_transaction_id)
+    .bind(entity.sender_transaction_id)
+    .bind(entity.deposit_transaction_id)
+    .execute(&mut tx)
+    .await?;
+
+    let result = get_transaction_tx(&mut tx, settings, &entity.id)
+        .await?
+        .ok_or(DbError::EntityNotFound("Transaction".to_string()))?;
+    tx.commit().await?;
+
+    return Ok(result);
+}
+
+/// Id of the transaction of the user booked with `key` within the last `window_secs` seconds
+pub async fn get_idempotent_transaction_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> std::result::Result<Option<i32>, DbError> {
+    let transaction_id = sqlx::query_scalar::<_, i32>(
+        "SELECT id FROM transactions
+        WHERE user_id = ? AND idempotency_key = ? AND created >= datetime('now', 'localtime', ?)
+        ORDER BY created DESC, id DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(format!("-{} seconds", window_secs))
+    .fetch_optional(tx)
+    .await?;
+
+    return Ok(transaction_id);
+}
+
+/// Marks the lines of a cart as booked together, by the id of the first one.
+pub async fn link_cart_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    transaction_ids: &[i32],
+) -> std::result::Result<(), DbError> {
+    let first = match transaction_ids.first() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    for id in transaction_ids.iter() {
+        sqlx::query("UPDATE transactions SET cart_transaction_id = ? WHERE id = ?")
+            .bind(first)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    return Ok(());
+}
+
+/// Ids of the lines of the cart starting with `first_id`, in booking order
+pub async fn get_cart_transaction_ids_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    first_id: &i32,
+) -> std::result::Result<Vec<i32>, DbError> {
+    let ids = sqlx::query_scalar::<_, i32>(
+        "SELECT id FROM transactions WHERE cart_transaction_id = ? ORDER BY id",
+    )
+    .bind(first_id)
+    .fetch_all(tx)
+    .await?;
+
+    return Ok(ids);
+}
+
+/// Stores the key of the booking, the key of an older booking outside of the window is released.
+/// Fails with `DuplicateKey` if a concurrent request stored the key first.
+pub async fn set_idempotency_key_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    user_id: &i32,
+    transaction_id: &i32,
+    key: &str,
+    window_secs: u64,
+) -> std::result::Result<(), DbError> {
+    let result = sqlx::query(
+        "UPDATE transactions SET idempotency_key = NULL
+        WHERE user_id = ? AND idempotency_key = ? AND created < datetime('now', 'localtime', ?);
+
+        UPDATE transactions SET idempotency_key = ? WHERE id = ?",
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(format!("-{} seconds", window_secs))
+    .bind(key)
+    .bind(transaction_id)
+    .execute(tx)
+    .await;
+
+    return match result {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(e)) if e.message().contains("UNIQUE constraint failed") => {
+            Err(DbError::DuplicateKey("Idempotency key".to_string()))
+        }
+        Err(e) => Err(e.into()),
+    };
+}
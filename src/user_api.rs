@@ -5,11 +5,14 @@ use sqlx::SqlitePool;
 
 use crate::{
     common,
-    error::DbError,
+    error::{ClientError, DbError},
+    events::{Event, EventBus},
     model::{self, json_reply, JsonReply, UserResp},
     settings, user_db,
 };
 
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+
 pub async fn get_users(
     db: SqlitePool,
     settings: Arc<settings::StrichlisteSetting>,
@@ -70,6 +73,7 @@ pub async fn find_user(
 
 pub async fn add_user(
     db: SqlitePool,
+    events: EventBus,
     user_req: model::UserAddReq,
 ) -> Result<JsonReply<UserResp>, warp::Rejection> {
     let name = user_req.name.trim();
@@ -84,6 +88,9 @@ pub async fn add_user(
         .transpose()?;
 
     let user_entity = user_db::create_user(&db, name_san.as_ref(), email.as_deref()).await?;
+    events
+        .publish_committed(&db, Event::UserCreated(user_entity.clone()))
+        .await;
 
     let result = model::UserResp { user: user_entity };
 
@@ -93,6 +100,7 @@ pub async fn add_user(
 pub async fn update_user(
     db: SqlitePool,
     settings: Arc<settings::StrichlisteSetting>,
+    events: EventBus,
     user_id: i32,
     user_req: model::UserUpdateReq,
 ) -> Result<JsonReply<UserResp>, warp::Rejection> {
@@ -107,6 +115,20 @@ pub async fn update_user(
         .map(|v| common::assert_email(v))
         .transpose()?;
 
+    let display_name = user_req
+        .display_name
+        .as_deref()
+        .map(|v| common::sanitize_control_chars(v.trim()));
+    if display_name
+        .as_ref()
+        .map_or(false, |v| v.chars().count() > MAX_DISPLAY_NAME_LEN)
+    {
+        return Err(ClientError::ParameterInvalid(format!(
+            "Display name must not have more than {} characters.",
+            MAX_DISPLAY_NAME_LEN
+        ))
+        .into());
+    }
     let disabled = user_req.is_disabled;
 
     let user_entity = user_db::update_user(
@@ -115,11 +137,36 @@ pub async fn update_user(
         user_id,
         name_san.as_ref(),
         email.as_deref(),
+        display_name.as_deref(),
         disabled,
     )
     .await?;
+    events
+        .publish_committed(&db, Event::UserUpdated(user_entity.clone()))
+        .await;
 
     let result = model::UserResp { user: user_entity };
 
     Ok(json_reply(result))
 }
+
+/// Notes of the admins on the user, part of the admin API only
+pub async fn get_notes(
+    db: SqlitePool,
+    user_id: i32,
+) -> Result<JsonReply<model::UserNotesResp>, warp::Rejection> {
+    let user_notes = user_db::get_notes(&db, user_id).await?;
+
+    Ok(json_reply(model::UserNotesResp { user_notes }))
+}
+
+pub async fn set_notes(
+    db: SqlitePool,
+    user_id: i32,
+    req: model::UserNotesReq,
+) -> Result<JsonReply<model::UserNotesResp>, warp::Rejection> {
+    let notes = req.notes.as_deref().map(|v| v.trim());
+    let user_notes = user_db::set_notes(&db, user_id, notes).await?;
+
+    Ok(json_reply(model::UserNotesResp { user_notes }))
+}
@@ -23,8 +23,10 @@ pub async fn get_users(
 
     let mut tx = db.begin().await?;
     let user_entities_result = sqlx::query_as::<_, model::UserEntity>(
-        "SELECT id, name, email, balance, disabled, 
+        "SELECT id, name, display_name, email, balance, disabled, 
          CASE WHEN updated NOTNULL THEN (strftime('%s','now', 'localtime') - strftime('%s',updated)) < ? ELSE FALSE END as active,
+         EXISTS (SELECT 1 FROM user_pin WHERE user_id = user.id) AS has_pin,
+         EXISTS (SELECT 1 FROM user_avatar WHERE user_id = user.id) AS has_avatar,
          created, updated 
          FROM user WHERE disabled IS ? AND (active IS ? OR active IS NOT ?)
          ORDER BY name",
@@ -57,8 +59,10 @@ pub async fn get_user_tx(
     let stale_period = settings::get_stale_period(settings);
 
     let user_entity_result = sqlx::query_as::<_, model::UserEntity>(
-        "SELECT id, name, email, balance, disabled, 
+        "SELECT id, name, display_name, email, balance, disabled, 
          CASE WHEN updated NOTNULL THEN (strftime('%s','now', 'localtime') - strftime('%s',updated)) < ? ELSE FALSE END as active,
+         EXISTS (SELECT 1 FROM user_pin WHERE user_id = user.id) AS has_pin,
+         EXISTS (SELECT 1 FROM user_avatar WHERE user_id = user.id) AS has_avatar,
          created, updated 
          FROM user WHERE id = ?",
 	)
@@ -81,13 +85,16 @@ pub async fn search_user(
 
     let mut tx = db.begin().await?;
     let user_entity_result = sqlx::query_as::<_, model::UserEntity>(
-        "SELECT id, name, email, balance, disabled, 
+        "SELECT id, name, display_name, email, balance, disabled, 
          CASE WHEN updated NOTNULL THEN (strftime('%s','now', 'localtime') - strftime('%s',updated)) < ? ELSE FALSE END as active,
+         EXISTS (SELECT 1 FROM user_pin WHERE user_id = user.id) AS has_pin,
+         EXISTS (SELECT 1 FROM user_avatar WHERE user_id = user.id) AS has_avatar,
          created, updated 
-		 FROM user WHERE disabled IS FALSE AND name LIKE ?
+		 FROM user WHERE disabled IS FALSE AND (name LIKE ? OR display_name LIKE ?)
 		 ORDER BY name LIMIT ?",
     )
 	.bind(stale_period)
+	.bind(search.clone())
 	.bind(search)
 	.bind(limit)
     .fetch_all(&mut tx).await?;
@@ -106,7 +113,8 @@ pub async fn create_user(
         "INSERT INTO user (name, email, balance, disabled, created)
 		 VALUES(?, ?, 0, FALSE, datetime('now', 'localtime'));
 		 
-		 SELECT id, name, email, balance, disabled, FALSE AS active, created, updated
+		 SELECT id, name, display_name, email, balance, disabled, FALSE AS active,
+         FALSE AS has_pin, FALSE AS has_avatar, created, updated
          FROM user WHERE id = last_insert_rowid();",
     )
     .bind(name)
@@ -124,17 +132,22 @@ pub async fn update_user(
     user_id: i32,
     name: &str,
     email: Option<&str>,
+    display_name: Option<&str>,
     disabled: bool,
 ) -> std::result::Result<model::UserEntity, DbError> {
     let mut tx = db.begin().await?;
+    // the display name is kept if None and removed if empty
     sqlx::query(
         "UPDATE user
-		 SET name = ?, email = ?, disabled = ?
+		 SET name = ?, email = ?, disabled = ?,
+		     display_name = CASE WHEN ? IS NULL THEN display_name ELSE NULLIF(?, '') END
          WHERE id = ?;",
     )
     .bind(name)
     .bind(email)
     .bind(disabled)
+    .bind(display_name)
+    .bind(display_name)
     .bind(user_id)
     .execute(&mut tx)
     .await?;
@@ -144,3 +157,38 @@ pub async fn update_user(
 
     return user.ok_or(DbError::EntityNotFound("User".to_string()));
 }
+
+/// Notes of the admins on the user, kept apart from `UserEntity` so they are never part of the public API
+pub async fn get_notes(
+    db: &SqlitePool,
+    user_id: i32,
+) -> std::result::Result<model::UserNotesEntity, DbError> {
+    let notes = sqlx::query_as::<_, model::UserNotesEntity>(
+        "SELECT id AS user_id, notes FROM user WHERE id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    return notes.ok_or(DbError::EntityNotFound("User".to_string()));
+}
+
+/// Replaces the notes, None or empty ones are removed.
+pub async fn set_notes(
+    db: &SqlitePool,
+    user_id: i32,
+    notes: Option<&str>,
+) -> std::result::Result<model::UserNotesEntity, DbError> {
+    let notes = sqlx::query_as::<_, model::UserNotesEntity>(
+        "UPDATE user SET notes = NULLIF(?, '') WHERE id = ?;
+
+         SELECT id AS user_id, notes FROM user WHERE id = ?;",
+    )
+    .bind(notes)
+    .bind(user_id)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await?;
+
+    return notes.ok_or(DbError::EntityNotFound("User".to_string()));
+}
@@ -0,0 +1,246 @@
+use std::{sync::Arc, time::Duration};
+
+use hmac::{Hmac, Mac, NewMac};
+use hyper::{client::HttpConnector, header, Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use log::{error, info, warn};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use tokio::sync::{
+    broadcast::{self, RecvError},
+    Notify,
+};
+
+use crate::{
+    common,
+    error::DbError,
+    events::Event,
+    model::WebhookOutboxEntity,
+    settings::{self, HookSetting, WebhookSetting},
+    webhook_db,
+};
+
+/// Events a hook can subscribe to
+pub const EVENT_NAMES: &[&str] = &[
+    "transaction.created",
+    "user.created",
+    "user.updated",
+    "article.created",
+    "article.updated",
+    "article.deleted",
+    LOW_BALANCE_EVENT,
+];
+const LOW_BALANCE_EVENT: &str = "user.balance.low";
+
+/// Deliveries handled per database round trip
+const BATCH_SIZE: i32 = 20;
+/// Period to look for due retries
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Upper bound of the exponential backoff
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(24 * 3600);
+
+const EVENT_HEADER: &str = "X-Strichliste-Event";
+const DELIVERY_HEADER: &str = "X-Strichliste-Delivery";
+const SIGNATURE_HEADER: &str = "X-Strichliste-Signature";
+
+type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Delivers the outbox to the configured hooks, right after each published event.
+/// Every hook has its own task, so a slow or unreachable one doesn't hold up the others.
+/// Deliveries left over from a previous run are picked up right away.
+pub async fn run_webhooks(
+    db: SqlitePool,
+    mut receiver: broadcast::Receiver<Event>,
+    settings: WebhookSetting,
+) {
+    if let Err(e) = give_up_removed_hooks(&db, &settings).await {
+        error!("Giving up deliveries to removed webhooks failed: {}", e);
+    }
+
+    let settings = Arc::new(settings);
+    let client: HttpClient = Client::builder().build(HttpsConnector::new());
+    let mut notifies = Vec::new();
+    for hook in settings.hooks.iter() {
+        let notify = Arc::new(Notify::new());
+        tokio::spawn(run_delivery(
+            db.clone(),
+            settings.clone(),
+            hook.clone(),
+            client.clone(),
+            notify.clone(),
+        ));
+        notifies.push(notify);
+    }
+
+    loop {
+        // the deliveries are in the outbox already, missed events only delay them until the next poll
+        match receiver.recv().await {
+            Ok(_) | Err(RecvError::Lagged(_)) => notifies.iter().for_each(|n| n.notify()),
+            Err(RecvError::Closed) => return,
+        };
+    }
+}
+
+/// Writes the deliveries of `event` to the outbox, within the transaction making the change.
+pub async fn queue_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    settings: &WebhookSetting,
+    event: &Event,
+) -> Result<(), DbError> {
+    if settings.hooks.is_empty() {
+        return Ok(());
+    }
+    for (name, data) in webhook_events(event, settings.low_balance) {
+        let payload = json!({
+            "event": name,
+            "created": common::cur_datetime_str(),
+            "data": data,
+        })
+        .to_string();
+        for hook in settings.hooks.iter().filter(|h| subscribed(h, name)) {
+            webhook_db::add_delivery_tx(&mut *tx, hook.id(), &hook.url, name, &payload).await?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// Maps an event to the webhook events, adding `user.balance.low` if a booking crossed the threshold.
+fn webhook_events(event: &Event, low_balance: i32) -> Vec<(&'static str, serde_json::Value)> {
+    let mut result = vec![(event.name(), event.payload())];
+
+    if let Some(t) = event.balance_dropped_below(low_balance) {
+        result.push((
+            LOW_BALANCE_EVENT,
+            json!({ "user": t.user, "transaction": t }),
+        ));
+    }
+
+    return result;
+}
+
+fn subscribed(hook: &HookSetting, event: &str) -> bool {
+    hook.events.is_empty() || hook.events.iter().any(|e| e == event)
+}
+
+/// Deliveries to hooks which are not configured anymore would never be attempted again.
+async fn give_up_removed_hooks(db: &SqlitePool, settings: &WebhookSetting) -> Result<(), DbError> {
+    for hook in webhook_db::get_queued_hooks(db).await? {
+        if settings.hooks.iter().all(|h| h.id() != hook) {
+            warn!("Giving up the deliveries to the removed webhook {}", hook);
+            webhook_db::give_up_deliveries(db, &hook, "webhook is not configured anymore").await?;
+        }
+    }
+
+    return Ok(());
+}
+
+async fn run_delivery(
+    db: SqlitePool,
+    settings: Arc<WebhookSetting>,
+    hook: HookSetting,
+    client: HttpClient,
+    notify: Arc<Notify>,
+) {
+    loop {
+        if let Err(e) = deliver_due(&db, &settings, &hook, &client).await {
+            error!("Delivering webhooks to {} failed: {}", hook.url, e);
+        }
+        tokio::select! {
+            _ = notify.notified() => (),
+            _ = tokio::time::delay_for(POLL_INTERVAL) => (),
+        }
+    }
+}
+
+/// Delivers the due deliveries of `hook` in the order they were queued.
+async fn deliver_due(
+    db: &SqlitePool,
+    settings: &WebhookSetting,
+    hook: &HookSetting,
+    client: &HttpClient,
+) -> Result<(), DbError> {
+    loop {
+        let deliveries = webhook_db::get_due_deliveries(db, hook.id(), BATCH_SIZE).await?;
+        for delivery in deliveries.iter() {
+            match deliver(client, hook, delivery, settings).await {
+                Ok(_) => webhook_db::remove_delivery(db, delivery.id).await?,
+                Err(e) => {
+                    let next_attempt = next_attempt(settings, delivery);
+                    match next_attempt {
+                        Some(_) => warn!(
+                            "Delivering {} to {} failed: {}",
+                            delivery.event, delivery.url, e
+                        ),
+                        None => error!(
+                            "Giving up delivering {} to {}: {}",
+                            delivery.event, delivery.url, e
+                        ),
+                    };
+                    webhook_db::reschedule_delivery(db, delivery.id, &e, next_attempt.as_deref())
+                        .await?;
+                }
+            };
+        }
+        // failed ones are scheduled for later, so a full batch means there may be more due
+        if deliveries.len() < BATCH_SIZE as usize {
+            return Ok(());
+        }
+    }
+}
+
+/// Time of the next attempt with exponential backoff, None if the attempts are exhausted.
+fn next_attempt(settings: &WebhookSetting, delivery: &WebhookOutboxEntity) -> Option<String> {
+    let attempts = delivery.attempts + 1;
+    if attempts >= settings.max_attempts {
+        return None;
+    }
+    let delay = settings::to_duration(&settings.retry_delay)
+        .checked_mul(2u32.saturating_pow(delivery.attempts as u32))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY);
+    let next = chrono::Local::now() + chrono::Duration::from_std(delay).ok()?;
+    return Some(next.naive_local().format("%Y-%m-%d %H:%M:%S").to_string());
+}
+
+async fn deliver(
+    client: &HttpClient,
+    hook: &HookSetting,
+    delivery: &WebhookOutboxEntity,
+    settings: &WebhookSetting,
+) -> Result<(), String> {
+    let mut request = Request::post(&delivery.url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id);
+    if !hook.secret.is_empty() {
+        request = request.header(SIGNATURE_HEADER, sign(&hook.secret, &delivery.payload));
+    }
+    let request = request
+        .body(Body::from(delivery.payload.clone()))
+        .map_err(|e| e.to_string())?;
+
+    let timeout = settings::to_duration(&settings.timeout);
+    let response = match tokio::time::timeout(timeout, client.request(request)).await {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err(format!("no response within {}", settings.timeout)),
+    };
+
+    match response.status().is_success() {
+        true => {
+            info!("Delivered {} to {}", delivery.event, delivery.url);
+            Ok(())
+        }
+        false => Err(format!("HTTP status {}", response.status())),
+    }
+}
+
+/// HMAC-SHA256 of the body, in the form `sha256=<hex>`
+fn sign(secret: &str, payload: &str) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC key rejected");
+    mac.update(payload.as_bytes());
+    return format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+}
@@ -0,0 +1,113 @@
+use sqlx::{sqlite::SqlitePool, Sqlite, Transaction};
+
+use crate::{error::DbError, model};
+
+/// Queues the delivery of `payload` to the hook `hook` at `url`, returns the id of the delivery.
+pub async fn add_delivery_tx(
+    tx: &mut Transaction<'static, Sqlite>,
+    hook: &str,
+    url: &str,
+    event: &str,
+    payload: &str,
+) -> std::result::Result<i32, DbError> {
+    let id: (i32,) = sqlx::query_as(
+        "INSERT INTO webhook_outbox (hook, url, event, payload, attempts, next_attempt, failed, created)
+        VALUES (?, ?, ?, ?, 0, datetime('now', 'localtime'), FALSE, datetime('now', 'localtime'));
+
+        SELECT last_insert_rowid();",
+    )
+    .bind(hook)
+    .bind(url)
+    .bind(event)
+    .bind(payload)
+    .fetch_one(tx)
+    .await?;
+
+    return Ok(id.0);
+}
+
+/// Returns the deliveries to `hook` which are due, oldest first.
+pub async fn get_due_deliveries(
+    db: &SqlitePool,
+    hook: &str,
+    limit: i32,
+) -> std::result::Result<Vec<model::WebhookOutboxEntity>, DbError> {
+    let mut tx = db.begin().await?;
+    let deliveries = sqlx::query_as::<_, model::WebhookOutboxEntity>(
+        "SELECT id, hook, url, event, payload, attempts, next_attempt, last_error, failed, created
+        FROM webhook_outbox
+        WHERE hook = ? AND failed IS FALSE AND next_attempt <= datetime('now', 'localtime')
+        ORDER BY id LIMIT ?",
+    )
+    .bind(hook)
+    .bind(limit)
+    .fetch_all(&mut tx)
+    .await?;
+
+    return Ok(deliveries);
+}
+
+/// Returns the hooks with deliveries which are not given up yet.
+pub async fn get_queued_hooks(db: &SqlitePool) -> std::result::Result<Vec<String>, DbError> {
+    let mut tx = db.begin().await?;
+    let hooks: Vec<(String,)> =
+        sqlx::query_as("SELECT DISTINCT hook FROM webhook_outbox WHERE failed IS FALSE")
+            .fetch_all(&mut tx)
+            .await?;
+
+    return Ok(hooks.into_iter().map(|v| v.0).collect());
+}
+
+/// Gives up all pending deliveries to `hook`, they stay in the outbox with `error`.
+pub async fn give_up_deliveries(
+    db: &SqlitePool,
+    hook: &str,
+    error: &str,
+) -> std::result::Result<(), DbError> {
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "UPDATE webhook_outbox SET last_error = ?, failed = TRUE WHERE hook = ? AND failed IS FALSE",
+    )
+    .bind(error)
+    .bind(hook)
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(());
+}
+
+pub async fn remove_delivery(db: &SqlitePool, id: i32) -> std::result::Result<(), DbError> {
+    let mut tx = db.begin().await?;
+    sqlx::query("DELETE FROM webhook_outbox WHERE id = ?")
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    return Ok(());
+}
+
+/// Records a failed attempt and schedules the next one, or gives up if `next_attempt` is None.
+pub async fn reschedule_delivery(
+    db: &SqlitePool,
+    id: i32,
+    error: &str,
+    next_attempt: Option<&str>,
+) -> std::result::Result<(), DbError> {
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "UPDATE webhook_outbox
+        SET attempts = attempts + 1, last_error = ?, failed = ?, next_attempt = COALESCE(?, next_attempt)
+        WHERE id = ?",
+    )
+    .bind(error)
+    .bind(next_attempt.is_none())
+    .bind(next_attempt)
+    .bind(id)
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    return Ok(());
+}